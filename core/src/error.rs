@@ -7,7 +7,15 @@ pub enum Error {
     #[fail(display = "communication error: {}", message)]
     CommunicationError {
         message: String,
-    }
+    },
+    /// The headset replied that it could not carry out a command.
+    #[fail(display = "command 0x{:02x} was rejected: {}", command, reason)]
+    CommandRejected {
+        /// The ID of the command.
+        command: u8,
+        /// Why the headset rejected it.
+        reason: String,
+    },
 }
 
 impl Error {
@@ -15,6 +23,11 @@ impl Error {
     pub fn communication_error<M>(message: M) -> Self where M: std::fmt::Display {
         Error::CommunicationError { message: message.to_string() }
     }
+
+    /// Creates a new error for a command the headset rejected.
+    pub fn command_rejected<M>(command: u8, reason: M) -> Self where M: std::fmt::Display {
+        Error::CommandRejected { command, reason: reason.to_string() }
+    }
 }
 
 impl From<std::io::Error> for Error {
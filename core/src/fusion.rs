@@ -0,0 +1,504 @@
+//! Sensor fusion for headsets that only have an inertial measurement unit.
+//!
+//! A backend converts each gyroscope and accelerometer reading into an
+//! `ImuSample` in the canonical frame described in `math`, and feeds it to
+//! a `Fusion`, which tracks the orientation with a pair of Madgwick filters.
+//! While the headset sits still, the gyroscope bias is learnt and removed.
+
+use crate::math::{self, Frame, Orientation, Scalar, Vector3};
+use ahrs::{self, Ahrs};
+use na::{Quaternion, UnitQuaternion};
+
+use std::collections::VecDeque;
+use std::time::{self, Duration};
+
+/// The smallest accelerometer reading that gravity is corrected from, in g.
+const MIN_ACCELEROMETER_NORM: Scalar = 1e-3;
+/// How quickly the gyroscope mean and variance follow new samples, in seconds.
+const STILLNESS_TIME_CONSTANT: f32 = 0.25;
+/// How long the headset must be still before it counts as stationary.
+const MIN_STATIONARY_TIME: Duration = Duration::from_millis(500);
+/// The largest gyroscope variance while still, in (rad/s)².
+const STATIONARY_GYRO_VARIANCE: Scalar = 4e-4;
+/// The fastest turn, once the bias is removed, while still, in rad/s.
+const STATIONARY_GYRO_RATE: Scalar = 0.1;
+/// How far the accelerometer may read from 1g while still.
+const STATIONARY_ACCEL_TOLERANCE: Scalar = 0.05;
+/// How quickly the bias estimate follows the gyroscope while stationary,
+/// in seconds.
+///
+/// This is slow so that the corrected rates, and so the view, change smoothly.
+const BIAS_TIME_CONSTANT: f32 = 2.0;
+
+/// A calibrated reading from an inertial measurement unit.
+///
+/// Both vectors are in the canonical headset frame.
+#[derive(Clone, Debug, PartialEq, PartialOrd)]
+pub struct ImuSample {
+    /// The rate of turn, in radians per second.
+    pub gyroscope: Vector3,
+    /// The acceleration, in g. This reads +1g on Z while the headset sits level.
+    pub accelerometer: Vector3,
+}
+
+/// The fused orientation at a point in time.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct OrientationSample {
+    /// When the sample was taken.
+    pub timestamp: time::Instant,
+    /// The orientation of the headset.
+    pub orientation: Orientation,
+    /// The angular velocity of the headset in radians per second,
+    /// in the headset's frame of reference.
+    pub angular_velocity: Vector3,
+}
+
+/// What fusion needs to know about an inertial measurement unit.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Config {
+    /// How many samples the unit takes per second.
+    ///
+    /// Samples are integrated over the time that actually passed between
+    /// updates. This is only used before there is anything to measure from.
+    pub sample_rate: Scalar,
+    /// The Madgwick gain of the filter that corrects drift.
+    pub beta_anti_drift: Scalar,
+    /// The Madgwick gain of the filter that keeps the view steady.
+    pub beta_steadiness: Scalar,
+    /// The largest bias the gyroscope can plausibly have, in rad/s.
+    pub max_gyro_bias: Scalar,
+    /// How many samples the orientation history keeps.
+    pub history_length: usize,
+    /// How far past the newest sample `Fusion::orientation_at` will extrapolate.
+    pub max_extrapolation: Duration,
+}
+
+/// Tracks the orientation of a headset from its inertial measurement unit.
+#[derive(Debug)]
+pub struct Fusion {
+    config: Config,
+    integrators: Integrators,
+    /// When fusion was last updated.
+    last_update: Option<time::Instant>,
+    /// The newest sample, with the bias removed.
+    latest: Option<ImuSample>,
+    /// The most recent orientations, oldest first.
+    history: VecDeque<OrientationSample>,
+    /// Tracks the gyroscope bias.
+    bias: BiasTracker,
+}
+
+/// Estimates the gyroscope bias while the headset is stationary.
+#[derive(Debug, Default)]
+struct BiasTracker {
+    /// The current bias estimate.
+    bias: Vector3,
+    /// The recent mean of the raw gyroscope.
+    mean: Vector3,
+    /// The recent variance of the raw gyroscope.
+    variance: Scalar,
+    /// How long the headset has been still for.
+    still_for: Duration,
+}
+
+#[derive(Debug)]
+struct Integrators {
+    /// A Madgwick filter optimized for its anti-drift qualities.
+    anti_drift: ahrs::Madgwick<Scalar>,
+    /// A Madgwick filter optimized for its steadying qualities.
+    steadiness: ahrs::Madgwick<Scalar>,
+}
+
+/// Runs a Madgwick filter over a single sample that covers `delta` seconds.
+///
+/// The filter corrects towards gravity by normalising the accelerometer and
+/// the gradient between it and the estimate. Either can be zero, such as in
+/// free fall or when the estimate already matches gravity exactly, and the
+/// filter would divide by zero. The gyroscope alone is integrated instead.
+fn step(filter: &mut ahrs::Madgwick<Scalar>, sample: &ImuSample, delta: Scalar) {
+    let before = filter.quat;
+    let corrected = sample.accelerometer.norm() > MIN_ACCELEROMETER_NORM &&
+        filter.update_imu(&sample.gyroscope, &sample.accelerometer).is_ok() &&
+        filter.quat.coords.iter().all(|x| x.is_finite());
+
+    if !corrected {
+        let rate = before * Quaternion::from_parts(0.0, sample.gyroscope) * 0.5;
+        filter.quat = (before + rate * delta).normalize();
+    }
+}
+
+impl Config {
+    /// Creates a configuration for a unit with a given sample rate, using
+    /// gains and limits that suit most consumer headsets.
+    pub fn new(sample_rate: Scalar) -> Self {
+        Config {
+            sample_rate,
+            beta_anti_drift: 0.125,
+            beta_steadiness: 0.035,
+            max_gyro_bias: 0.05,
+            history_length: 2048,
+            max_extrapolation: Duration::from_millis(50),
+        }
+    }
+
+    /// Gets the time between samples.
+    pub fn sample_period(&self) -> Duration {
+        Duration::from_secs_f32(1.0 / self.sample_rate)
+    }
+}
+
+impl Fusion {
+    /// Starts tracking a headset at the identity orientation.
+    pub fn new(config: Config) -> Self {
+        let period = 1.0 / config.sample_rate;
+
+        Fusion {
+            integrators: Integrators {
+                anti_drift: ahrs::Madgwick::new(period, config.beta_anti_drift),
+                steadiness: ahrs::Madgwick::new(period, config.beta_steadiness),
+            },
+            last_update: None,
+            latest: None,
+            history: VecDeque::with_capacity(config.history_length),
+            bias: BiasTracker::default(),
+            config,
+        }
+    }
+
+    /// Gets the configuration.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Updates fusion with a sample taken just now.
+    pub fn update(&mut self, sample: &ImuSample) {
+        self.update_batch(std::slice::from_ref(sample))
+    }
+
+    /// Updates fusion with every sample taken since the last update,
+    /// oldest first.
+    ///
+    /// The time since the last update is split evenly between the samples.
+    pub fn update_batch(&mut self, samples: &[ImuSample]) {
+        self.update_batch_at(samples, time::Instant::now())
+    }
+
+    /// Updates fusion with samples taken up until `now`, oldest first.
+    ///
+    /// The time since the last update is split evenly between the samples.
+    /// The first update has nothing to measure from, so its samples are
+    /// taken to be one sample period apart.
+    pub fn update_batch_at(&mut self, samples: &[ImuSample], now: time::Instant) {
+        if samples.is_empty() {
+            return;
+        }
+
+        let period = match self.last_update {
+            Some(last) => now.saturating_duration_since(last) / samples.len() as u32,
+            None => self.config.sample_period(),
+        };
+        self.last_update = Some(now);
+
+        for (i, sample) in samples.iter().enumerate() {
+            let sample = self.bias.update(sample, period, self.config.max_gyro_bias);
+            self.integrate(&sample, period.as_secs_f64());
+
+            if self.history.len() == self.config.history_length {
+                self.history.pop_front();
+            }
+            self.history.push_back(OrientationSample {
+                timestamp: now - period * (samples.len() - i - 1) as u32,
+                orientation: self.orientation(),
+                angular_velocity: sample.gyroscope,
+            });
+            self.latest = Some(sample);
+        }
+    }
+
+    /// Integrates a single sample that covers `delta` seconds.
+    fn integrate(&mut self, sample: &ImuSample, delta: f64) {
+        // Change the sample period of the existing Madgwick object.
+        // The library doesn't directly support dynamic periods.
+        self.integrators.anti_drift = ahrs::Madgwick::new_with_quat(delta as _, self.config.beta_anti_drift, self.integrators.anti_drift.quat);
+        self.integrators.steadiness = ahrs::Madgwick::new_with_quat(delta as _, self.config.beta_steadiness, self.integrators.steadiness.quat);
+
+        step(&mut self.integrators.anti_drift, sample, delta as Scalar);
+        step(&mut self.integrators.steadiness, sample, delta as Scalar);
+    }
+
+    /// Gets the current orientation of the headset.
+    pub fn orientation(&self) -> Orientation {
+        // FIXME: t parameter should be calculated on-the-fly.
+        // https://github.com/dylanmckay/psvr-protocol/issues/14#issuecomment-435378326
+        let t = 0.5;
+
+        let anti_drift = UnitQuaternion::new_normalize(self.integrators.anti_drift.quat);
+        let steadiness = UnitQuaternion::new_normalize(self.integrators.steadiness.quat);
+        UnitQuaternion::slerp(&anti_drift, &steadiness, t).into()
+    }
+
+    /// Checks if the headset has been still for long enough to re-estimate
+    /// the gyroscope bias.
+    pub fn is_stationary(&self) -> bool {
+        self.bias.is_stationary()
+    }
+
+    /// Gets the current estimate of the gyroscope bias, in radians per second.
+    ///
+    /// This is subtracted from every gyroscope reading before fusion.
+    pub fn gyroscope_bias(&self) -> Vector3 {
+        self.bias.bias
+    }
+
+    /// Gets the angular velocity of the headset, in radians per second.
+    ///
+    /// The gyroscope bias has already been removed. Returns `None` until
+    /// fusion has been updated.
+    pub fn angular_velocity(&self, frame: Frame) -> Option<Vector3> {
+        let latest = self.latest.as_ref()?;
+        Some(self.to_frame(latest.gyroscope, frame))
+    }
+
+    /// Gets the acceleration of the headset with gravity removed, in metres
+    /// per second squared.
+    ///
+    /// Returns `None` until fusion has been updated.
+    pub fn linear_acceleration(&self, frame: Frame) -> Option<Vector3> {
+        let latest = self.latest.as_ref()?;
+
+        // At rest, the accelerometer reads 1g upwards.
+        let gravity = self.orientation().inverse() * Vector3::z();
+        Some(self.to_frame(latest.accelerometer - gravity, frame) * math::STANDARD_GRAVITY)
+    }
+
+    /// Converts a vector from the head frame.
+    fn to_frame(&self, head: Vector3, frame: Frame) -> Vector3 {
+        match frame {
+            Frame::Head => head,
+            Frame::World => self.orientation() * head,
+        }
+    }
+
+    /// Gets the most recent orientation samples, oldest first.
+    pub fn history(&self) -> impl Iterator<Item=&OrientationSample> {
+        self.history.iter()
+    }
+
+    /// Gets the orientation of the headset at a point in time.
+    ///
+    /// Orientations between samples are interpolated. Orientations after
+    /// the newest sample are extrapolated from its angular velocity, for
+    /// at most `Config::max_extrapolation`.
+    ///
+    /// Returns `None` if the time is before the oldest sample kept.
+    pub fn orientation_at(&self, time: time::Instant) -> Option<Orientation> {
+        let after = self.history.partition_point(|sample| sample.timestamp <= time);
+
+        let orientation = match (after.checked_sub(1).map(|i| &self.history[i]), self.history.get(after)) {
+            (None, _) => return None,
+            (Some(before), Some(after)) => {
+                let span = after.timestamp - before.timestamp;
+                let t = (time - before.timestamp).as_secs_f32() / span.as_secs_f32();
+                before.orientation.slerp(&after.orientation, t)
+            },
+            (Some(newest), None) => {
+                let ahead = (time - newest.timestamp).min(self.config.max_extrapolation);
+                newest.orientation * Orientation::from_rotation_vector(newest.angular_velocity * ahead.as_secs_f32())
+            },
+        };
+
+        Some(orientation)
+    }
+}
+
+impl BiasTracker {
+    /// Feeds the tracker a sample that covers `delta`, and gets the sample
+    /// with the bias removed.
+    ///
+    /// A slow, steady turn looks the same as bias, so turns slower than the
+    /// largest bias may be partly absorbed while the headset sits still.
+    fn update(&mut self, sample: &ImuSample, delta: Duration, max_bias: Scalar) -> ImuSample {
+        let dt = delta.as_secs_f32();
+        let smoothing = (dt / STILLNESS_TIME_CONSTANT).min(1.0);
+        let deviation = sample.gyroscope - self.mean;
+        self.mean += deviation * smoothing;
+        self.variance += (deviation.norm_squared() - self.variance) * smoothing;
+
+        let still = self.variance < STATIONARY_GYRO_VARIANCE
+            && (sample.gyroscope - self.bias).norm() < STATIONARY_GYRO_RATE
+            && (sample.accelerometer.norm() - 1.0).abs() < STATIONARY_ACCEL_TOLERANCE;
+        self.still_for = if still { self.still_for + delta } else { Duration::ZERO };
+
+        if self.is_stationary() {
+            let bias = self.bias + (sample.gyroscope - self.bias) * (dt / BIAS_TIME_CONSTANT).min(1.0);
+            self.bias = if bias.norm() > max_bias { bias.normalize() * max_bias } else { bias };
+        }
+
+        ImuSample {
+            gyroscope: sample.gyroscope - self.bias,
+            accelerometer: sample.accelerometer,
+        }
+    }
+
+    fn is_stationary(&self) -> bool {
+        self.still_for >= MIN_STATIONARY_TIME
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config() -> Config {
+        Config::new(1000.0)
+    }
+
+    /// Feeds fusion samples of a steady yaw, one every millisecond.
+    fn yawing(rate: Scalar, samples: u32) -> (Fusion, time::Instant) {
+        let mut fusion = Fusion::new(config());
+        let start = time::Instant::now();
+        let sample = ImuSample { gyroscope: Vector3::z() * rate, accelerometer: Vector3::z() };
+
+        for i in 0..=samples {
+            fusion.update_batch_at(std::slice::from_ref(&sample), start + Duration::from_millis(i as u64));
+        }
+        (fusion, start)
+    }
+
+    /// Feeds fusion the same sample every millisecond for a while.
+    ///
+    /// The gyroscope dithers by a little, like real sensor noise.
+    fn hold(fusion: &mut Fusion, start: time::Instant, from: Duration, to: Duration, sample: &ImuSample) {
+        let mut time = from;
+        while time < to {
+            let noise = if time.as_millis() & 1 == 0 { 0.002 } else { -0.002 };
+            let noisy = ImuSample { gyroscope: sample.gyroscope.add_scalar(noise), ..sample.clone() };
+
+            fusion.update_batch_at(std::slice::from_ref(&noisy), start + time);
+            time += Duration::from_millis(1);
+        }
+    }
+
+    fn yaw(orientation: Orientation) -> Scalar {
+        orientation.yaw_pitch_roll()[0]
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let (fusion, _) = yawing(0.0, config().history_length as u32 + 10);
+
+        assert_eq!(config().history_length, fusion.history().count());
+    }
+
+    #[test]
+    fn first_update_uses_the_sample_period() {
+        let (fusion, _) = yawing(1.0, 0);
+
+        assert!((yaw(fusion.orientation()) - 0.001).abs() < 1e-4);
+    }
+
+    #[test]
+    fn orientation_at_interpolates_between_samples() {
+        let (fusion, start) = yawing(1.0, 100);
+        let at = |micros| yaw(fusion.orientation_at(start + Duration::from_micros(micros)).unwrap());
+
+        let between = at(50_500);
+        assert!(at(50_000) < between && between < at(51_000));
+        assert!((between - (at(50_000) + at(51_000)) / 2.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn orientation_at_extrapolates_a_bounded_distance() {
+        let (fusion, start) = yawing(1.0, 100);
+        let newest = start + Duration::from_millis(100);
+        let at = |time| yaw(fusion.orientation_at(time).unwrap());
+
+        assert!((at(newest + Duration::from_millis(20)) - at(newest) - 0.02).abs() < 1e-3);
+        assert_eq!(at(newest + config().max_extrapolation), at(newest + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn angular_velocity_is_reported_in_both_frames() {
+        let (fusion, _) = yawing(1.0, 100);
+
+        assert_eq!(Some(Vector3::z()), fusion.angular_velocity(Frame::Head));
+        assert!((fusion.angular_velocity(Frame::World).unwrap() - Vector3::z()).norm() < 0.01);
+        assert_eq!(None, Fusion::new(config()).angular_velocity(Frame::Head));
+    }
+
+    #[test]
+    fn linear_acceleration_removes_gravity() {
+        let (fusion, _) = yawing(0.0, 100);
+
+        assert!(fusion.linear_acceleration(Frame::World).unwrap().norm() < 0.1);
+        assert_eq!(None, Fusion::new(config()).linear_acceleration(Frame::World));
+    }
+
+    #[test]
+    fn bias_is_learnt_while_stationary() {
+        let bias = Vector3::new(0.01, -0.02, 0.015);
+        let still = ImuSample { gyroscope: bias, accelerometer: Vector3::z() };
+        let mut fusion = Fusion::new(config());
+        let start = time::Instant::now();
+
+        hold(&mut fusion, start, Duration::ZERO, Duration::from_millis(400), &still);
+        assert!(!fusion.is_stationary());
+
+        hold(&mut fusion, start, Duration::from_millis(400), Duration::from_secs(12), &still);
+        assert!(fusion.is_stationary());
+        assert!((fusion.gyroscope_bias() - bias).norm() < 1e-3, "estimated bias {:?}", fusion.gyroscope_bias());
+
+        // With the bias removed, the view barely drifts.
+        let before = yaw(fusion.orientation());
+        hold(&mut fusion, start, Duration::from_secs(12), Duration::from_secs(22), &still);
+        assert!((yaw(fusion.orientation()) - before).abs() < 0.01);
+    }
+
+    #[test]
+    fn bias_is_kept_while_moving() {
+        let turning = ImuSample { gyroscope: Vector3::z(), accelerometer: Vector3::z() };
+        let shaken = ImuSample { gyroscope: Vector3::zeros(), accelerometer: Vector3::new(0.3, 0.0, 1.2) };
+        let mut fusion = Fusion::new(config());
+        let start = time::Instant::now();
+
+        hold(&mut fusion, start, Duration::ZERO, Duration::from_secs(2), &turning);
+        assert!(!fusion.is_stationary());
+        hold(&mut fusion, start, Duration::from_secs(2), Duration::from_secs(4), &shaken);
+        assert!(!fusion.is_stationary());
+        assert_eq!(Vector3::zeros(), fusion.gyroscope_bias());
+    }
+
+    #[test]
+    fn bias_is_limited() {
+        let still = ImuSample { gyroscope: Vector3::new(0.09, 0.0, 0.0), accelerometer: Vector3::z() };
+        let mut fusion = Fusion::new(Config { max_gyro_bias: 0.02, ..config() });
+
+        hold(&mut fusion, time::Instant::now(), Duration::ZERO, Duration::from_secs(10), &still);
+        assert!((fusion.gyroscope_bias().norm() - 0.02).abs() < 1e-6);
+    }
+
+    #[test]
+    fn degenerate_accelerometer_readings_fall_back_to_the_gyroscope() {
+        for &accelerometer in &[Vector3::zeros(), Vector3::z()] {
+            let mut fusion = Fusion::new(config());
+            let start = time::Instant::now();
+            let sample = ImuSample { gyroscope: Vector3::z(), accelerometer };
+
+            for i in 0..=100 {
+                fusion.update_batch_at(std::slice::from_ref(&sample), start + Duration::from_millis(i));
+            }
+
+            let orientation = fusion.orientation();
+            assert!(orientation.coords.iter().all(|x| x.is_finite()), "{:?} gave {:?}", accelerometer, orientation);
+            assert!((yaw(orientation) - 0.101).abs() < 1e-3, "{:?} gave a yaw of {}", accelerometer, yaw(orientation));
+        }
+    }
+
+    #[test]
+    fn orientation_at_is_unknown_before_history() {
+        let (fusion, start) = yawing(1.0, 10);
+
+        assert!(fusion.orientation_at(start - Duration::from_millis(1)).is_none());
+        assert!(Fusion::new(config()).orientation_at(start).is_none());
+    }
+}
@@ -1,11 +1,17 @@
 //! The core HMD data types for all devices.
+//!
+//! Only the math types are available without the `std` feature.
 
-extern crate failure;
-#[macro_use] extern crate failure_derive;
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "std")] extern crate ahrs;
+#[cfg(feature = "std")] extern crate failure;
+#[cfg(feature = "std")] #[macro_use] extern crate failure_derive;
 extern crate nalgebra as na;
 
-pub use self::error::Error;
+#[cfg(feature = "std")] pub use self::error::Error;
 
-mod error;
+#[cfg(feature = "std")] mod error;
+#[cfg(feature = "std")] pub mod fusion;
 pub mod math;
 
@@ -0,0 +1,166 @@
+//! Mathematics types.
+//!
+//! # Axes
+//!
+//! Every backend reports vectors and orientations in the same canonical
+//! frame: right-handed, with X pointing forward out of the front of the
+//! headset, Y to the wearer's left and Z up through the top of their head.
+//! This matches [ROS REP-103](https://www.ros.org/reps/rep-0103.html).
+//!
+//! Use a [`Convention`] to convert into the frame an engine expects.
+
+pub use self::orientation::{EulerOrder, Orientation};
+pub use self::pose::{NeckModel, Pose};
+
+use na;
+
+mod orientation;
+mod pose;
+
+/// The magnitude type.
+pub type Scalar = f32;
+/// The 2D vector type.
+pub type Vector2 = na::Vector2<Scalar>;
+/// The 3D vector type.
+pub type Vector3 = na::Vector3<Scalar>;
+/// The 3x3 matrix type.
+pub type Matrix3 = na::Matrix3<Scalar>;
+/// The 4x4 matrix type.
+pub type Matrix4 = na::Matrix4<Scalar>;
+/// The quaternion type.
+pub type Quaternion = na::Quaternion<Scalar>;
+
+/// Standard gravity, in metres per second squared.
+pub const STANDARD_GRAVITY: Scalar = 9.80665;
+
+/// A frame of reference that vectors are given in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Frame {
+    /// Relative to the headset, so that the axes turn with the head.
+    Head,
+    /// Relative to the world, so that the axes stay put as the head turns.
+    ///
+    /// The world frame is the head frame at the headset's starting
+    /// orientation, levelled so that Z points straight up.
+    World,
+}
+
+
+/// A coordinate system convention used by a graphics engine or framework.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Convention {
+    /// OpenGL: right-handed, X right, Y up, Z backward.
+    OpenGl,
+    /// Unity: left-handed, X right, Y up, Z forward.
+    Unity,
+    /// Unreal Engine: left-handed, X forward, Y right, Z up.
+    Unreal,
+    /// ROS REP-103: right-handed, X forward, Y left, Z up.
+    ///
+    /// This is the canonical frame, so converting to it changes nothing.
+    Ros,
+}
+
+impl Convention {
+    /// Whether the convention is right-handed.
+    pub fn is_right_handed(self) -> bool {
+        match self {
+            Convention::OpenGl | Convention::Ros => true,
+            Convention::Unity | Convention::Unreal => false,
+        }
+    }
+
+    /// Converts a vector from the canonical frame.
+    ///
+    /// This is for ordinary vectors such as acceleration. Rotation axes and
+    /// angular velocities also flip when the handedness changes, so use
+    /// [`Convention::axial_vector`] for those.
+    pub fn vector(self, canonical: Vector3) -> Vector3 {
+        let (forward, left, up) = (canonical.x, canonical.y, canonical.z);
+        match self {
+            Convention::OpenGl => Vector3::new(-left, up, -forward),
+            Convention::Unity => Vector3::new(-left, up, forward),
+            Convention::Unreal => Vector3::new(forward, -left, up),
+            Convention::Ros => canonical,
+        }
+    }
+
+    /// Converts an axial vector, such as an angular velocity, from the
+    /// canonical frame.
+    ///
+    /// Positive rotation follows the right-hand rule in right-handed
+    /// conventions and the left-hand rule in left-handed ones.
+    pub fn axial_vector(self, canonical: Vector3) -> Vector3 {
+        if self.is_right_handed() {
+            self.vector(canonical)
+        } else {
+            -self.vector(canonical)
+        }
+    }
+
+    /// Converts an orientation from the canonical frame.
+    pub fn orientation(self, canonical: Orientation) -> Orientation {
+        let q = canonical.quaternion();
+        // The conversion is a rotation or reflection, so the norm is kept.
+        Orientation::from(na::UnitQuaternion::new_unchecked(Quaternion::from_parts(q.w, self.axial_vector(q.imag()))))
+    }
+
+    /// Converts a pose from the canonical frame.
+    pub fn pose(self, canonical: Pose) -> Pose {
+        Pose { orientation: self.orientation(canonical.orientation), position: self.vector(canonical.position), ..canonical }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn yaw_left(angle: Scalar) -> Orientation {
+        Orientation::from_yaw_pitch_roll(angle, 0.0, 0.0)
+    }
+
+    #[test]
+    fn forward_maps_to_each_convention() {
+        let forward = Vector3::x();
+
+        assert_eq!(Vector3::new(0.0, 0.0, -1.0), Convention::OpenGl.vector(forward));
+        assert_eq!(Vector3::new(0.0, 0.0, 1.0), Convention::Unity.vector(forward));
+        assert_eq!(Vector3::new(1.0, 0.0, 0.0), Convention::Unreal.vector(forward));
+        assert_eq!(forward, Convention::Ros.vector(forward));
+    }
+
+    #[test]
+    fn converted_orientation_rotates_converted_vectors() {
+        let q = Orientation::from_yaw_pitch_roll(0.7, 0.3, 0.0);
+        let v = Vector3::new(0.2, -0.5, 0.9);
+        let rotated = q * v;
+
+        for &convention in &[Convention::OpenGl, Convention::Unity, Convention::Unreal, Convention::Ros] {
+            let q = convention.orientation(q);
+            assert!((q * convention.vector(v) - convention.vector(rotated)).norm() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn converted_pose_moves_converted_points() {
+        let pose = Pose::new(Orientation::from_yaw_pitch_roll(-0.4, 0.1, 0.8), Vector3::new(0.5, 1.0, -0.3), Default::default());
+        let v = Vector3::new(0.2, -0.5, 0.9);
+
+        for &convention in &[Convention::OpenGl, Convention::Unity, Convention::Unreal, Convention::Ros] {
+            let converted = convention.pose(pose);
+            assert!((converted * convention.vector(v) - convention.vector(pose * v)).norm() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn left_handed_conventions_reverse_rotation_sense() {
+        // Turning left is a positive yaw about Z in the canonical frame.
+        // In Unity, that is a negative (anticlockwise) turn about Y up.
+        let unity = Convention::Unity.orientation(yaw_left(0.5));
+        assert!(unity.j < 0.0 && unity.i.abs() < 1e-6 && unity.k.abs() < 1e-6);
+
+        // Unreal yaw is about Z up with positive turning right.
+        let unreal = Convention::Unreal.orientation(yaw_left(0.5));
+        assert!(unreal.k < 0.0);
+    }
+}
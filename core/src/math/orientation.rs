@@ -0,0 +1,302 @@
+//! Orientations and the different ways of writing them down.
+
+use super::{Matrix3, Quaternion, Scalar, Vector3};
+
+use core::f32::consts::{FRAC_PI_2, PI};
+use core::ops::{Deref, Mul};
+use na::{self, ComplexField, RealField, UnitQuaternion};
+
+/// How close the middle Euler angle must be to a singularity before the
+/// first and last angles are treated as rotating about the same axis.
+const GIMBAL_LOCK_EPSILON: Scalar = 1e-4;
+
+/// An orientation, stored as a quaternion that is always normalised.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Orientation(UnitQuaternion<Scalar>);
+
+/// An order of intrinsic Euler angle rotations.
+///
+/// Intrinsic rotations turn about the axes as they have been moved by the
+/// rotations before them, so `Zyx` is a turn about Z, then about the new Y,
+/// then about the newest X.
+///
+/// The first six orders use three different axes (Tait-Bryan angles) and
+/// the rest come back to the first axis (proper Euler angles).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum EulerOrder {
+    Xyz, Xzy, Yxz, Yzx, Zxy, Zyx,
+    Xyx, Xzx, Yxy, Yzy, Zxz, Zyz,
+}
+
+impl EulerOrder {
+    /// All of the orders.
+    pub const ALL: [EulerOrder; 12] = [
+        EulerOrder::Xyz, EulerOrder::Xzy, EulerOrder::Yxz,
+        EulerOrder::Yzx, EulerOrder::Zxy, EulerOrder::Zyx,
+        EulerOrder::Xyx, EulerOrder::Xzx, EulerOrder::Yxy,
+        EulerOrder::Yzy, EulerOrder::Zxz, EulerOrder::Zyz,
+    ];
+
+    /// Gets the indices of the axes to rotate about, in order.
+    fn axes(self) -> [usize; 3] {
+        const X: usize = 0;
+        const Y: usize = 1;
+        const Z: usize = 2;
+
+        match self {
+            EulerOrder::Xyz => [X, Y, Z],
+            EulerOrder::Xzy => [X, Z, Y],
+            EulerOrder::Yxz => [Y, X, Z],
+            EulerOrder::Yzx => [Y, Z, X],
+            EulerOrder::Zxy => [Z, X, Y],
+            EulerOrder::Zyx => [Z, Y, X],
+            EulerOrder::Xyx => [X, Y, X],
+            EulerOrder::Xzx => [X, Z, X],
+            EulerOrder::Yxy => [Y, X, Y],
+            EulerOrder::Yzy => [Y, Z, Y],
+            EulerOrder::Zxz => [Z, X, Z],
+            EulerOrder::Zyz => [Z, Y, Z],
+        }
+    }
+
+    /// Whether the first and last rotations are about the same axis.
+    pub fn is_proper(self) -> bool {
+        let axes = self.axes();
+        axes[0] == axes[2]
+    }
+}
+
+impl Orientation {
+    /// The orientation that doesn't rotate anything.
+    pub fn identity() -> Self {
+        Orientation(UnitQuaternion::identity())
+    }
+
+    /// Creates an orientation by normalising a quaternion.
+    ///
+    /// Returns `None` if the quaternion is zero or isn't finite.
+    pub fn from_quaternion(quaternion: Quaternion) -> Option<Self> {
+        let norm = quaternion.norm();
+        if norm.is_finite() && norm > 0.0 {
+            Some(Orientation(UnitQuaternion::new_unchecked(quaternion / norm)))
+        } else {
+            None
+        }
+    }
+
+    /// Creates an orientation from intrinsic Euler angles, in radians.
+    pub fn from_euler_angles(order: EulerOrder, angles: [Scalar; 3]) -> Self {
+        let axes = order.axes();
+        let rotation = |i: usize| {
+            let mut axis = Vector3::zeros();
+            axis[axes[i]] = 1.0;
+            UnitQuaternion::from_axis_angle(&na::Unit::new_unchecked(axis), angles[i])
+        };
+
+        Orientation(rotation(0) * rotation(1) * rotation(2))
+    }
+
+    /// Creates an orientation from yaw, pitch and roll, in radians.
+    ///
+    /// See `Orientation::yaw_pitch_roll`.
+    pub fn from_yaw_pitch_roll(yaw: Scalar, pitch: Scalar, roll: Scalar) -> Self {
+        Orientation::from_euler_angles(EulerOrder::Zyx, [yaw, pitch, roll])
+    }
+
+    /// Creates an orientation from a rotation vector, whose direction is the
+    /// axis to turn about and whose length is the angle in radians.
+    pub fn from_rotation_vector(rotation: Vector3) -> Self {
+        Orientation(UnitQuaternion::from_scaled_axis(rotation))
+    }
+
+    /// Creates an orientation from the rotation matrix closest to a matrix.
+    pub fn from_matrix(matrix: &Matrix3) -> Self {
+        Orientation(UnitQuaternion::from_matrix(matrix))
+    }
+
+    /// Gets the orientation as a quaternion.
+    pub fn quaternion(&self) -> Quaternion {
+        self.0.into_inner()
+    }
+
+    /// Gets the orientation as intrinsic Euler angles, in radians.
+    ///
+    /// Every angle is within `-π..=π`. The middle angle is within
+    /// `-π/2..=π/2` for Tait-Bryan orders and `0..=π` for proper ones.
+    ///
+    /// At gimbal lock, where the first and last axes line up, the whole turn
+    /// about them is given to the first angle and the last angle is zero.
+    pub fn euler_angles(&self, order: EulerOrder) -> [Scalar; 3] {
+        // This follows Bernardes and Viollet, "Quaternion to Euler angles
+        // conversion: A direct, general and computationally efficient
+        // method" (2022). It is written for extrinsic rotations, which are
+        // the same as intrinsic ones in the reverse order.
+        let [last_axis, j, i] = order.axes();
+        let proper = order.is_proper();
+        let k = if proper { 3 - i - j } else { last_axis };
+        let sign = ((i as i32 - j as i32) * (j as i32 - k as i32) * (k as i32 - i as i32) / 2) as Scalar;
+
+        let q = self.0.quaternion();
+        let (w, v) = (q.w, [q.i, q.j, q.k]);
+        let (a, b, c, d) = if proper {
+            (w, v[i], v[j], v[k] * sign)
+        } else {
+            (w - v[j], v[i] + v[k] * sign, v[j] + w, v[k] * sign - v[i])
+        };
+
+        let mut middle = 2.0 * RealField::atan2(ComplexField::hypot(c, d), ComplexField::hypot(a, b));
+        let half_sum = RealField::atan2(b, a);
+        let half_diff = RealField::atan2(d, c);
+
+        let (first, mut last) = if middle.abs() <= GIMBAL_LOCK_EPSILON {
+            (0.0, 2.0 * half_sum)
+        } else if (middle - PI).abs() <= GIMBAL_LOCK_EPSILON {
+            (0.0, 2.0 * half_diff)
+        } else {
+            (half_sum - half_diff, half_sum + half_diff)
+        };
+
+        if !proper {
+            last *= sign;
+            middle -= FRAC_PI_2;
+        }
+
+        // Back to the intrinsic order.
+        [wrap_angle(last), middle, wrap_angle(first)]
+    }
+
+    /// Gets yaw, pitch and roll, in radians.
+    ///
+    /// These are intrinsic Z-Y-X Euler angles in the canonical headset frame.
+    /// Yaw is positive turning left, pitch is positive looking down and roll
+    /// is positive tilting to the right.
+    pub fn yaw_pitch_roll(&self) -> [Scalar; 3] {
+        self.euler_angles(EulerOrder::Zyx)
+    }
+
+    /// Gets the orientation as a rotation vector, whose direction is the axis
+    /// to turn about and whose length is the angle in radians.
+    pub fn rotation_vector(&self) -> Vector3 {
+        self.0.scaled_axis()
+    }
+
+    /// Gets the orientation as a rotation matrix.
+    pub fn matrix(&self) -> Matrix3 {
+        self.0.to_rotation_matrix().into_inner()
+    }
+
+    /// Gets the orientation that undoes this one.
+    pub fn inverse(&self) -> Self {
+        Orientation(self.0.inverse())
+    }
+
+    /// Interpolates between two orientations along the shortest path.
+    pub fn slerp(&self, other: &Orientation, t: Scalar) -> Self {
+        Orientation(self.0.slerp(&other.0, t))
+    }
+}
+
+/// Wraps an angle into `-π..=π`.
+fn wrap_angle(angle: Scalar) -> Scalar {
+    if angle < -PI {
+        angle + 2.0 * PI
+    } else if angle > PI {
+        angle - 2.0 * PI
+    } else {
+        angle
+    }
+}
+
+impl Default for Orientation {
+    fn default() -> Self { Orientation::identity() }
+}
+
+impl Deref for Orientation {
+    type Target = UnitQuaternion<Scalar>;
+
+    fn deref(&self) -> &UnitQuaternion<Scalar> { &self.0 }
+}
+
+impl From<UnitQuaternion<Scalar>> for Orientation {
+    fn from(quaternion: UnitQuaternion<Scalar>) -> Self { Orientation(quaternion) }
+}
+
+impl From<Orientation> for UnitQuaternion<Scalar> {
+    fn from(orientation: Orientation) -> Self { orientation.0 }
+}
+
+impl From<Orientation> for Quaternion {
+    fn from(orientation: Orientation) -> Self { orientation.quaternion() }
+}
+
+impl Mul for Orientation {
+    type Output = Orientation;
+
+    fn mul(self, rhs: Orientation) -> Orientation { Orientation(self.0 * rhs.0) }
+}
+
+impl Mul<Vector3> for Orientation {
+    type Output = Vector3;
+
+    fn mul(self, rhs: Vector3) -> Vector3 { self.0 * rhs }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn assert_same(expected: Orientation, actual: Orientation) {
+        assert!(expected.angle_to(&actual) < 1e-3, "expected {:?} but got {:?}", expected, actual);
+    }
+
+    #[test]
+    fn euler_angles_round_trip_in_every_order() {
+        let samples = [[0.3, 0.2, -0.9], [-2.5, 1.1, 3.0], [1.0, -0.4, 0.1]];
+
+        for &order in EulerOrder::ALL.iter() {
+            for &angles in samples.iter() {
+                // Keep the middle angle in range for proper orders.
+                let angles = if order.is_proper() { [angles[0], angles[1].abs() + 0.5, angles[2]] } else { angles };
+                let orientation = Orientation::from_euler_angles(order, angles);
+                let found = orientation.euler_angles(order);
+
+                for (expected, actual) in angles.iter().zip(found.iter()) {
+                    assert!((expected - actual).abs() < 1e-3, "{:?}: expected {:?} but got {:?}", order, angles, found);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn euler_angles_survive_gimbal_lock() {
+        for &order in EulerOrder::ALL.iter() {
+            let middles: &[Scalar] = if order.is_proper() { &[0.0, PI] } else { &[FRAC_PI_2, -FRAC_PI_2] };
+
+            for &middle in middles {
+                let orientation = Orientation::from_euler_angles(order, [0.4, middle, 0.3]);
+                let found = orientation.euler_angles(order);
+
+                assert_eq!(0.0, found[2], "{:?}", order);
+                assert_same(orientation, Orientation::from_euler_angles(order, found));
+            }
+        }
+    }
+
+    #[test]
+    fn yaw_turns_left_about_up() {
+        let left = Orientation::from_yaw_pitch_roll(FRAC_PI_2, 0.0, 0.0);
+
+        assert!((left * Vector3::x() - Vector3::y()).norm() < 1e-6);
+        assert!((left.yaw_pitch_roll()[0] - FRAC_PI_2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn converts_to_and_from_other_forms() {
+        let orientation = Orientation::from_yaw_pitch_roll(0.7, -0.2, 1.3);
+
+        assert_same(orientation, Orientation::from_rotation_vector(orientation.rotation_vector()));
+        assert_same(orientation, Orientation::from_matrix(&orientation.matrix()));
+        assert_same(orientation, Orientation::from_quaternion(orientation.quaternion() * 3.0).unwrap());
+        assert_eq!(None, Orientation::from_quaternion(Quaternion::new(0.0, 0.0, 0.0, 0.0)));
+    }
+}
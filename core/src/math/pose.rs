@@ -0,0 +1,222 @@
+//! Poses, which place something in space as well as turning it.
+
+use super::{Matrix4, Orientation, Scalar, Vector3};
+
+use core::ops::Mul;
+use core::time::Duration;
+use na::{Isometry3, Translation3};
+
+/// An orientation and a position, at a point in time.
+///
+/// As a transform, a pose turns a point by its orientation and then moves
+/// it by its position. A headset pose takes points from the head frame to
+/// the world frame.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Pose {
+    /// The orientation.
+    pub orientation: Orientation,
+    /// The position, in metres.
+    pub position: Vector3,
+    /// When the pose was measured.
+    ///
+    /// Backends measure this from when the headset was opened, so poses
+    /// from different headsets can't be compared.
+    pub timestamp: Duration,
+}
+
+/// Places the eyes of a headset that only tracks orientation.
+///
+/// The head turns about the top of the neck, so the eyes move as it turns.
+/// At the starting orientation the eyes are at the origin.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct NeckModel {
+    /// Where the eyes are from the top of the neck in the head frame, in metres.
+    pub neck_to_eyes: Vector3,
+}
+
+impl Pose {
+    /// Creates a pose.
+    pub fn new(orientation: Orientation, position: Vector3, timestamp: Duration) -> Self {
+        Pose { orientation, position, timestamp }
+    }
+
+    /// The pose that doesn't move or turn anything, at time zero.
+    pub fn identity() -> Self {
+        Pose::new(Orientation::identity(), Vector3::zeros(), Duration::default())
+    }
+
+    /// Creates the pose of a headset that only tracks orientation, placing
+    /// the eyes with a neck model.
+    pub fn from_neck_model(orientation: Orientation, neck: &NeckModel, timestamp: Duration) -> Self {
+        Pose::new(orientation, neck.position(orientation), timestamp)
+    }
+
+    /// Creates a pose from a homogeneous transformation matrix.
+    ///
+    /// The rotation is taken from the closest rotation matrix to the upper
+    /// left 3x3 block, and any scale or projection is thrown away.
+    pub fn from_matrix(matrix: &Matrix4, timestamp: Duration) -> Self {
+        let rotation = matrix.fixed_slice::<na::U3, na::U3>(0, 0).into_owned();
+        let position = matrix.fixed_slice::<na::U3, na::U1>(0, 3).into_owned();
+
+        Pose::new(Orientation::from_matrix(&rotation), position, timestamp)
+    }
+
+    /// Gets the pose as a homogeneous transformation matrix.
+    pub fn matrix(&self) -> Matrix4 {
+        self.isometry().to_homogeneous()
+    }
+
+    /// Gets the pose as an isometry, leaving out the timestamp.
+    pub fn isometry(&self) -> Isometry3<Scalar> {
+        Isometry3::from_parts(Translation3::from(self.position), *self.orientation)
+    }
+
+    /// Gets the pose that undoes this one.
+    ///
+    /// The timestamp is kept.
+    pub fn inverse(&self) -> Self {
+        let orientation = self.orientation.inverse();
+        Pose::new(orientation, -(orientation * self.position), self.timestamp)
+    }
+
+    /// Applies another pose first, then this one.
+    ///
+    /// The timestamp is the later of the two.
+    pub fn compose(&self, other: &Pose) -> Self {
+        Pose::new(self.orientation * other.orientation,
+                  self.position + self.orientation * other.position,
+                  self.timestamp.max(other.timestamp))
+    }
+
+    /// Moves a point by the pose.
+    pub fn transform_point(&self, point: Vector3) -> Vector3 {
+        self.orientation * point + self.position
+    }
+
+    /// Turns a direction by the pose, without moving it.
+    pub fn transform_vector(&self, vector: Vector3) -> Vector3 {
+        self.orientation * vector
+    }
+
+    /// Interpolates between two poses.
+    ///
+    /// The orientation turns along the shortest path, and the position and
+    /// timestamp move in a straight line. `t` is 0 at this pose and 1 at the
+    /// other.
+    pub fn interpolate(&self, other: &Pose, t: Scalar) -> Self {
+        let (from, to) = (self.timestamp.as_secs_f64(), other.timestamp.as_secs_f64());
+        let timestamp = from + (to - from) * t as f64;
+
+        Pose::new(self.orientation.slerp(&other.orientation, t),
+                  self.position.lerp(&other.position, t),
+                  Duration::from_secs_f64(timestamp.max(0.0)))
+    }
+
+    /// Interpolates between two poses to find the pose at a point in time.
+    ///
+    /// Times outside of the two poses are extrapolated. If both poses have
+    /// the same timestamp, this pose is given back at the new time.
+    pub fn interpolate_at(&self, other: &Pose, timestamp: Duration) -> Self {
+        let span = other.timestamp.as_secs_f64() - self.timestamp.as_secs_f64();
+        let t = if span == 0.0 {
+            0.0
+        } else {
+            (timestamp.as_secs_f64() - self.timestamp.as_secs_f64()) / span
+        };
+
+        Pose { timestamp, ..self.interpolate(other, t as Scalar) }
+    }
+}
+
+impl NeckModel {
+    /// Gets where the eyes are when the head has a given orientation.
+    pub fn position(&self, orientation: Orientation) -> Vector3 {
+        orientation * self.neck_to_eyes - self.neck_to_eyes
+    }
+}
+
+impl Default for Pose {
+    fn default() -> Self { Pose::identity() }
+}
+
+impl Default for NeckModel {
+    /// The average adult neck, as used by the Oculus SDK: 8.05cm forward and
+    /// 7.5cm up.
+    fn default() -> Self {
+        NeckModel { neck_to_eyes: Vector3::new(0.0805, 0.0, 0.075) }
+    }
+}
+
+impl Mul for Pose {
+    type Output = Pose;
+
+    fn mul(self, rhs: Pose) -> Pose { self.compose(&rhs) }
+}
+
+impl Mul<Vector3> for Pose {
+    type Output = Vector3;
+
+    /// Moves a point by the pose.
+    fn mul(self, rhs: Vector3) -> Vector3 { self.transform_point(rhs) }
+}
+
+impl From<Pose> for Isometry3<Scalar> {
+    fn from(pose: Pose) -> Self { pose.isometry() }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::f32::consts::FRAC_PI_2;
+
+    fn example() -> Pose {
+        Pose::new(Orientation::from_yaw_pitch_roll(0.6, -0.2, 0.4), Vector3::new(1.0, -2.0, 0.5), Duration::from_millis(10))
+    }
+
+    fn assert_close(expected: &Pose, actual: &Pose) {
+        assert!(expected.orientation.angle_to(&actual.orientation) < 1e-4, "expected {:?} but got {:?}", expected, actual);
+        assert!((expected.position - actual.position).norm() < 1e-4, "expected {:?} but got {:?}", expected, actual);
+    }
+
+    #[test]
+    fn composes_like_matrices() {
+        let (a, b) = (example(), Pose::new(Orientation::from_yaw_pitch_roll(-1.0, 0.3, 0.0), Vector3::new(0.0, 0.2, 0.1), Duration::from_millis(20)));
+        let composed = a * b;
+
+        assert!((composed.matrix() - a.matrix() * b.matrix()).norm() < 1e-4);
+        assert_eq!(Duration::from_millis(20), composed.timestamp);
+        assert_close(&Pose::identity(), &(example() * example().inverse()));
+    }
+
+    #[test]
+    fn matrix_round_trips() {
+        let pose = example();
+        let point = Vector3::new(0.3, 0.1, -0.7);
+
+        assert_close(&pose, &Pose::from_matrix(&pose.matrix(), pose.timestamp));
+        assert!((pose.matrix().transform_point(&point.into()).coords - pose * point).norm() < 1e-5);
+    }
+
+    #[test]
+    fn interpolates_by_time() {
+        let from = Pose::new(Orientation::identity(), Vector3::zeros(), Duration::from_millis(100));
+        let to = Pose::new(Orientation::from_yaw_pitch_roll(FRAC_PI_2, 0.0, 0.0), Vector3::new(2.0, 0.0, 0.0), Duration::from_millis(200));
+
+        let middle = from.interpolate_at(&to, Duration::from_millis(150));
+        assert_close(&Pose::new(Orientation::from_yaw_pitch_roll(FRAC_PI_2 / 2.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0), middle.timestamp), &middle);
+        assert_eq!(Duration::from_millis(150), middle.timestamp);
+        assert_eq!(Duration::from_millis(150), from.interpolate(&to, 0.5).timestamp);
+    }
+
+    #[test]
+    fn neck_model_moves_the_eyes_as_the_head_turns() {
+        let neck = NeckModel::default();
+
+        assert_eq!(Vector3::zeros(), neck.position(Orientation::identity()));
+
+        // Looking straight down brings the eyes down in front of the neck.
+        let down = neck.position(Orientation::from_yaw_pitch_roll(0.0, FRAC_PI_2, 0.0));
+        assert!((down - Vector3::new(0.075 - 0.0805, 0.0, -0.0805 - 0.075)).norm() < 1e-5, "eyes moved to {:?}", down);
+    }
+}
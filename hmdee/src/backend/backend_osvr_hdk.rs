@@ -0,0 +1,337 @@
+//! The OSVR Hacker Development Kit (HDK 1.x and HDK2).
+//!
+//! The HDK fuses its IMU on board and reports the orientation over HID,
+//! along with the angular velocity on newer firmware.
+//!
+//! The report layout follows the HDK driver in OSVR-Core.
+
+use crate::{core::math, info, input, Error};
+use crate::backend::{hid, HeadMountedDevice};
+
+use std::time::{Duration, Instant};
+
+const HDK2_HDMI_MONITOR_NAME: &str = "OSVR HDK2";
+
+const HMD_RESOLUTION_HORIZONTAL: u32 = 2160;
+const HMD_RESOLUTION_VERTICAL: u32 = 1200;
+
+const LENS_RESOLUTION_HORIZONTAL: u32 = HMD_RESOLUTION_HORIZONTAL / 2; // one panel for left, one for right.
+const LENS_RESOLUTION_VERTICAL: u32 = HMD_RESOLUTION_VERTICAL;
+
+/// The length of an IMU report.
+const IMU_REPORT_LENGTH: usize = 32;
+/// The first report version that carries the angular velocity.
+const ANGULAR_VELOCITY_VERSION: u8 = 2;
+
+/// How long to wait for an IMU report.
+const READ_TIMEOUT_MILLISECONDS: i32 = 100;
+
+/// The quaternion components are Q1.14 fixed point.
+const QUATERNION_UNIT: math::Scalar = 1.0 / (1 << 14) as math::Scalar;
+/// The angular velocity is Q6.9 fixed point, in rad/s.
+const ANGULAR_VELOCITY_UNIT: math::Scalar = 1.0 / (1 << 9) as math::Scalar;
+
+fn hdk2_properties() -> info::Properties {
+    let lens = info::Lens {
+        resolution: (LENS_RESOLUTION_HORIZONTAL, LENS_RESOLUTION_VERTICAL),
+        field_of_view: info::FieldOfView {
+            horizontal: info::FieldOfViewAxis {
+                minimum_degrees: 92.0,
+                maximum_degrees: 92.0,
+                recommended_degrees: 92.0,
+            },
+            vertical: info::FieldOfViewAxis {
+                minimum_degrees: 92.0,
+                maximum_degrees: 92.0,
+                recommended_degrees: 92.0,
+            },
+        },
+        // Not provided, see `OsvrHdk`.
+        distortion_coefficients: vec![],
+        chromatic_aberration_factors: info::ChromaticAberrationFactors::no_adjustments(),
+    };
+
+    let visuals = info::Visuals::LensBased {
+        left: lens.clone(), right: lens,
+        lens_separation: info::Distance {
+            micrometers: 63_000, // 63 millimeters.
+        },
+        lens_to_eye_distance: info::Distance {
+            micrometers: 13_000, // 13 millimeters.
+        },
+        screen_to_lens_distance: info::Distance {
+            micrometers: 40_000, // 40 millimeters
+        },
+    };
+
+    info::Properties {
+        display_info: info::DisplayInfo {
+            monitor_name: HDK2_HDMI_MONITOR_NAME.to_owned(),
+            physical_size_millimeters: None,
+            supported_resolutions: vec![
+                (HMD_RESOLUTION_HORIZONTAL, HMD_RESOLUTION_VERTICAL),
+            ],
+        },
+        visuals
+    }
+}
+
+/// An OSVR HDK.
+///
+/// The display properties are those of the HDK2, except for the lens
+/// distortion, which isn't provided. Its `distortion_coefficients` are
+/// empty, as there is no source for HDK2 coefficients in this form to take
+/// them from. Applications need to correct for the lenses themselves, such
+/// as with OSVR's display descriptor for the HDK2.
+pub struct OsvrHdk {
+    /// The tracker's HID interface.
+    device: Box<dyn hid::Device>,
+    /// The latest IMU report.
+    latest_report: Option<ImuReport>,
+    /// The heading of the first report, which the world frame starts from.
+    reference: Option<math::Orientation>,
+    /// When the headset was opened, which pose timestamps count from.
+    opened_at: Instant,
+    /// When the tracker was last read.
+    updated_at: Option<Instant>,
+    /// The headset properties.
+    headset_properties: info::Properties,
+}
+
+/// An IMU report, in the canonical frame.
+#[derive(Clone, Debug, PartialEq)]
+struct ImuReport {
+    /// The version of the report format.
+    version: u8,
+    /// The orientation the HDK fused.
+    ///
+    /// The HDK has no magnetometer, so the heading is arbitrary.
+    orientation: math::Orientation,
+    /// The angular velocity in the head frame, in rad/s.
+    ///
+    /// Only reports from version 2 onwards carry it.
+    angular_velocity: Option<math::Vector3>,
+}
+
+impl OsvrHdk {
+    /// The USB vendor ID of Razer, who make the HDK.
+    pub const VENDOR_ID: u16 = 0x1532;
+    /// The USB product ID of the HDK tracker.
+    pub const PRODUCT_ID: u16 = 0x0b00;
+
+    /// Starts talking to an HDK over its tracker HID interface.
+    pub fn new<D>(device: D) -> Self
+        where D: hid::Device + 'static {
+        OsvrHdk {
+            device: Box::new(device),
+            latest_report: None,
+            reference: None,
+            opened_at: Instant::now(),
+            updated_at: None,
+            headset_properties: hdk2_properties(),
+        }
+    }
+
+    /// Gets the properties of the HDK2.
+    pub fn standard_properties() -> info::Properties { hdk2_properties() }
+
+    /// Gets the version of the report format the firmware sends, as of the
+    /// last update.
+    pub fn report_version(&self) -> Option<u8> {
+        self.latest_report.as_ref().map(|report| report.version)
+    }
+
+    /// Reads the next IMU report.
+    ///
+    /// Returns `None` if no report arrived within the timeout.
+    fn read_report(&mut self, timeout_milliseconds: i32) -> Result<Option<ImuReport>, Error> {
+        let mut buf = [0; IMU_REPORT_LENGTH];
+
+        match self.device.read_timeout(&mut buf, timeout_milliseconds)? {
+            0 => Ok(None),
+            length => ImuReport::decode(&buf[..length]).map(Some),
+        }
+    }
+
+    /// Takes in the newest IMU report.
+    fn process(&mut self, report: ImuReport) {
+        if self.reference.is_none() {
+            let [yaw, _, _] = report.orientation.yaw_pitch_roll();
+            self.reference = Some(math::Orientation::from_yaw_pitch_roll(yaw, 0.0, 0.0));
+        }
+
+        self.latest_report = Some(report);
+        self.updated_at = Some(Instant::now());
+    }
+}
+
+impl HeadMountedDevice for OsvrHdk {
+    fn product_name(&self) -> &'static str {
+        "OSVR HDK"
+    }
+
+    fn orientation(&self) -> math::Orientation {
+        match (self.reference, self.latest_report.as_ref()) {
+            (Some(reference), Some(report)) => reference.inverse() * report.orientation,
+            _ => math::Orientation::identity(),
+        }
+    }
+
+    fn pose_timestamp(&self) -> Duration {
+        self.updated_at.map(|updated_at| updated_at - self.opened_at).unwrap_or_default()
+    }
+
+    fn angular_velocity(&self, frame: math::Frame) -> Option<math::Vector3> {
+        let head = self.latest_report.as_ref()?.angular_velocity?;
+
+        Some(match frame {
+            math::Frame::Head => head,
+            math::Frame::World => self.orientation() * head,
+        })
+    }
+
+    /// The HDK has no buttons.
+    fn button(&self, _: input::Button) -> input::ButtonState {
+        input::ButtonState::NotPressed
+    }
+
+    fn properties(&self) -> &info::Properties {
+        &self.headset_properties
+    }
+
+    fn update(&mut self) -> Result<(), Error> {
+        let report = self.read_report(READ_TIMEOUT_MILLISECONDS)?
+            .ok_or_else(|| Error::communication_error("timed out waiting for an IMU report"))?;
+        self.process(report);
+
+        Ok(())
+    }
+
+    fn update_all(&mut self) -> Result<usize, Error> {
+        self.update()?;
+
+        // Every report holds the whole orientation, so only the newest matters.
+        let mut frames = 1;
+        while let Some(report) = self.read_report(0)? {
+            self.process(report);
+            frames += 1;
+        }
+
+        Ok(frames)
+    }
+
+    /// Does nothing, as the HDK powers on with its USB connection.
+    fn power_on(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Does nothing, as the HDK powers off with its USB connection.
+    fn power_off(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl ImuReport {
+    /// Decodes an IMU report.
+    fn decode(buf: &[u8]) -> Result<Self, Error> {
+        if buf.len() < 16 {
+            return Err(Error::communication_error(format!("malformed IMU report of {} bytes", buf.len())));
+        }
+
+        // The high nibble is the HDMI status on some firmware.
+        let version = buf[0] & 0x0f;
+        if version == 0 {
+            return Err(Error::communication_error("IMU report has no version"));
+        }
+
+        // Byte 1 is a sequence number.
+        let fixed_at = |i: usize, unit: math::Scalar| i16::from_le_bytes([buf[i], buf[i + 1]]) as math::Scalar * unit;
+        let vector_at = |i: usize, unit: math::Scalar| canonical(math::Vector3::new(fixed_at(i, unit), fixed_at(i + 2, unit), fixed_at(i + 4, unit)));
+
+        let imag = vector_at(2, QUATERNION_UNIT);
+        let orientation = math::Orientation::from_quaternion(math::Quaternion::new(fixed_at(8, QUATERNION_UNIT), imag.x, imag.y, imag.z))
+            .ok_or_else(|| Error::communication_error("IMU report has an invalid orientation"))?;
+
+        let angular_velocity = if version >= ANGULAR_VELOCITY_VERSION {
+            Some(vector_at(10, ANGULAR_VELOCITY_UNIT))
+        } else {
+            None
+        };
+
+        Ok(ImuReport { version, orientation, angular_velocity })
+    }
+}
+
+/// Converts a vector from the OSVR frame (X right, Y up, Z backward) to the
+/// canonical frame.
+///
+/// The change is a rotation, so this also converts rotation axes.
+fn canonical(v: math::Vector3) -> math::Vector3 {
+    math::Vector3::new(-v.z, -v.x, v.y)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::hid::mock;
+    use std::f32::consts::FRAC_PI_2;
+
+    /// A hand-built version 3 report, for an HDK turned 90° left and turning
+    /// left at 1 rad/s.
+    const TURNED_LEFT_REPORT: [u8; IMU_REPORT_LENGTH] = [
+        0x13, 0x2a, 0x00, 0x00, 0x41, 0x2d, 0x00, 0x00, 0x41, 0x2d, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+    /// A hand-built report that follows it, turned 120° left.
+    const TURNED_FURTHER_REPORT: [u8; IMU_REPORT_LENGTH] = [
+        0x13, 0x2b, 0x00, 0x00, 0x6d, 0x37, 0x00, 0x00, 0x00, 0x20, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+    /// A hand-built version 1 report, as an HDK 1.2 sends, turned 90° left.
+    const VERSION_1_REPORT: [u8; IMU_REPORT_LENGTH] = [
+        0x01, 0x07, 0x00, 0x00, 0x41, 0x2d, 0x00, 0x00, 0x41, 0x2d, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn decodes_imu_report() {
+        let report = ImuReport::decode(&TURNED_LEFT_REPORT).unwrap();
+
+        assert_eq!(3, report.version);
+        assert!(report.orientation.angle_to(&math::Orientation::from_yaw_pitch_roll(FRAC_PI_2, 0.0, 0.0)) < 1e-3,
+                "decoded {:?}", report.orientation);
+        assert!((report.angular_velocity.unwrap() - math::Vector3::z()).norm() < 1e-3);
+    }
+
+    #[test]
+    fn old_reports_have_no_angular_velocity() {
+        let report = ImuReport::decode(&VERSION_1_REPORT).unwrap();
+
+        assert_eq!(1, report.version);
+        assert_eq!(None, report.angular_velocity);
+    }
+
+    #[test]
+    fn rejects_malformed_reports() {
+        assert!(ImuReport::decode(&TURNED_LEFT_REPORT[..8]).is_err());
+        assert!(ImuReport::decode(&[0; IMU_REPORT_LENGTH]).is_err());
+    }
+
+    #[test]
+    fn orientation_starts_facing_forward() {
+        let device = mock::Device::new();
+        let mut hdk = OsvrHdk::new(device.clone());
+
+        device.push_input(&TURNED_LEFT_REPORT);
+        hdk.update().unwrap();
+        assert!(hdk.orientation().angle_to(&math::Orientation::identity()) < 1e-3);
+
+        device.push_input(&TURNED_FURTHER_REPORT);
+        assert_eq!(1, hdk.update_all().unwrap());
+
+        let turned = math::Orientation::from_yaw_pitch_roll(FRAC_PI_2 / 3.0, 0.0, 0.0);
+        assert!(hdk.orientation().angle_to(&turned) < 1e-3, "orientation is {:?}", hdk.orientation());
+        assert!((hdk.angular_velocity(math::Frame::Head).unwrap() - math::Vector3::z()).norm() < 1e-3);
+        assert!(hdk.update().is_err(), "there are no reports left");
+    }
+}
@@ -62,15 +62,45 @@ pub struct Psvr {
 
     /// The latest readout from the PSVR sensors.
     latest_sensor_readout: Option<psvr::sensor::Readout>,
+    /// Commands waiting to be sent, so they aren't sent too close together.
+    commands: psvr::queue::CommandQueue,
+    /// When the headset was opened, which pose timestamps count from.
+    opened_at: std::time::Instant,
+    /// When the sensors were last read.
+    updated_at: Option<std::time::Instant>,
     /// The headset properties.
     headset_properties: info::Properties,
 }
 
 impl Psvr {
+    /// Gets the properties shared by every PSVR.
+    ///
+    /// This is useful for replaying a PSVR recording without the headset.
+    pub fn standard_properties() -> info::Properties { psvr_properties() }
+
     /// Gets the underlying PSVR client.
     pub fn underlying(&self) -> &psvr::Psvr { &self.psvr }
     /// Gets the underlying PSVR client.
     pub fn underlying_mut(&mut self) -> &mut psvr::Psvr { &mut self.psvr }
+
+    /// Gets the commands waiting to be sent.
+    ///
+    /// Commands are sent as they become due whenever the headset updates.
+    /// A command that never takes effect doesn't fail the update, but is
+    /// kept for `CommandQueue::take_given_up`.
+    pub fn commands(&self) -> &psvr::queue::CommandQueue { &self.commands }
+    /// Gets the commands waiting to be sent.
+    pub fn commands_mut(&mut self) -> &mut psvr::queue::CommandQueue { &mut self.commands }
+
+    /// Confirms and sends queued commands.
+    fn send_commands(&mut self) -> Result<(), Error> {
+        if let Some(ref readout) = self.latest_sensor_readout {
+            self.commands.confirm(&readout.status);
+        }
+        self.commands.poll(self.psvr.control_mut())?;
+
+        Ok(())
+    }
 }
 
 impl HeadMountedDevice for Psvr {
@@ -78,10 +108,26 @@ impl HeadMountedDevice for Psvr {
         "PlayStation VR"
     }
 
-    fn orientation(&self) -> math::Quaternion {
+    fn orientation(&self) -> math::Orientation {
         self.psvr.orientation()
     }
 
+    fn pose_timestamp(&self) -> std::time::Duration {
+        self.updated_at.map(|updated_at| updated_at - self.opened_at).unwrap_or_default()
+    }
+
+    fn angular_velocity(&self, frame: math::Frame) -> Option<math::Vector3> {
+        self.psvr.angular_velocity(frame)
+    }
+
+    fn linear_acceleration(&self, frame: math::Frame) -> Option<math::Vector3> {
+        self.psvr.linear_acceleration(frame)
+    }
+
+    fn orientation_at(&self, time: std::time::Instant) -> Option<math::Orientation> {
+        self.psvr.orientation_at(time)
+    }
+
     fn button(&self, button: input::Button) -> input::ButtonState {
         match button {
             input::Button::VolumeUp => button_from_readout(&self.latest_sensor_readout, |r| r.buttons.plus),
@@ -90,6 +136,19 @@ impl HeadMountedDevice for Psvr {
         }
     }
 
+    fn status(&self) -> info::Status {
+        match self.latest_sensor_readout {
+            Some(ref readout) => info::Status {
+                worn: Some(readout.status.worn),
+                display_active: Some(readout.status.display_active),
+                hdmi_disconnected: Some(readout.status.hdmi_disconnected),
+                microphone_muted: Some(readout.status.microphone_muted),
+                headphone_connected: Some(readout.status.headphone_connected),
+            },
+            None => info::Status::default(),
+        }
+    }
+
     fn properties(&self) -> &info::Properties {
         &self.headset_properties
     }
@@ -97,20 +156,36 @@ impl HeadMountedDevice for Psvr {
     fn update(&mut self) -> Result<(), Error> {
         let sensor_readout = self.psvr.receive_sensor()?;
         self.latest_sensor_readout = Some(sensor_readout);
+        self.updated_at = Some(std::time::Instant::now());
 
-        Ok(())
+        self.send_commands()
     }
 
+    fn update_all(&mut self) -> Result<usize, Error> {
+        let update = self.psvr.receive_all_sensors()?;
+        self.latest_sensor_readout = Some(update.readout);
+        self.updated_at = Some(std::time::Instant::now());
+        self.send_commands()?;
+
+        Ok(update.frames)
+    }
+
+    /// Queues the commands that power on the headset and start tracking.
+    ///
+    /// The processor unit misses commands sent too close together, so only
+    /// the first is sent straight away. The rest are sent by later updates.
     fn power_on(&mut self) -> Result<(), Error> {
-        self.psvr.power_on()?;
-        self.psvr.vr_mode()?;
-        self.psvr.vr_tracking()?;
+        self.commands.push_set_power(true);
+        self.commands.push(psvr::command::SetVrMode { vr_mode: true });
+        self.commands.push(psvr::command::EnableVrTracking);
 
-        Ok(())
+        self.send_commands()
     }
 
     fn power_off(&mut self) -> Result<(), Error> {
-        self.psvr.power_off()
+        self.commands.push_set_power(false);
+
+        self.send_commands()
     }
 }
 
@@ -118,6 +193,9 @@ impl From<psvr::Psvr> for Psvr {
     fn from(psvr: psvr::Psvr) -> Self {
         Psvr {
             latest_sensor_readout: None,
+            commands: psvr::queue::CommandQueue::new(),
+            opened_at: std::time::Instant::now(),
+            updated_at: None,
             psvr,
             headset_properties: psvr_properties(),
         }
@@ -175,5 +253,81 @@ mod test {
             }
         }
     }
-}
 
+    mod commands {
+        use super::super::Psvr;
+        use crate::backend::HeadMountedDevice;
+        use psvr::command::{Command, EnableVrTracking, SetPower, SetVrMode};
+        use psvr::emulator::{Emulator, Timeline};
+        use std::time::Duration;
+
+        #[test]
+        fn power_on_is_sent_over_several_updates() {
+            let emulator = Emulator::new(Timeline::at_rest());
+            let mut hmd = Psvr::from(emulator.connect());
+
+            hmd.power_on().unwrap();
+            assert_eq!(1, emulator.state().commands.len());
+
+            while !hmd.commands().is_empty() {
+                hmd.update().unwrap();
+            }
+
+            let state = emulator.state();
+            assert!(state.powered && state.vr_mode && state.vr_tracking);
+            assert_eq!(3, state.commands.len());
+        }
+
+        #[test]
+        fn powering_on_twice_keeps_commands_in_order() {
+            let emulator = Emulator::new(Timeline::at_rest());
+            let mut hmd = Psvr::from(emulator.connect());
+
+            hmd.power_on().unwrap();
+            hmd.power_on().unwrap();
+            while !hmd.commands().is_empty() {
+                hmd.update().unwrap();
+            }
+
+            let ids: Vec<u8> = emulator.state().commands.iter().map(|command| command.id()).collect();
+            let power_on = [SetPower::ID, SetVrMode::ID, EnableVrTracking::ID];
+            assert_eq!([power_on, power_on].concat(), ids);
+        }
+
+        #[test]
+        fn unconfirmed_commands_do_not_fail_updates() {
+            let emulator = Emulator::new(Timeline::at_rest());
+            let mut hmd = Psvr::from(emulator.connect());
+            hmd.commands_mut().set_min_interval(Duration::from_millis(0));
+            hmd.commands_mut().set_retries(Duration::from_millis(0), 1);
+            hmd.commands_mut().push_confirmed(psvr::command::EnableVrTracking, |_| false);
+
+            hmd.update().unwrap();
+            hmd.update().unwrap();
+
+            assert!(hmd.commands().is_empty());
+            assert_eq!(vec![psvr::command::AnyCommand::from(psvr::command::EnableVrTracking)], hmd.commands_mut().take_given_up());
+        }
+    }
+
+    mod tracking {
+        use super::super::Psvr;
+        use crate::backend::HeadMountedDevice;
+        use psvr::emulator::{Emulator, Timeline};
+        use std::time::Duration;
+
+        #[test]
+        fn pose_is_timed_from_opening() {
+            let emulator = Emulator::new(Timeline::at_rest());
+            let mut hmd = Psvr::from(emulator.connect());
+            assert_eq!(Duration::default(), hmd.pose().timestamp);
+
+            std::thread::sleep(Duration::from_millis(5));
+            hmd.update().unwrap();
+
+            let pose = hmd.pose();
+            assert!(pose.timestamp >= Duration::from_millis(5));
+            assert!(pose.position.norm() < 0.01, "the head hasn't moved but the eyes are at {:?}", pose.position);
+        }
+    }
+}
@@ -0,0 +1,282 @@
+use crate::{core::math, info, input, record, Error};
+use crate::backend::HeadMountedDevice;
+
+use std::{thread, time};
+
+/// How a replay moves through its samples.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Timing {
+    /// Updates wait until each sample is due, as it was recorded.
+    Original,
+    /// Each update moves to the next sample straight away.
+    Stepped,
+}
+
+/// A headset that plays back a recording.
+///
+/// This makes it possible to reproduce what an application saw without
+/// the headset that was recorded.
+pub struct Replay {
+    samples: Vec<record::Sample>,
+    timing: Timing,
+    properties: info::Properties,
+    /// The index of the current sample, or `None` before the first update.
+    position: Option<usize>,
+    /// When the first sample played, and that sample's time.
+    anchor: Option<(time::Instant, f64)>,
+}
+
+impl Replay {
+    /// Creates a replay of some samples.
+    ///
+    /// The properties should be those of the headset that was recorded.
+    pub fn new(samples: Vec<record::Sample>,
+               properties: info::Properties,
+               timing: Timing) -> Self {
+        Replay { samples, timing, properties, position: None, anchor: None }
+    }
+
+    /// Gets the sample being played, if playback has started.
+    pub fn current(&self) -> Option<&record::Sample> {
+        self.position.map(|position| &self.samples[position])
+    }
+
+    /// Checks if every sample has been played.
+    pub fn is_finished(&self) -> bool {
+        self.next_position() >= self.samples.len()
+    }
+
+    fn next_position(&self) -> usize {
+        self.position.map(|position| position + 1).unwrap_or(0)
+    }
+
+    /// Gets when a sample is due, starting the clock if needed.
+    ///
+    /// Samples are due as long after the first sample as they were
+    /// recorded. One recorded before the first is due straight away.
+    fn due(&mut self, sample_time: f64) -> time::Instant {
+        let (played, first_time) = *self.anchor.get_or_insert_with(|| (time::Instant::now(), sample_time));
+        played + seconds(sample_time - first_time)
+    }
+}
+
+impl HeadMountedDevice for Replay {
+    fn product_name(&self) -> &'static str {
+        "Replay"
+    }
+
+    fn orientation(&self) -> math::Orientation {
+        self.current().map(record::Sample::orientation).unwrap_or_default()
+    }
+
+    /// Gets the time of the current sample, from the start of the recording.
+    fn pose_timestamp(&self) -> time::Duration {
+        self.current().map(|sample| seconds(sample.time)).unwrap_or_default()
+    }
+
+    fn angular_velocity(&self, frame: math::Frame) -> Option<math::Vector3> {
+        let head = math::Vector3::from(self.current()?.angular_velocity?);
+        Some(to_frame(head, self.orientation(), frame))
+    }
+
+    fn linear_acceleration(&self, frame: math::Frame) -> Option<math::Vector3> {
+        let head = math::Vector3::from(self.current()?.linear_acceleration?);
+        Some(to_frame(head, self.orientation(), frame))
+    }
+
+    fn button(&self, button: input::Button) -> input::ButtonState {
+        match self.current() {
+            Some(sample) => sample.button(button),
+            None => input::ButtonState::NotPressed,
+        }
+    }
+
+    fn status(&self) -> info::Status {
+        self.current().map(|sample| sample.status).unwrap_or_default()
+    }
+
+    fn properties(&self) -> &info::Properties {
+        &self.properties
+    }
+
+    fn update(&mut self) -> Result<(), Error> {
+        let next = self.next_position();
+        let time = self.samples.get(next)
+            .ok_or_else(|| Error::communication_error("the recording has no more samples"))?
+            .time;
+
+        if let Timing::Original = self.timing {
+            let due = self.due(time);
+            if let Some(wait) = due.checked_duration_since(time::Instant::now()) {
+                thread::sleep(wait);
+            }
+        }
+
+        self.position = Some(next);
+        Ok(())
+    }
+
+    fn update_all(&mut self) -> Result<usize, Error> {
+        self.update()?;
+        let mut frames = 1;
+
+        if let Timing::Original = self.timing {
+            while let Some(time) = self.samples.get(self.next_position()).map(|sample| sample.time) {
+                if self.due(time) > time::Instant::now() {
+                    break;
+                }
+
+                self.position = Some(self.next_position());
+                frames += 1;
+            }
+        }
+
+        Ok(frames)
+    }
+
+    fn power_on(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn power_off(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Converts a time in seconds to a duration, which is zero if the time is
+/// negative or not finite.
+fn seconds(time: f64) -> time::Duration {
+    time::Duration::try_from_secs_f64(time).unwrap_or_default()
+}
+
+fn to_frame(head: math::Vector3, orientation: math::Orientation, frame: math::Frame) -> math::Vector3 {
+    match frame {
+        math::Frame::Head => head,
+        math::Frame::World => orientation * head,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+    use crate::record::{Buttons, Sample};
+    use std::time::{Duration, Instant};
+
+    /// Properties for tests that don't need a particular headset's.
+    pub(crate) fn properties() -> info::Properties {
+        let lens = info::Lens {
+            resolution: (960, 1080),
+            field_of_view: info::FieldOfView {
+                horizontal: info::FieldOfViewAxis { minimum_degrees: 100.0, maximum_degrees: 100.0, recommended_degrees: 100.0 },
+                vertical: info::FieldOfViewAxis { minimum_degrees: 68.0, maximum_degrees: 68.0, recommended_degrees: 68.0 },
+            },
+            distortion_coefficients: vec![],
+            chromatic_aberration_factors: info::ChromaticAberrationFactors::no_adjustments(),
+        };
+
+        info::Properties {
+            visuals: info::Visuals::LensBased {
+                left: lens.clone(), right: lens,
+                lens_separation: info::Distance { micrometers: 63_100 },
+                lens_to_eye_distance: info::Distance { micrometers: 39_480 },
+                screen_to_lens_distance: info::Distance { micrometers: 35_400 },
+            },
+            display_info: info::DisplayInfo {
+                monitor_name: "Replay".to_owned(),
+                physical_size_millimeters: None,
+                supported_resolutions: vec![(1920, 1080)],
+            },
+        }
+    }
+
+    /// Samples of a steady yaw with volume up held on the last one.
+    fn samples(count: usize, period: f64) -> Vec<Sample> {
+        (0..count).map(|i| {
+            let orientation = math::Orientation::from_yaw_pitch_roll(i as math::Scalar * 0.1, 0.0, 0.0).quaternion();
+            Sample {
+                time: i as f64 * period,
+                orientation: [orientation.w, orientation.i, orientation.j, orientation.k],
+                buttons: Buttons {
+                    volume_up: if i + 1 == count { input::ButtonState::Pressed } else { input::ButtonState::NotPressed },
+                    volume_down: input::ButtonState::NotPressed,
+                    mute: input::ButtonState::NotPressed,
+                },
+                angular_velocity: Some([0.0, 0.0, 0.1]),
+                linear_acceleration: None,
+                status: info::Status::default(),
+            }
+        }).collect()
+    }
+
+    #[test]
+    fn steps_through_samples() {
+        let mut replay = Replay::new(samples(3, 10.0), properties(), Timing::Stepped);
+        let started = Instant::now();
+
+        for _ in 0..3 {
+            replay.update().unwrap();
+        }
+
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!((replay.orientation().yaw_pitch_roll()[0] - 0.2).abs() < 1e-5);
+        assert_eq!(Duration::from_secs(20), replay.pose().timestamp);
+        assert!(replay.button(input::Button::VolumeUp).is_pressed());
+        assert_eq!(None, replay.linear_acceleration(math::Frame::Head));
+        assert!(replay.is_finished());
+        assert!(replay.update().is_err());
+    }
+
+    #[test]
+    fn plays_at_original_timing() {
+        let mut replay = Replay::new(samples(3, 0.02), properties(), Timing::Original);
+        let started = Instant::now();
+
+        replay.update().unwrap();
+        assert!(started.elapsed() < Duration::from_millis(20));
+        replay.update().unwrap();
+        replay.update().unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(40));
+    }
+
+    #[test]
+    fn update_all_catches_up() {
+        let mut replay = Replay::new(samples(3, 0.01), properties(), Timing::Original);
+
+        replay.update().unwrap();
+        std::thread::sleep(Duration::from_millis(30));
+
+        assert_eq!(2, replay.update_all().unwrap());
+        assert!(replay.is_finished());
+    }
+
+    #[test]
+    fn plays_recordings_that_start_late_from_the_first_sample() {
+        let mut samples = samples(2, 0.01);
+        for sample in &mut samples {
+            // Later than the host has been up for.
+            sample.time += 1e12;
+        }
+        let mut replay = Replay::new(samples, properties(), Timing::Original);
+        let started = Instant::now();
+
+        replay.update().unwrap();
+        replay.update().unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(Duration::from_secs_f64(1e12 + 0.01), replay.pose().timestamp);
+    }
+
+    #[test]
+    fn unplayable_times_do_not_panic() {
+        let mut samples = samples(3, 0.01);
+        samples[1].time = -1.0;
+        samples[2].time = f64::NAN;
+        let mut replay = Replay::new(samples, properties(), Timing::Original);
+
+        replay.update().unwrap();
+        replay.update().unwrap();
+        assert_eq!(Duration::default(), replay.pose().timestamp);
+        replay.update().unwrap();
+        assert_eq!(Duration::default(), replay.pose().timestamp);
+    }
+}
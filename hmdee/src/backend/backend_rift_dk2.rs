@@ -0,0 +1,464 @@
+//! The Oculus Rift DK2.
+//!
+//! The DK2 streams its IMU over a HID interface. It only does so for as long
+//! as the host keeps sending keep-alive feature reports.
+//!
+//! The report layouts follow OpenHMD's `drv_oculus_rift`.
+
+use crate::{core::math, info, input, Error};
+use crate::backend::{hid, HeadMountedDevice};
+use crate::core::fusion;
+
+use std::time::{Duration, Instant};
+
+const DK2_HDMI_MONITOR_NAME: &str = "Rift DK2";
+
+const HMD_RESOLUTION_HORIZONTAL: u32 = 1920;
+const HMD_RESOLUTION_VERTICAL: u32 = 1080;
+
+const LENS_RESOLUTION_HORIZONTAL: u32 = HMD_RESOLUTION_HORIZONTAL / 2; // one lens for left, one for right.
+const LENS_RESOLUTION_VERTICAL: u32 = HMD_RESOLUTION_VERTICAL; // both lens cover the full height of the display.
+
+/// The ID of the input report carrying IMU samples.
+const TRACKER_REPORT_ID: u8 = 0x0b;
+/// The length of a tracker report, including its ID.
+const TRACKER_REPORT_LENGTH: usize = 64;
+/// The most samples a tracker report carries.
+const SAMPLES_PER_REPORT: usize = 2;
+
+/// The ID of the feature report that configures the tracker.
+const SENSOR_CONFIG_REPORT_ID: u8 = 0x02;
+/// The ID of the feature report that keeps the tracker reporting.
+const KEEP_ALIVE_REPORT_ID: u8 = 0x11;
+/// Keeps the IMU reporting, without the LEDs used for positional tracking.
+const KEEP_ALIVE_IMU_ONLY: u8 = 0x0b;
+
+/// Sensor configuration flags.
+const SENSOR_CONFIG_USE_CALIBRATION: u8 = 0x04;
+const SENSOR_CONFIG_AUTO_CALIBRATION: u8 = 0x08;
+const SENSOR_CONFIG_MOTION_KEEP_ALIVE: u8 = 0x10;
+const SENSOR_CONFIG_COMMAND_KEEP_ALIVE: u8 = 0x20;
+
+/// How long the tracker keeps reporting after a keep-alive.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(10);
+/// How often keep-alives are sent, well within the interval.
+const KEEP_ALIVE_PERIOD: Duration = Duration::from_secs(5);
+
+/// How long to wait for a tracker report.
+const READ_TIMEOUT_MILLISECONDS: i32 = 100;
+
+/// How many samples the IMU takes per second.
+const SAMPLE_FREQUENCY: u32 = 1000;
+
+/// The size of a unit of acceleration, in m/s².
+const ACCELEROMETER_UNIT: math::Scalar = 1e-4;
+/// The size of a unit of rate of turn, in rad/s.
+const GYROSCOPE_UNIT: math::Scalar = 1e-4;
+
+fn rift_dk2_properties() -> info::Properties {
+    let lens = info::Lens {
+        resolution: (LENS_RESOLUTION_HORIZONTAL, LENS_RESOLUTION_VERTICAL),
+        // From the default FOV port of the Oculus SDK.
+        field_of_view: info::FieldOfView {
+            horizontal: info::FieldOfViewAxis {
+                minimum_degrees: 94.0,
+                maximum_degrees: 94.0,
+                recommended_degrees: 94.0,
+            },
+            vertical: info::FieldOfViewAxis {
+                minimum_degrees: 106.0,
+                maximum_degrees: 106.0,
+                recommended_degrees: 106.0,
+            },
+        },
+        // From OpenHMD's hand calibration of the DK2.
+        distortion_coefficients: vec![0.098, 0.324, -0.241, 0.819],
+        chromatic_aberration_factors: info::ChromaticAberrationFactors {
+            red: 0.995242,
+            green: 1.0,
+            blue: 1.0008074,
+        },
+    };
+
+    let visuals = info::Visuals::LensBased {
+        left: lens.clone(), right: lens,
+        lens_separation: info::Distance {
+            micrometers: 63_500, // 63.5 millimeters.
+        },
+        lens_to_eye_distance: info::Distance {
+            micrometers: 12_000, // 12 millimeters, with the default eye cups.
+        },
+        screen_to_lens_distance: info::Distance {
+            micrometers: 40_000, // 40 millimeters
+        },
+    };
+
+    info::Properties {
+        display_info: info::DisplayInfo {
+            monitor_name: DK2_HDMI_MONITOR_NAME.to_owned(),
+            physical_size_millimeters: Some((126, 71)),
+            supported_resolutions: vec![
+                (HMD_RESOLUTION_HORIZONTAL, HMD_RESOLUTION_VERTICAL),
+            ],
+        },
+        visuals
+    }
+}
+
+/// An Oculus Rift DK2.
+pub struct RiftDk2 {
+    /// The tracker's HID interface.
+    device: Box<dyn hid::Device>,
+    /// Tracks the orientation from the IMU.
+    fusion: fusion::Fusion,
+    /// The latest tracker report.
+    latest_report: Option<TrackerReport>,
+    /// The ID of the next feature report command.
+    next_command_id: u16,
+    /// When a keep-alive was last sent.
+    kept_alive_at: Option<Instant>,
+    /// When the headset was opened, which pose timestamps count from.
+    opened_at: Instant,
+    /// When the tracker was last read.
+    updated_at: Option<Instant>,
+    /// The headset properties.
+    headset_properties: info::Properties,
+}
+
+/// A tracker report, holding up to two IMU samples.
+#[derive(Clone, Debug, PartialEq)]
+struct TrackerReport {
+    /// How many samples were taken since the last report.
+    ///
+    /// This can be more than the report has room for if reports were missed.
+    sample_count: u8,
+    /// The temperature of the IMU, in hundredths of a degree Celsius.
+    temperature: i16,
+    /// The samples, oldest first.
+    samples: [RawSample; SAMPLES_PER_REPORT],
+    /// The magnetometer reading, in units of 10⁻⁴ gauss.
+    magnetometer: [i16; 3],
+}
+
+/// An IMU sample in the Rift's frame: X right, Y up and Z backward.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+struct RawSample {
+    /// The acceleration, in units of 10⁻⁴ m/s².
+    accelerometer: [i32; 3],
+    /// The rate of turn, in units of 10⁻⁴ rad/s.
+    gyroscope: [i32; 3],
+}
+
+impl RiftDk2 {
+    /// The USB vendor ID of Oculus VR.
+    pub const VENDOR_ID: u16 = 0x2833;
+    /// The USB product ID of the DK2.
+    pub const PRODUCT_ID: u16 = 0x0021;
+
+    /// Starts talking to a DK2 over its HID interface.
+    ///
+    /// This configures the tracker and sends the first keep-alive.
+    pub fn new<D>(device: D) -> Result<Self, Error>
+        where D: hid::Device + 'static {
+        let mut rift = RiftDk2 {
+            device: Box::new(device),
+            fusion: fusion::Fusion::new(fusion::Config::new(SAMPLE_FREQUENCY as math::Scalar)),
+            latest_report: None,
+            next_command_id: 0,
+            kept_alive_at: None,
+            opened_at: Instant::now(),
+            updated_at: None,
+            headset_properties: rift_dk2_properties(),
+        };
+        rift.configure()?;
+        rift.keep_alive()?;
+
+        Ok(rift)
+    }
+
+    /// Gets the properties shared by every DK2.
+    pub fn standard_properties() -> info::Properties { rift_dk2_properties() }
+
+    /// Gets the temperature of the IMU in degrees Celsius, as of the last
+    /// update.
+    pub fn temperature(&self) -> Option<math::Scalar> {
+        self.latest_report.as_ref().map(|report| report.temperature as math::Scalar / 100.0)
+    }
+
+    /// Gets the magnetometer reading in gauss, in the canonical head frame,
+    /// as of the last update.
+    pub fn magnetometer(&self) -> Option<math::Vector3> {
+        self.latest_report.as_ref().map(|report| {
+            let [x, y, z] = report.magnetometer;
+            canonical([x as i32, y as i32, z as i32]) * 1e-4
+        })
+    }
+
+    /// Tells the tracker to report calibrated samples at its full rate.
+    fn configure(&mut self) -> Result<(), Error> {
+        let flags = SENSOR_CONFIG_USE_CALIBRATION | SENSOR_CONFIG_AUTO_CALIBRATION |
+            SENSOR_CONFIG_MOTION_KEEP_ALIVE | SENSOR_CONFIG_COMMAND_KEEP_ALIVE;
+        let [command_lo, command_hi] = self.command_id().to_le_bytes();
+        let [interval_lo, interval_hi] = (KEEP_ALIVE_INTERVAL.as_millis() as u16).to_le_bytes();
+
+        // A packet interval of zero sends every sample.
+        self.device.send_feature_report(&[SENSOR_CONFIG_REPORT_ID, command_lo, command_hi, flags, 0, interval_lo, interval_hi])
+    }
+
+    /// Keeps the tracker reporting for another keep-alive interval.
+    fn keep_alive(&mut self) -> Result<(), Error> {
+        let [command_lo, command_hi] = self.command_id().to_le_bytes();
+        let [interval_lo, interval_hi] = (KEEP_ALIVE_INTERVAL.as_millis() as u16).to_le_bytes();
+
+        self.device.send_feature_report(&[KEEP_ALIVE_REPORT_ID, command_lo, command_hi, KEEP_ALIVE_IMU_ONLY, interval_lo, interval_hi])?;
+        self.kept_alive_at = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Sends a keep-alive if one is due.
+    fn keep_alive_if_due(&mut self) -> Result<(), Error> {
+        match self.kept_alive_at {
+            Some(at) if at.elapsed() < KEEP_ALIVE_PERIOD => Ok(()),
+            _ => self.keep_alive(),
+        }
+    }
+
+    /// Takes the ID for the next command.
+    fn command_id(&mut self) -> u16 {
+        let id = self.next_command_id;
+        self.next_command_id = self.next_command_id.wrapping_add(1);
+        id
+    }
+
+    /// Reads the next tracker report, skipping any other reports.
+    ///
+    /// Returns `None` if no tracker report arrived within the timeout.
+    fn read_report(&mut self, timeout_milliseconds: i32) -> Result<Option<TrackerReport>, Error> {
+        let mut buf = [0; TRACKER_REPORT_LENGTH];
+
+        loop {
+            match self.device.read_timeout(&mut buf, timeout_milliseconds)? {
+                0 => return Ok(None),
+                length if buf[0] == TRACKER_REPORT_ID => return TrackerReport::decode(&buf[..length]).map(Some),
+                _ => continue,
+            }
+        }
+    }
+
+    /// Feeds the samples from tracker reports into fusion.
+    fn process(&mut self, reports: Vec<TrackerReport>) {
+        let samples: Vec<_> = reports.iter().flat_map(TrackerReport::samples).map(RawSample::imu_sample).collect();
+        self.fusion.update_batch(&samples);
+
+        self.latest_report = reports.into_iter().last();
+        self.updated_at = Some(Instant::now());
+    }
+}
+
+impl HeadMountedDevice for RiftDk2 {
+    fn product_name(&self) -> &'static str {
+        "Oculus Rift DK2"
+    }
+
+    fn orientation(&self) -> math::Orientation {
+        self.fusion.orientation()
+    }
+
+    fn pose_timestamp(&self) -> Duration {
+        self.updated_at.map(|updated_at| updated_at - self.opened_at).unwrap_or_default()
+    }
+
+    fn angular_velocity(&self, frame: math::Frame) -> Option<math::Vector3> {
+        self.fusion.angular_velocity(frame)
+    }
+
+    fn linear_acceleration(&self, frame: math::Frame) -> Option<math::Vector3> {
+        self.fusion.linear_acceleration(frame)
+    }
+
+    fn orientation_at(&self, time: Instant) -> Option<math::Orientation> {
+        self.fusion.orientation_at(time)
+    }
+
+    /// The DK2 has no buttons.
+    fn button(&self, _: input::Button) -> input::ButtonState {
+        input::ButtonState::NotPressed
+    }
+
+    fn properties(&self) -> &info::Properties {
+        &self.headset_properties
+    }
+
+    fn update(&mut self) -> Result<(), Error> {
+        self.keep_alive_if_due()?;
+
+        let report = self.read_report(READ_TIMEOUT_MILLISECONDS)?
+            .ok_or_else(|| Error::communication_error("timed out waiting for a tracker report"))?;
+        self.process(vec![report]);
+
+        Ok(())
+    }
+
+    fn update_all(&mut self) -> Result<usize, Error> {
+        self.keep_alive_if_due()?;
+
+        let mut reports = Vec::new();
+        let mut timeout = READ_TIMEOUT_MILLISECONDS;
+        while let Some(report) = self.read_report(timeout)? {
+            reports.push(report);
+            timeout = 0;
+        }
+
+        if reports.is_empty() {
+            return Err(Error::communication_error("timed out waiting for a tracker report"));
+        }
+
+        let frames = reports.len();
+        self.process(reports);
+        Ok(frames)
+    }
+
+    /// Starts the tracker reporting.
+    ///
+    /// The DK2 display can't be switched over HID; it follows the video
+    /// signal.
+    fn power_on(&mut self) -> Result<(), Error> {
+        self.configure()?;
+        self.keep_alive()
+    }
+
+    /// Does nothing, as the DK2 display follows the video signal.
+    ///
+    /// The tracker stops reporting on its own once updates, and so
+    /// keep-alives, stop.
+    fn power_off(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl TrackerReport {
+    /// Decodes a tracker report, including its report ID.
+    fn decode(buf: &[u8]) -> Result<Self, Error> {
+        if buf.len() < TRACKER_REPORT_LENGTH || buf[0] != TRACKER_REPORT_ID {
+            return Err(Error::communication_error(format!("malformed tracker report of {} bytes", buf.len())));
+        }
+
+        let u16_at = |i: usize| u16::from_le_bytes([buf[i], buf[i + 1]]);
+        let i16_at = |i: usize| u16_at(i) as i16;
+
+        let mut samples = [RawSample::default(); SAMPLES_PER_REPORT];
+        for (i, sample) in samples.iter_mut().enumerate() {
+            let at = 12 + i * 16;
+            sample.accelerometer = unpack_sensor(&buf[at..at + 8]);
+            sample.gyroscope = unpack_sensor(&buf[at + 8..at + 16]);
+        }
+
+        Ok(TrackerReport {
+            sample_count: buf[3],
+            temperature: i16_at(6),
+            samples,
+            magnetometer: [i16_at(44), i16_at(46), i16_at(48)],
+        })
+    }
+
+    /// Gets the samples the report carries, oldest first.
+    fn samples(&self) -> &[RawSample] {
+        &self.samples[..(self.sample_count as usize).min(SAMPLES_PER_REPORT)]
+    }
+}
+
+impl RawSample {
+    /// Converts the sample into sensor fusion input.
+    fn imu_sample(&self) -> fusion::ImuSample {
+        fusion::ImuSample {
+            gyroscope: canonical(self.gyroscope) * GYROSCOPE_UNIT,
+            accelerometer: canonical(self.accelerometer) * (ACCELEROMETER_UNIT / math::STANDARD_GRAVITY),
+        }
+    }
+}
+
+/// Unpacks three signed 21-bit values packed big-endian into eight bytes.
+fn unpack_sensor(buf: &[u8]) -> [i32; 3] {
+    let packed = u64::from_be_bytes([buf[0], buf[1], buf[2], buf[3], buf[4], buf[5], buf[6], buf[7]]);
+    // Shift each value to the top, then back down to sign extend it.
+    let field = |offset: u32| ((packed << offset) as i64 >> 43) as i32;
+
+    [field(0), field(21), field(42)]
+}
+
+/// Converts a vector from the Rift frame (X right, Y up, Z backward) to the
+/// canonical frame.
+fn canonical([x, y, z]: [i32; 3]) -> math::Vector3 {
+    math::Vector3::new(-z as math::Scalar, -x as math::Scalar, y as math::Scalar)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::hid::mock;
+
+    /// A hand-built tracker report with two samples of a level, still
+    /// headset, laid out as the DK2 sends them.
+    const RESTING_REPORT: [u8; TRACKER_REPORT_LENGTH] = [
+        0x0b, 0x03, 0x00, 0x02, 0x34, 0x12, 0x22, 0x0b, 0x87, 0xd6, 0x12, 0x00, 0xff, 0xfb, 0x50, 0x5f,
+        0xb9, 0x00, 0x03, 0x34, 0x00, 0x00, 0x67, 0xff, 0xfe, 0x40, 0x00, 0x06, 0xff, 0xfb, 0x60, 0x5f,
+        0xbb, 0xc0, 0x03, 0x24, 0x00, 0x00, 0x7f, 0xff, 0xfd, 0xc0, 0x00, 0x02, 0xf5, 0xfd, 0xba, 0x04,
+        0x58, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn decodes_tracker_report() {
+        let report = TrackerReport::decode(&RESTING_REPORT).unwrap();
+
+        assert_eq!(2850, report.temperature);
+        assert_eq!([-523, 1210, 88], report.magnetometer);
+        assert_eq!(&[
+            RawSample { accelerometer: [-150, 98020, 410], gyroscope: [12, -7, 3] },
+            RawSample { accelerometer: [-148, 98031, 402], gyroscope: [15, -9, 1] },
+        ], report.samples());
+    }
+
+    #[test]
+    fn rejects_short_reports() {
+        assert!(TrackerReport::decode(&RESTING_REPORT[..62]).is_err());
+    }
+
+    #[test]
+    fn resting_headset_reads_gravity_up() {
+        let report = TrackerReport::decode(&RESTING_REPORT).unwrap();
+        let sample = report.samples()[0].imu_sample();
+
+        assert!((sample.accelerometer - math::Vector3::z()).norm() < 0.01, "accelerometer read {:?}", sample.accelerometer);
+        assert!(sample.gyroscope.norm() < 0.01);
+    }
+
+    #[test]
+    fn configures_and_keeps_the_tracker_alive() {
+        let device = mock::Device::new();
+        let rift = RiftDk2::new(device.clone()).unwrap();
+
+        let sent = device.state().sent_features.clone();
+        assert_eq!(vec![
+            vec![SENSOR_CONFIG_REPORT_ID, 0, 0, 0x3c, 0, 0x10, 0x27],
+            vec![KEEP_ALIVE_REPORT_ID, 1, 0, KEEP_ALIVE_IMU_ONLY, 0x10, 0x27],
+        ], sent);
+        assert!(rift.temperature().is_none());
+    }
+
+    #[test]
+    fn updates_from_tracker_reports() {
+        let device = mock::Device::new();
+        let mut rift = RiftDk2::new(device.clone()).unwrap();
+
+        // Other reports are skipped.
+        device.push_input(&[0x0c; 64]);
+        for _ in 0..3 {
+            device.push_input(&RESTING_REPORT);
+        }
+
+        assert_eq!(3, rift.update_all().unwrap());
+        assert_eq!(Some(28.5), rift.temperature());
+        assert!(rift.orientation().angle_to(&math::Orientation::identity()) < 0.01);
+        assert!(rift.update().is_err(), "there are no reports left");
+    }
+}
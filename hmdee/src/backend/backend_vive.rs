@@ -0,0 +1,671 @@
+//! The HTC Vive and Vive Pro, tracking orientation from the IMU alone.
+//!
+//! The headset IMU is on the Lighthouse FPGA, a Valve USB device separate
+//! from the HTC mainboard that drives the display. Its HID interface also
+//! holds the headset's configuration, a zlib compressed JSON document with
+//! the IMU calibration and the lens parameters.
+//!
+//! The report layouts follow OpenHMD's `drv_htc_vive`.
+
+use crate::{core::math, info, input, Error};
+use crate::backend::{hid, HeadMountedDevice};
+use crate::core::fusion;
+use miniz_oxide;
+use serde_json::{self, Value};
+
+use std::time::{Duration, Instant};
+
+/// The ID of the input report carrying IMU samples.
+const IMU_REPORT_ID: u8 = 0x20;
+/// The length of an IMU report, including its ID.
+const IMU_REPORT_LENGTH: usize = 52;
+/// How many samples an IMU report carries.
+const SAMPLES_PER_REPORT: usize = 3;
+/// The length of each sample in an IMU report.
+const SAMPLE_LENGTH: usize = 17;
+
+/// The feature report that starts reading the configuration.
+const CONFIG_START_REPORT_ID: u8 = 0x10;
+/// The feature report that reads the next chunk of the configuration.
+const CONFIG_READ_REPORT_ID: u8 = 0x11;
+/// The length of the configuration feature reports.
+const CONFIG_REPORT_LENGTH: usize = 64;
+/// The largest configuration that will be read, compressed.
+const MAX_CONFIG_LENGTH: usize = 64 * 1024;
+
+/// How long to wait for an IMU report.
+const READ_TIMEOUT_MILLISECONDS: i32 = 100;
+
+/// How many samples the IMU takes per second.
+const SAMPLE_FREQUENCY: u32 = 1000;
+/// How fast the sample timestamps count, in ticks per second.
+const TICK_FREQUENCY: u64 = 48_000_000;
+
+/// The full scale of the accelerometer, in m/s².
+///
+/// The headset can be set to other ranges, but is ±4g by default.
+const ACCELEROMETER_RANGE: math::Scalar = 4.0 * math::STANDARD_GRAVITY;
+/// The full scale of the gyroscope, in rad/s.
+///
+/// The headset can be set to other ranges, but is ±500°/s by default.
+const GYROSCOPE_RANGE: math::Scalar = (500.0 * ::core::f64::consts::PI / 180.0) as math::Scalar;
+
+/// The lens separation to use if the configuration doesn't have one.
+const DEFAULT_LENS_SEPARATION_METRES: f64 = 0.0635;
+
+/// An HTC Vive or Vive Pro.
+pub struct Vive {
+    /// The IMU's HID interface.
+    device: Box<dyn hid::Device>,
+    /// The configuration read from the headset, as JSON.
+    config: String,
+    /// Whether the headset is a Vive Pro.
+    is_pro: bool,
+    /// Corrects and turns raw IMU samples.
+    calibration: ImuCalibration,
+    /// Tracks the orientation from the IMU.
+    fusion: fusion::Fusion,
+    /// Turns sample timestamps into instants, and holds the ticks of the
+    /// last sample used.
+    clock: Option<Clock>,
+    /// When the headset was opened, which pose timestamps count from.
+    opened_at: Instant,
+    /// When the newest sample used was taken.
+    sampled_at: Option<Instant>,
+    /// The headset properties.
+    headset_properties: info::Properties,
+}
+
+/// Turns the IMU's 32-bit tick counter into instants.
+///
+/// The first sample is taken to be read as it arrives, and later samples
+/// are placed from there by their ticks. The IMU clock drifts against the
+/// host clock by a few parts per million.
+#[derive(Copy, Clone, Debug)]
+struct Clock {
+    /// When the first sample was read.
+    started_at: Instant,
+    /// The ticks of the last sample.
+    last_ticks: u32,
+    /// The ticks since the first sample, which unlike the counter doesn't
+    /// wrap around.
+    elapsed_ticks: u64,
+}
+
+/// The IMU calibration from the configuration.
+#[derive(Clone, Debug, PartialEq)]
+struct ImuCalibration {
+    /// Subtracted from the accelerometer after scaling, in m/s².
+    accelerometer_bias: math::Vector3,
+    accelerometer_scale: math::Vector3,
+    /// Subtracted from the gyroscope after scaling, in rad/s.
+    gyroscope_bias: math::Vector3,
+    gyroscope_scale: math::Vector3,
+    /// Turns the IMU axes into the headset's (X right, Y up, Z backward).
+    imu_to_head: math::Matrix3,
+}
+
+/// An IMU sample as sent by the headset.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+struct RawSample {
+    /// The acceleration, as a fraction of `ACCELEROMETER_RANGE`.
+    accelerometer: [i16; 3],
+    /// The rate of turn, as a fraction of `GYROSCOPE_RANGE`.
+    gyroscope: [i16; 3],
+    /// When the sample was taken, in ticks of `TICK_FREQUENCY`.
+    ticks: u32,
+}
+
+impl Vive {
+    /// The USB vendor ID of Valve, who make the Lighthouse FPGA.
+    pub const VENDOR_ID: u16 = 0x28de;
+    /// The USB product ID of the Vive's Lighthouse FPGA.
+    pub const PRODUCT_ID: u16 = 0x2000;
+    /// The USB product ID of the Vive Pro's Lighthouse FPGA.
+    pub const PRO_PRODUCT_ID: u16 = 0x2300;
+    /// The number of the USB interface the IMU reports over.
+    pub const IMU_INTERFACE: i32 = 0;
+
+    /// Starts talking to a Vive over its IMU HID interface.
+    ///
+    /// This reads the configuration from the headset.
+    pub fn new<D>(device: D) -> Result<Self, Error>
+        where D: hid::Device + 'static {
+        let mut device = Box::new(device);
+        let config = read_config(&mut *device)?;
+        let json: Value = serde_json::from_str(&config).map_err(Error::communication_error)?;
+
+        let is_pro = json.get("model_number").and_then(Value::as_str)
+            .is_some_and(|model| model.to_lowercase().contains("pro"));
+
+        Ok(Vive {
+            device,
+            is_pro,
+            calibration: ImuCalibration::from_config(&json),
+            fusion: fusion::Fusion::new(fusion::Config::new(SAMPLE_FREQUENCY as math::Scalar)),
+            clock: None,
+            opened_at: Instant::now(),
+            sampled_at: None,
+            headset_properties: vive_properties(&json, is_pro),
+            config,
+        })
+    }
+
+    /// Gets the configuration read from the headset, as JSON.
+    pub fn config(&self) -> &str { &self.config }
+
+    /// Whether the headset is a Vive Pro.
+    pub fn is_pro(&self) -> bool { self.is_pro }
+
+    /// Reads the next IMU report, skipping any other reports.
+    ///
+    /// Returns `None` if no IMU report arrived within the timeout.
+    fn read_report(&mut self, timeout_milliseconds: i32) -> Result<Option<[RawSample; SAMPLES_PER_REPORT]>, Error> {
+        let mut buf = [0; IMU_REPORT_LENGTH];
+
+        loop {
+            match self.device.read_timeout(&mut buf, timeout_milliseconds)? {
+                0 => return Ok(None),
+                length if buf[0] == IMU_REPORT_ID => return decode_imu_report(&buf[..length]).map(Some),
+                _ => continue,
+            }
+        }
+    }
+
+    /// Feeds the samples from an IMU report that haven't been used yet into
+    /// fusion, oldest first.
+    ///
+    /// Each report repeats the two samples before its newest, so usually
+    /// only one sample is new. Samples are ordered by their ticks, as the
+    /// 8-bit sequence number wraps every 256 ms and can't tell new samples
+    /// from old ones after a stall.
+    fn process(&mut self, samples: [RawSample; SAMPLES_PER_REPORT]) {
+        let mut samples = samples;
+        let first = samples[0].ticks;
+        samples.sort_by_key(|sample| sample.ticks.wrapping_sub(first) as i32);
+
+        let last = self.clock.map(|clock| clock.last_ticks);
+        let is_new = |sample: &RawSample| last.is_none_or(|last| sample.ticks.wrapping_sub(last) as i32 > 0);
+
+        // If every sample looks older than the last one used without
+        // repeating it, the ticks have run on by over half their range,
+        // so start again from this report.
+        let resync = !samples.iter().any(|sample| is_new(sample) || Some(sample.ticks) == last);
+        if resync {
+            self.clock = None;
+        }
+
+        for sample in samples.iter().filter(|&sample| resync || is_new(sample)) {
+            let taken_at = self.tick(sample.ticks);
+            self.fusion.update_batch_at(&[self.calibration.imu_sample(sample)], taken_at);
+            self.sampled_at = Some(taken_at);
+        }
+    }
+
+    /// Works out when a sample was taken from its ticks.
+    fn tick(&mut self, ticks: u32) -> Instant {
+        let clock = self.clock.get_or_insert(Clock { started_at: Instant::now(), last_ticks: ticks, elapsed_ticks: 0 });
+        clock.elapsed_ticks += ticks.wrapping_sub(clock.last_ticks) as u64;
+        clock.last_ticks = ticks;
+
+        clock.started_at + Duration::from_nanos(clock.elapsed_ticks * 1_000_000_000 / TICK_FREQUENCY)
+    }
+}
+
+impl HeadMountedDevice for Vive {
+    fn product_name(&self) -> &'static str {
+        if self.is_pro { "HTC Vive Pro" } else { "HTC Vive" }
+    }
+
+    fn orientation(&self) -> math::Orientation {
+        self.fusion.orientation()
+    }
+
+    fn pose_timestamp(&self) -> Duration {
+        self.sampled_at.map(|sampled_at| sampled_at.saturating_duration_since(self.opened_at)).unwrap_or_default()
+    }
+
+    fn angular_velocity(&self, frame: math::Frame) -> Option<math::Vector3> {
+        self.fusion.angular_velocity(frame)
+    }
+
+    fn linear_acceleration(&self, frame: math::Frame) -> Option<math::Vector3> {
+        self.fusion.linear_acceleration(frame)
+    }
+
+    fn orientation_at(&self, time: Instant) -> Option<math::Orientation> {
+        self.fusion.orientation_at(time)
+    }
+
+    /// The headset's only button is on the mainboard, which isn't opened.
+    fn button(&self, _: input::Button) -> input::ButtonState {
+        input::ButtonState::NotPressed
+    }
+
+    fn properties(&self) -> &info::Properties {
+        &self.headset_properties
+    }
+
+    fn update(&mut self) -> Result<(), Error> {
+        let samples = self.read_report(READ_TIMEOUT_MILLISECONDS)?
+            .ok_or_else(|| Error::communication_error("timed out waiting for an IMU report"))?;
+        self.process(samples);
+
+        Ok(())
+    }
+
+    fn update_all(&mut self) -> Result<usize, Error> {
+        self.update()?;
+
+        let mut frames = 1;
+        while let Some(samples) = self.read_report(0)? {
+            self.process(samples);
+            frames += 1;
+        }
+
+        Ok(frames)
+    }
+
+    /// Does nothing, as the display is powered through the HTC mainboard,
+    /// which isn't opened.
+    fn power_on(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Does nothing, as the display is powered through the HTC mainboard,
+    /// which isn't opened.
+    fn power_off(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl ImuCalibration {
+    /// Reads the calibration from the configuration.
+    ///
+    /// The Vive Pro keeps it in an `imu` object, and the Vive at the top
+    /// level. Anything missing is left uncorrected.
+    fn from_config(config: &Value) -> Self {
+        let field = |name: &str| config.get("imu").and_then(|imu| imu.get(name)).or_else(|| config.get(name)).and_then(json_vector);
+
+        let plus_x = field("plus_x").unwrap_or_else(math::Vector3::x);
+        let plus_z = field("plus_z").unwrap_or_else(math::Vector3::z);
+
+        ImuCalibration {
+            accelerometer_bias: field("acc_bias").unwrap_or_else(math::Vector3::zeros),
+            accelerometer_scale: field("acc_scale").unwrap_or_else(|| math::Vector3::repeat(1.0)),
+            gyroscope_bias: field("gyro_bias").unwrap_or_else(math::Vector3::zeros),
+            gyroscope_scale: field("gyro_scale").unwrap_or_else(|| math::Vector3::repeat(1.0)),
+            imu_to_head: math::Matrix3::from_columns(&[plus_x, plus_z.cross(&plus_x), plus_z]),
+        }
+    }
+
+    /// Converts a raw sample into sensor fusion input.
+    fn imu_sample(&self, sample: &RawSample) -> fusion::ImuSample {
+        let scaled = |raw: [i16; 3], range: math::Scalar, scale: &math::Vector3, bias: &math::Vector3| {
+            let raw = math::Vector3::new(raw[0] as math::Scalar, raw[1] as math::Scalar, raw[2] as math::Scalar);
+            canonical(self.imu_to_head * (raw.component_mul(scale) * (range / 32768.0) - bias))
+        };
+
+        fusion::ImuSample {
+            gyroscope: scaled(sample.gyroscope, GYROSCOPE_RANGE, &self.gyroscope_scale, &self.gyroscope_bias),
+            accelerometer: scaled(sample.accelerometer, ACCELEROMETER_RANGE, &self.accelerometer_scale, &self.accelerometer_bias) / math::STANDARD_GRAVITY,
+        }
+    }
+}
+
+/// Reads the configuration from the headset.
+///
+/// The configuration is read in chunks of up to 62 bytes, each prefixed
+/// by its length. An empty chunk ends it.
+fn read_config(device: &mut dyn hid::Device) -> Result<String, Error> {
+    let mut buf = [0; CONFIG_REPORT_LENGTH];
+    buf[0] = CONFIG_START_REPORT_ID;
+    device.get_feature_report(&mut buf)?;
+
+    let mut compressed = Vec::new();
+    loop {
+        buf[0] = CONFIG_READ_REPORT_ID;
+        let length = device.get_feature_report(&mut buf)?;
+
+        let chunk_length = buf[1] as usize;
+        if length < 2 || 2 + chunk_length > length {
+            return Err(Error::communication_error(format!("malformed configuration report of {} bytes", length)));
+        }
+        if chunk_length == 0 {
+            break;
+        }
+        if compressed.len() + chunk_length > MAX_CONFIG_LENGTH {
+            return Err(Error::communication_error("the configuration is too long"));
+        }
+
+        compressed.extend_from_slice(&buf[2..2 + chunk_length]);
+    }
+
+    let config = miniz_oxide::inflate::decompress_to_vec_zlib(&compressed).map_err(Error::communication_error)?;
+    String::from_utf8(config).map_err(Error::communication_error)
+}
+
+/// Decodes an IMU report, including its report ID.
+fn decode_imu_report(buf: &[u8]) -> Result<[RawSample; SAMPLES_PER_REPORT], Error> {
+    if buf.len() < IMU_REPORT_LENGTH || buf[0] != IMU_REPORT_ID {
+        return Err(Error::communication_error(format!("malformed IMU report of {} bytes", buf.len())));
+    }
+
+    let mut samples = [RawSample::default(); SAMPLES_PER_REPORT];
+    for (i, sample) in samples.iter_mut().enumerate() {
+        let at = 1 + i * SAMPLE_LENGTH;
+        let i16_at = |offset: usize| i16::from_le_bytes([buf[at + offset], buf[at + offset + 1]]);
+
+        *sample = RawSample {
+            accelerometer: [i16_at(0), i16_at(2), i16_at(4)],
+            gyroscope: [i16_at(6), i16_at(8), i16_at(10)],
+            // The last byte is an 8-bit sequence number, which the ticks
+            // make redundant.
+            ticks: u32::from_le_bytes([buf[at + 12], buf[at + 13], buf[at + 14], buf[at + 15]]),
+        };
+    }
+
+    Ok(samples)
+}
+
+/// Builds the headset properties from the configuration.
+///
+/// The configuration describes each eye's lens. The display of the model
+/// is assumed for anything missing.
+fn vive_properties(config: &Value, is_pro: bool) -> info::Properties {
+    let (default_width, default_height) = if is_pro { (1440, 1600) } else { (1080, 1200) };
+    let device = config.get("device");
+    let pixels = |name: &str, default: u32| device.and_then(|device| device.get(name)).and_then(Value::as_u64).map_or(default, |pixels| pixels as u32);
+    let resolution = (pixels("eye_target_width_in_pixels", default_width), pixels("eye_target_height_in_pixels", default_height));
+
+    let eyes = config.get("tracking_to_eye_transform").and_then(Value::as_array);
+    let lens = |eye: usize| vive_lens(eyes.and_then(|eyes| eyes.get(eye)), resolution);
+
+    let lens_separation = config.get("lens_separation").and_then(Value::as_f64).unwrap_or(DEFAULT_LENS_SEPARATION_METRES);
+
+    let visuals = info::Visuals::LensBased {
+        left: lens(0), right: lens(1),
+        lens_separation: info::Distance {
+            micrometers: (lens_separation * 1e6).round() as u64,
+        },
+        lens_to_eye_distance: info::Distance {
+            micrometers: 15_000, // 15 millimeters, with the default eye relief.
+        },
+        screen_to_lens_distance: info::Distance {
+            micrometers: 40_000, // 40 millimeters
+        },
+    };
+
+    info::Properties {
+        display_info: info::DisplayInfo {
+            monitor_name: if is_pro { "VIVE Pro" } else { "HTC VIVE" }.to_owned(),
+            physical_size_millimeters: None,
+            supported_resolutions: vec![
+                (resolution.0 * 2, resolution.1),
+            ],
+        },
+        visuals
+    }
+}
+
+/// Builds a lens from an eye of the configuration.
+///
+/// Each colour has its own distortion coefficients. The green ones are
+/// used for the lens, and the red and blue factors are their magnification
+/// relative to green at the edge of the lens.
+fn vive_lens(eye: Option<&Value>, resolution: (u32, u32)) -> info::Lens {
+    let coefficients = |name: &str| -> Vec<math::Scalar> {
+        eye.and_then(|eye| eye.get(name)).and_then(|distortion| distortion.get("coeffs")).and_then(Value::as_array)
+            .map(|coefficients| coefficients.iter().filter_map(Value::as_f64).map(|k| k as math::Scalar).collect())
+            .unwrap_or_default()
+    };
+    let edge_magnification = |coefficients: &[math::Scalar]| 1.0 + coefficients.iter().sum::<math::Scalar>();
+
+    let green = coefficients("distortion");
+    let relative = |name: &str| {
+        let coefficients = coefficients(name);
+        if coefficients.is_empty() { 1.0 } else { edge_magnification(&coefficients) / edge_magnification(&green) }
+    };
+
+    // The intrinsics project the eye's view onto -1..1 in each axis.
+    let intrinsics = eye.and_then(|eye| eye.get("intrinsics")).and_then(Value::as_array);
+    let intrinsic = |row: usize, column: usize| intrinsics.and_then(|rows| rows.get(row)).and_then(Value::as_array)
+        .and_then(|row| row.get(column)).and_then(Value::as_f64);
+    let field_of_view = |axis: usize, default: math::Scalar| -> math::Scalar {
+        match (intrinsic(axis, axis), intrinsic(axis, 2)) {
+            (Some(focal), Some(center)) if focal > 0.0 => (((1.0 - center) / focal).atan() + ((1.0 + center) / focal).atan()).to_degrees() as math::Scalar,
+            _ => default,
+        }
+    };
+    let axis = |degrees: math::Scalar| info::FieldOfViewAxis {
+        minimum_degrees: degrees,
+        maximum_degrees: degrees,
+        recommended_degrees: degrees,
+    };
+
+    info::Lens {
+        resolution,
+        field_of_view: info::FieldOfView {
+            horizontal: axis(field_of_view(0, 100.0)),
+            vertical: axis(field_of_view(1, 110.0)),
+        },
+        chromatic_aberration_factors: info::ChromaticAberrationFactors {
+            red: relative("distortion_red"),
+            green: 1.0,
+            blue: relative("distortion_blue"),
+        },
+        distortion_coefficients: green,
+    }
+}
+
+/// Reads a JSON array of three numbers.
+fn json_vector(value: &Value) -> Option<math::Vector3> {
+    let array = value.as_array()?;
+    let component = |i: usize| array.get(i).and_then(Value::as_f64).map(|x| x as math::Scalar);
+
+    Some(math::Vector3::new(component(0)?, component(1)?, component(2)?))
+}
+
+/// Converts a vector from the headset frame (X right, Y up, Z backward) to
+/// the canonical frame.
+fn canonical(v: math::Vector3) -> math::Vector3 {
+    math::Vector3::new(-v.z, -v.x, v.y)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::hid::mock;
+
+    /// The parts of a Vive Pro configuration this backend reads, with
+    /// made-up values.
+    const VIVE_PRO_CONFIG: &str = r#"{
+        "device_class": "hmd",
+        "model_number": "VIVE_Pro MV",
+        "device": {
+            "eye_target_height_in_pixels": 1600,
+            "eye_target_width_in_pixels": 1440,
+            "first_eye": "eEYE_LEFT"
+        },
+        "imu": {
+            "acc_bias": [0.12, -0.05, 0.21],
+            "acc_scale": [1.001, 0.998, 1.002],
+            "gyro_bias": [0.002, -0.001, 0.003],
+            "gyro_scale": [1.0, 1.0, 1.0],
+            "plus_x": [1.0, 0.0, 0.0],
+            "plus_z": [0.0, 0.0, 1.0]
+        },
+        "lens_separation": 0.0636,
+        "tracking_to_eye_transform": [
+            {
+                "distortion": { "center_x": 0.0, "center_y": 0.0, "coeffs": [0.22, 0.11, 0.05], "type": "DISTORT_DPOLY3" },
+                "distortion_red": { "center_x": 0.0, "center_y": 0.0, "coeffs": [0.20, 0.10, 0.04], "type": "DISTORT_DPOLY3" },
+                "distortion_blue": { "center_x": 0.0, "center_y": 0.0, "coeffs": [0.25, 0.12, 0.06], "type": "DISTORT_DPOLY3" },
+                "intrinsics": [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, -1.0]]
+            },
+            {
+                "distortion": { "center_x": 0.0, "center_y": 0.0, "coeffs": [0.22, 0.11, 0.05], "type": "DISTORT_DPOLY3" },
+                "distortion_red": { "center_x": 0.0, "center_y": 0.0, "coeffs": [0.20, 0.10, 0.04], "type": "DISTORT_DPOLY3" },
+                "distortion_blue": { "center_x": 0.0, "center_y": 0.0, "coeffs": [0.25, 0.12, 0.06], "type": "DISTORT_DPOLY3" },
+                "intrinsics": [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, -1.0]]
+            }
+        ]
+    }"#;
+
+    /// A hand-built IMU report for a level, still headset, holding samples
+    /// 0x41 to 0x43 at 1ms apart.
+    const RESTING_REPORT: [u8; IMU_REPORT_LENGTH] = [
+        0x20,
+        0x00, 0x00, 0xd3, 0x1f, 0x00, 0x00, 0x01, 0x00, 0xff, 0xff, 0x00, 0x00, 0x00, 0x1b, 0xb7, 0x00, 0x41,
+        0x00, 0x00, 0xd6, 0x1f, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0x01, 0x00, 0x80, 0xd6, 0xb7, 0x00, 0x42,
+        0x00, 0x00, 0xd1, 0x1f, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x92, 0xb8, 0x00, 0x43,
+    ];
+
+    /// Splits a configuration into the feature reports the headset sends.
+    fn config_reports(config: &str) -> Vec<Vec<u8>> {
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(config.as_bytes(), 6);
+
+        let mut reports: Vec<_> = compressed.chunks(CONFIG_REPORT_LENGTH - 2).map(|chunk| {
+            let mut report = vec![CONFIG_READ_REPORT_ID, chunk.len() as u8];
+            report.extend_from_slice(chunk);
+            report
+        }).collect();
+        reports.push(vec![CONFIG_READ_REPORT_ID, 0]);
+        reports
+    }
+
+    /// Copies `RESTING_REPORT`, with samples from `sequence` taken from
+    /// `ticks` on.
+    fn report_at(sequence: u8, ticks: u32) -> [u8; IMU_REPORT_LENGTH] {
+        let mut report = RESTING_REPORT;
+        for i in 0..SAMPLES_PER_REPORT {
+            let at = 1 + i * SAMPLE_LENGTH;
+            report[at + 12..at + 16].copy_from_slice(&ticks.wrapping_add(i as u32 * 48_000).to_le_bytes());
+            report[at + 16] = sequence.wrapping_add(i as u8);
+        }
+        report
+    }
+
+    fn connect(config: &str) -> (mock::Device, Vive) {
+        let device = mock::Device::new();
+        device.push_feature(&[CONFIG_START_REPORT_ID]);
+        for report in config_reports(config) {
+            device.push_feature(&report);
+        }
+
+        let vive = Vive::new(device.clone()).unwrap();
+        (device, vive)
+    }
+
+    #[test]
+    fn decodes_imu_report() {
+        let samples = decode_imu_report(&RESTING_REPORT).unwrap();
+
+        assert_eq!(RawSample { accelerometer: [0, 8147, 0], gyroscope: [1, -1, 0], ticks: 12_000_000 }, samples[0]);
+        assert_eq!(48_000, samples[1].ticks - samples[0].ticks);
+        assert!(decode_imu_report(&RESTING_REPORT[..40]).is_err());
+    }
+
+    #[test]
+    fn reads_config_from_the_headset() {
+        let (_, vive) = connect(VIVE_PRO_CONFIG);
+
+        assert!(vive.is_pro());
+        assert_eq!("HTC Vive Pro", vive.product_name());
+        assert_eq!(VIVE_PRO_CONFIG, vive.config());
+        assert_eq!(math::Vector3::new(0.12, -0.05, 0.21), vive.calibration.accelerometer_bias);
+    }
+
+    #[test]
+    fn properties_come_from_the_config() {
+        let (_, vive) = connect(VIVE_PRO_CONFIG);
+        let properties = vive.properties();
+
+        assert_eq!(vec![(2880, 1600)], properties.display_info.supported_resolutions);
+        let info::Visuals::LensBased { ref left, lens_separation, .. } = properties.visuals;
+        assert_eq!(63_600, lens_separation.micrometers());
+        assert_eq!((1440, 1600), left.resolution);
+        assert_eq!(vec![0.22, 0.11, 0.05], left.distortion_coefficients);
+        assert!(left.chromatic_aberration_factors.red < 1.0 && left.chromatic_aberration_factors.blue > 1.0);
+        assert!((left.field_of_view.horizontal.recommended_degrees - 90.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn missing_config_fields_are_left_uncorrected() {
+        let (_, vive) = connect(r#"{ "model_number": "Vive MV" }"#);
+
+        assert_eq!("HTC Vive", vive.product_name());
+        assert_eq!(math::Matrix3::identity(), vive.calibration.imu_to_head);
+        assert_eq!(vec![(2160, 1200)], vive.properties().display_info.supported_resolutions);
+    }
+
+    #[test]
+    fn uses_each_sample_once() {
+        let (device, mut vive) = connect(VIVE_PRO_CONFIG);
+
+        device.push_input(&[0x21; 64]);
+        device.push_input(&RESTING_REPORT);
+        vive.update().unwrap();
+        assert_eq!(Some(12_096_000), vive.clock.map(|clock| clock.last_ticks));
+        assert_eq!(3, vive.fusion.history().count());
+
+        // The next report repeats two of the samples.
+        let mut next = RESTING_REPORT;
+        next[1..1 + SAMPLE_LENGTH].copy_from_slice(&RESTING_REPORT[1 + SAMPLE_LENGTH..1 + 2 * SAMPLE_LENGTH]);
+        next[1 + SAMPLE_LENGTH..1 + 2 * SAMPLE_LENGTH].copy_from_slice(&RESTING_REPORT[1 + 2 * SAMPLE_LENGTH..]);
+        next[1 + 2 * SAMPLE_LENGTH..].copy_from_slice(&RESTING_REPORT[1..1 + SAMPLE_LENGTH]);
+        next[IMU_REPORT_LENGTH - 5..IMU_REPORT_LENGTH - 1].copy_from_slice(&12_144_000u32.to_le_bytes());
+        next[IMU_REPORT_LENGTH - 1] = 0x44;
+        device.push_input(&next);
+
+        assert_eq!(1, vive.update_all().unwrap());
+        assert_eq!(Some(12_144_000), vive.clock.map(|clock| clock.last_ticks));
+        assert_eq!(4, vive.fusion.history().count());
+
+        let history: Vec<_> = vive.fusion.history().map(|sample| sample.timestamp).collect();
+        assert_eq!(Duration::from_millis(1), history[3] - history[2]);
+        assert_eq!(history[3] - vive.opened_at, vive.pose_timestamp());
+    }
+
+    #[test]
+    fn uses_samples_after_a_stall() {
+        let (device, mut vive) = connect(VIVE_PRO_CONFIG);
+        device.push_input(&RESTING_REPORT);
+        vive.update().unwrap();
+
+        // 200 samples were dropped, so the sequence numbers look older.
+        device.push_input(&report_at(0x43u8.wrapping_add(201), 12_096_000 + 201 * 48_000));
+        vive.update().unwrap();
+
+        assert_eq!(6, vive.fusion.history().count());
+        let history: Vec<_> = vive.fusion.history().map(|sample| sample.timestamp).collect();
+        assert_eq!(Duration::from_millis(201), history[3] - history[2]);
+        assert_eq!(history[5] - vive.opened_at, vive.pose_timestamp());
+    }
+
+    #[test]
+    fn resynchronises_when_every_sample_looks_stale() {
+        let (device, mut vive) = connect(VIVE_PRO_CONFIG);
+        device.push_input(&RESTING_REPORT);
+        device.push_input(&RESTING_REPORT);
+        vive.update_all().unwrap();
+        assert_eq!(3, vive.fusion.history().count());
+
+        // Over half the tick counter's range later, the samples look older.
+        let ticks = 12_000_000u32.wrapping_sub(48_000_000);
+        device.push_input(&report_at(0x00, ticks));
+        vive.update().unwrap();
+
+        assert_eq!(6, vive.fusion.history().count());
+        assert_eq!(Some(ticks + 96_000), vive.clock.map(|clock| clock.last_ticks));
+    }
+
+    #[test]
+    fn resting_headset_reads_gravity_up() {
+        let (_, vive) = connect(r#"{ "model_number": "Vive MV" }"#);
+        let sample = vive.calibration.imu_sample(&decode_imu_report(&RESTING_REPORT).unwrap()[0]);
+
+        assert!((sample.accelerometer - math::Vector3::z()).norm() < 0.01, "accelerometer read {:?}", sample.accelerometer);
+        assert!(sample.gyroscope.norm() < 0.01);
+    }
+}
@@ -0,0 +1,158 @@
+//! Plain HID devices, for backends that talk to a headset with HID reports.
+
+use crate::Error;
+#[cfg(feature = "hidapi")] use hidapi;
+
+/// An open HID interface of a headset.
+pub trait Device : Send {
+    /// Writes an output report to the interface.
+    ///
+    /// Returns the number of bytes written.
+    fn write(&mut self, data: &[u8]) -> Result<usize, Error>;
+
+    /// Reads an input report from the interface.
+    ///
+    /// Waits at most `timeout_milliseconds` for a report to arrive, or
+    /// forever if the timeout is negative. Returns the number of bytes read,
+    /// which is zero if no report arrived in time.
+    fn read_timeout(&mut self, buf: &mut [u8], timeout_milliseconds: i32) -> Result<usize, Error>;
+
+    /// Sends a feature report. The first byte is the report ID.
+    fn send_feature_report(&mut self, data: &[u8]) -> Result<(), Error>;
+
+    /// Gets a feature report.
+    ///
+    /// The first byte of `buf` must be set to the report ID. Returns the
+    /// number of bytes read, including the report ID.
+    fn get_feature_report(&mut self, buf: &mut [u8]) -> Result<usize, Error>;
+}
+
+#[cfg(feature = "hidapi")]
+impl Device for hidapi::HidDevice {
+    fn write(&mut self, data: &[u8]) -> Result<usize, Error> {
+        hidapi::HidDevice::write(self, data).map_err(Error::communication_error)
+    }
+
+    fn read_timeout(&mut self, buf: &mut [u8], timeout_milliseconds: i32) -> Result<usize, Error> {
+        hidapi::HidDevice::read_timeout(self, buf, timeout_milliseconds).map_err(Error::communication_error)
+    }
+
+    fn send_feature_report(&mut self, data: &[u8]) -> Result<(), Error> {
+        hidapi::HidDevice::send_feature_report(self, data).map_err(Error::communication_error)
+    }
+
+    fn get_feature_report(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        hidapi::HidDevice::get_feature_report(self, buf).map_err(Error::communication_error)
+    }
+}
+
+/// Opens every HID interface with the given vendor and product IDs.
+///
+/// Interfaces that can't be opened, such as ones another program has open
+/// exclusively, are skipped.
+#[cfg(all(feature = "hidapi", any(feature = "rift-dk2", feature = "osvr-hdk")))]
+pub(crate) fn open_all(hidapi: &hidapi::HidApi, vendor_id: u16, product_id: u16) -> Vec<hidapi::HidDevice> {
+    open_matching(hidapi, |info| info.vendor_id() == vendor_id && info.product_id() == product_id)
+}
+
+/// Opens a single numbered HID interface of every device with the given
+/// vendor and product IDs.
+///
+/// See `open_all`.
+#[cfg(all(feature = "hidapi", feature = "vive"))]
+pub(crate) fn open_interface(hidapi: &hidapi::HidApi, vendor_id: u16, product_id: u16, interface_number: i32) -> Vec<hidapi::HidDevice> {
+    open_matching(hidapi, |info| info.vendor_id() == vendor_id && info.product_id() == product_id &&
+                  info.interface_number() == interface_number)
+}
+
+#[cfg(all(feature = "hidapi", any(feature = "rift-dk2", feature = "osvr-hdk", feature = "vive")))]
+fn open_matching<F>(hidapi: &hidapi::HidApi, f: F) -> Vec<hidapi::HidDevice>
+    where F: Fn(&hidapi::DeviceInfo) -> bool {
+    hidapi.device_list()
+        .filter(|info| f(info))
+        .filter_map(|info| info.open_device(hidapi).ok())
+        .collect()
+}
+
+/// A fake HID device that plays back reports, for testing backends.
+#[cfg(all(test, any(feature = "rift-dk2", feature = "osvr-hdk", feature = "vive")))]
+pub(crate) mod mock {
+    use crate::Error;
+
+    use std::collections::{BTreeMap, VecDeque};
+    use std::sync::{Arc, Mutex, MutexGuard};
+
+    /// A fake HID device.
+    ///
+    /// Clones share the same state, so a test can keep one to look at what
+    /// a backend sent.
+    #[derive(Clone, Debug, Default)]
+    pub struct Device {
+        state: Arc<Mutex<State>>,
+    }
+
+    /// What the fake device has been given and has sent.
+    #[derive(Debug, Default)]
+    pub struct State {
+        /// Input reports waiting to be read.
+        pub input: VecDeque<Vec<u8>>,
+        /// Feature reports waiting to be got, by report ID.
+        pub features: BTreeMap<u8, VecDeque<Vec<u8>>>,
+        /// Every output report written.
+        pub written: Vec<Vec<u8>>,
+        /// Every feature report sent.
+        pub sent_features: Vec<Vec<u8>>,
+    }
+
+    impl Device {
+        /// Creates a device with no reports.
+        pub fn new() -> Self { Device::default() }
+
+        /// Queues an input report.
+        pub fn push_input(&self, report: &[u8]) {
+            self.state().input.push_back(report.to_owned());
+        }
+
+        /// Queues a feature report to be got. The first byte is the report ID.
+        #[cfg(feature = "vive")]
+        pub fn push_feature(&self, report: &[u8]) {
+            self.state().features.entry(report[0]).or_default().push_back(report.to_owned());
+        }
+
+        /// Gets the state of the device.
+        pub fn state(&self) -> MutexGuard<'_, State> {
+            self.state.lock().unwrap()
+        }
+    }
+
+    impl super::Device for Device {
+        fn write(&mut self, data: &[u8]) -> Result<usize, Error> {
+            self.state().written.push(data.to_owned());
+            Ok(data.len())
+        }
+
+        fn read_timeout(&mut self, buf: &mut [u8], _: i32) -> Result<usize, Error> {
+            match self.state().input.pop_front() {
+                Some(report) => {
+                    let length = report.len().min(buf.len());
+                    buf[..length].copy_from_slice(&report[..length]);
+                    Ok(length)
+                },
+                None => Ok(0),
+            }
+        }
+
+        fn send_feature_report(&mut self, data: &[u8]) -> Result<(), Error> {
+            self.state().sent_features.push(data.to_owned());
+            Ok(())
+        }
+
+        fn get_feature_report(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+            let report = self.state().features.get_mut(&buf[0]).and_then(VecDeque::pop_front)
+                .ok_or_else(|| Error::communication_error(format!("no feature report 0x{:02x}", buf[0])))?;
+            let length = report.len().min(buf.len());
+            buf[..length].copy_from_slice(&report[..length]);
+            Ok(length)
+        }
+    }
+}
@@ -3,6 +3,16 @@
 #[cfg(feature = "psvr")] mod backend_psvr;
 #[cfg(feature = "psvr")] pub use self::backend_psvr::Psvr;
 #[cfg(feature = "psvr")] pub use psvr;
+mod backend_replay;
+pub use self::backend_replay::{Replay, Timing};
+#[cfg(test)] pub(crate) use self::backend_replay::test::properties as test_properties;
+#[cfg(feature = "osvr-hdk")] mod backend_osvr_hdk;
+#[cfg(feature = "osvr-hdk")] pub use self::backend_osvr_hdk::OsvrHdk;
+#[cfg(feature = "rift-dk2")] mod backend_rift_dk2;
+#[cfg(feature = "rift-dk2")] pub use self::backend_rift_dk2::RiftDk2;
+#[cfg(feature = "vive")] mod backend_vive;
+#[cfg(feature = "vive")] pub use self::backend_vive::Vive;
+pub mod hid;
 
 use crate::{core::math, info, input, Error};
 
@@ -12,11 +22,66 @@ pub trait HeadMountedDevice {
     fn product_name(&self) -> &'static str;
 
     /// Gets the orientation of the headset.
-    fn orientation(&self) -> math::Quaternion;
+    fn orientation(&self) -> math::Orientation;
+
+    /// Gets the orientation of the headset in a given axis convention.
+    fn orientation_in(&self, convention: math::Convention) -> math::Orientation {
+        convention.orientation(self.orientation())
+    }
+
+    /// Gets the pose of the headset, as of its last update.
+    ///
+    /// Headsets that only track orientation place the eyes with a
+    /// `math::NeckModel`, which is what this does unless overridden.
+    fn pose(&self) -> math::Pose {
+        math::Pose::from_neck_model(self.orientation(), &math::NeckModel::default(), self.pose_timestamp())
+    }
+
+    /// Gets when the headset was last updated, for timing its pose.
+    ///
+    /// This is measured from when the headset was opened, or from the start
+    /// of a recording.
+    fn pose_timestamp(&self) -> std::time::Duration;
+
+    /// Gets the pose of the headset in a given axis convention.
+    fn pose_in(&self, convention: math::Convention) -> math::Pose {
+        convention.pose(self.pose())
+    }
+
+    /// Gets the angular velocity of the headset, in radians per second.
+    ///
+    /// Returns `None` if the headset can't measure it.
+    fn angular_velocity(&self, _frame: math::Frame) -> Option<math::Vector3> {
+        None
+    }
+
+    /// Gets the acceleration of the headset with gravity removed, in metres
+    /// per second squared.
+    ///
+    /// Returns `None` if the headset can't measure it.
+    fn linear_acceleration(&self, _frame: math::Frame) -> Option<math::Vector3> {
+        None
+    }
+
+    /// Gets the orientation of the headset at a point in time.
+    ///
+    /// This can be used for late-latching, or for lining up head movement
+    /// with video captured by another device.
+    ///
+    /// Returns `None` if the headset doesn't keep a history of its
+    /// orientation, or if the time is further back than the history goes.
+    fn orientation_at(&self, _time: std::time::Instant) -> Option<math::Orientation> {
+        None
+    }
 
     /// Gets the state of a button.
     fn button(&self, button: input::Button) -> input::ButtonState;
 
+    /// Gets what the headset reports about itself, as of its last update.
+    fn status(&self) -> info::Status {
+        info::Status::default()
+    }
+
     /// Get information about the headset.
     fn properties(&self) -> &info::Properties;
 
@@ -25,6 +90,18 @@ pub trait HeadMountedDevice {
     /// This should be called often.
     fn update(&mut self) -> Result<(), Error>;
 
+    /// Updates the headset state with every sensor report waiting to be
+    /// processed.
+    ///
+    /// Use this instead of `update` when the application may run slower
+    /// than the headset sensors, so that the orientation doesn't lag
+    /// further and further behind.
+    ///
+    /// Returns the number of sensor frames processed.
+    fn update_all(&mut self) -> Result<usize, Error> {
+        self.update().map(|()| 1)
+    }
+
     /// Powers on the headset.
     ///
     /// **Contract**: If the device is already on, nothing should happen.
@@ -1,23 +1,29 @@
 use crate::Error;
-use hidapi;
+#[cfg(feature = "hidapi")] use hidapi;
 
 /// Provides access to system resources.
 pub struct Context {
+    #[cfg(feature = "hidapi")]
     hidapi: hidapi::HidApi,
 }
 
 impl Context {
     /// Creates a new context.
     pub fn new() -> Result<Self, Error> {
-        let mut hidapi = hidapi::HidApi::new().map_err(Error::communication_error)?;
-        hidapi.refresh_devices().map_err(Error::communication_error)?;
+        #[cfg(feature = "hidapi")]
+        let hidapi = {
+            let mut hidapi = hidapi::HidApi::new().map_err(Error::communication_error)?;
+            hidapi.refresh_devices().map_err(Error::communication_error)?;
+            hidapi
+        };
 
         Ok(Context {
+            #[cfg(feature = "hidapi")]
             hidapi,
         })
     }
 
     /// Gets the HIDAPI context.
+    #[cfg(feature = "hidapi")]
     pub(crate) fn hidapi(&self) -> &hidapi::HidApi { &self.hidapi }
 }
-
@@ -1,15 +1,60 @@
 use crate::{Context, Error, Headset};
-use psvr;
+#[cfg(all(feature = "hidapi", any(feature = "rift-dk2", feature = "osvr-hdk", feature = "vive")))] use crate::backend;
+#[cfg(all(feature = "psvr", any(feature = "hidapi", all(feature = "hidraw", target_os = "linux"))))] use psvr;
 
 /// Gets an iterator over all connected headsets.
-pub fn headsets(context: &Context) -> Result<::std::vec::IntoIter<Headset>, Error> {
+///
+/// Headsets are found with HIDAPI if the `hidapi` feature is enabled, and
+/// otherwise through hidraw if the `hidraw` feature is. Only PSVRs can be
+/// found through hidraw.
+pub fn headsets(context: &Context) -> Result<::std::vec::IntoIter<Headset<'_>>, Error> {
     let mut headsets = Vec::new();
+    headsets.extend(psvrs(context)?);
+    headsets.extend(hid_headsets(context)?);
 
-    for psvr in psvr::iter(context.hidapi())? {
-        let psvr = psvr?;
-        headsets.push(Headset::Psvr(psvr.into()));
+    Ok(headsets.into_iter())
+}
+
+#[cfg(all(feature = "psvr", feature = "hidapi"))]
+fn psvrs(context: &Context) -> Result<Vec<Headset<'_>>, Error> {
+    psvr::iter(context.hidapi())?.map(|psvr| Ok(Headset::Psvr(psvr?.into()))).collect()
+}
+
+#[cfg(all(feature = "psvr", not(feature = "hidapi"), feature = "hidraw", target_os = "linux"))]
+fn psvrs(_: &Context) -> Result<Vec<Headset<'_>>, Error> {
+    psvr::hidraw::Sysfs::new().psvrs()?.iter().map(|nodes| Ok(Headset::Psvr(nodes.connect()?.into()))).collect()
+}
+
+#[cfg(not(all(feature = "psvr", any(feature = "hidapi", all(feature = "hidraw", target_os = "linux")))))]
+fn psvrs(_: &Context) -> Result<Vec<Headset<'_>>, Error> {
+    Ok(Vec::new())
+}
+
+#[cfg(all(feature = "hidapi", any(feature = "rift-dk2", feature = "osvr-hdk", feature = "vive")))]
+fn hid_headsets(context: &Context) -> Result<Vec<Headset<'_>>, Error> {
+    let mut headsets = Vec::new();
+
+    // A headset that can't be opened or set up is skipped, rather than
+    // hiding every other headset.
+    #[cfg(feature = "rift-dk2")]
+    for device in backend::hid::open_all(context.hidapi(), backend::RiftDk2::VENDOR_ID, backend::RiftDk2::PRODUCT_ID) {
+        headsets.extend(backend::RiftDk2::new(device).ok().map(Headset::RiftDk2));
+    }
+    #[cfg(feature = "osvr-hdk")]
+    for device in backend::hid::open_all(context.hidapi(), backend::OsvrHdk::VENDOR_ID, backend::OsvrHdk::PRODUCT_ID) {
+        headsets.push(Headset::OsvrHdk(backend::OsvrHdk::new(device)));
+    }
+    #[cfg(feature = "vive")]
+    for &product_id in &[backend::Vive::PRODUCT_ID, backend::Vive::PRO_PRODUCT_ID] {
+        for device in backend::hid::open_interface(context.hidapi(), backend::Vive::VENDOR_ID, product_id, backend::Vive::IMU_INTERFACE) {
+            headsets.extend(backend::Vive::new(device).ok().map(Headset::Vive));
+        }
     }
 
-    Ok(headsets.into_iter())
+    Ok(headsets)
 }
 
+#[cfg(not(all(feature = "hidapi", any(feature = "rift-dk2", feature = "osvr-hdk", feature = "vive"))))]
+fn hid_headsets(_: &Context) -> Result<Vec<Headset<'_>>, Error> {
+    Ok(Vec::new())
+}
@@ -2,7 +2,15 @@ use crate::{core::math, backend, info, input, Error};
 
 /// A head mounted device.
 pub enum Headset<'context> {
+    #[cfg(feature = "psvr")]
     Psvr(backend::Psvr),
+    Replay(backend::Replay),
+    #[cfg(feature = "rift-dk2")]
+    RiftDk2(backend::RiftDk2),
+    #[cfg(feature = "osvr-hdk")]
+    OsvrHdk(backend::OsvrHdk),
+    #[cfg(feature = "vive")]
+    Vive(backend::Vive),
     #[doc(hidden)]
     Phantom(std::marker::PhantomData<&'context ()>),
 }
@@ -10,14 +18,30 @@ pub enum Headset<'context> {
 macro_rules! dispatch {
     { $self:expr => $method:ident ( $( $arg:expr ),* ) } => {
         match *$self {
+            #[cfg(feature = "psvr")]
             Headset::Psvr(ref psvr) => psvr . $method ( $( $arg ),* ),
+            Headset::Replay(ref replay) => replay . $method ( $( $arg ),* ),
+            #[cfg(feature = "rift-dk2")]
+            Headset::RiftDk2(ref rift) => rift . $method ( $( $arg ),* ),
+            #[cfg(feature = "osvr-hdk")]
+            Headset::OsvrHdk(ref hdk) => hdk . $method ( $( $arg ),* ),
+            #[cfg(feature = "vive")]
+            Headset::Vive(ref vive) => vive . $method ( $( $arg ),* ),
             Headset::Phantom(..) => unreachable!(),
         }
     };
 
     { mut $self:expr => $method:ident ( $( $arg:expr ),* ) } => {
         match *$self {
+            #[cfg(feature = "psvr")]
             Headset::Psvr(ref mut psvr) => psvr . $method ( $( $arg ),* ),
+            Headset::Replay(ref mut replay) => replay . $method ( $( $arg ),* ),
+            #[cfg(feature = "rift-dk2")]
+            Headset::RiftDk2(ref mut rift) => rift . $method ( $( $arg ),* ),
+            #[cfg(feature = "osvr-hdk")]
+            Headset::OsvrHdk(ref mut hdk) => hdk . $method ( $( $arg ),* ),
+            #[cfg(feature = "vive")]
+            Headset::Vive(ref mut vive) => vive . $method ( $( $arg ),* ),
             Headset::Phantom(..) => unreachable!(),
         }
     };
@@ -28,14 +52,38 @@ impl<'context> backend::HeadMountedDevice for Headset<'context> {
         dispatch! { self => product_name() }
     }
 
-    fn orientation(&self) -> math::Quaternion {
+    fn orientation(&self) -> math::Orientation {
         dispatch! { self => orientation() }
     }
 
+    fn pose(&self) -> math::Pose {
+        dispatch! { self => pose() }
+    }
+
+    fn pose_timestamp(&self) -> std::time::Duration {
+        dispatch! { self => pose_timestamp() }
+    }
+
+    fn angular_velocity(&self, frame: math::Frame) -> Option<math::Vector3> {
+        dispatch! { self => angular_velocity(frame) }
+    }
+
+    fn linear_acceleration(&self, frame: math::Frame) -> Option<math::Vector3> {
+        dispatch! { self => linear_acceleration(frame) }
+    }
+
+    fn orientation_at(&self, time: std::time::Instant) -> Option<math::Orientation> {
+        dispatch! { self => orientation_at(time) }
+    }
+
     fn button(&self, button: input::Button) -> input::ButtonState {
         dispatch! { self => button(button) }
     }
 
+    fn status(&self) -> info::Status {
+        dispatch! { self => status() }
+    }
+
     fn properties(&self) -> &info::Properties {
         dispatch! { self => properties() }
     }
@@ -44,6 +92,10 @@ impl<'context> backend::HeadMountedDevice for Headset<'context> {
         dispatch! { mut self => update() }
     }
 
+    fn update_all(&mut self) -> Result<usize, Error> {
+        dispatch! { mut self => update_all() }
+    }
+
     fn power_on(&mut self) -> Result<(), Error> {
         dispatch! { mut self => power_on() }
     }
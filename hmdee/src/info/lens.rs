@@ -2,6 +2,7 @@ use crate::{core::math, info};
 
 /// An individual lens.
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Lens {
     /// The screen resolution of the lens.
     pub resolution: (u32, u32),
@@ -14,6 +15,8 @@ pub struct Lens {
     /// ```text
     /// p' = p (1 + K1 r^2 + K2 r^4 + ... + Kn r^(2n))
     /// ```
+    ///
+    /// This is empty if the distortion of the lens isn't known.
     pub distortion_coefficients: Vec<math::Scalar>,
     /// Chromatic aberration properties.
     pub chromatic_aberration_factors: ChromaticAberrationFactors,
@@ -21,6 +24,7 @@ pub struct Lens {
 
 /// Chromatic aberration factors.
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ChromaticAberrationFactors {
     pub red: math::Scalar,
     pub green: math::Scalar,
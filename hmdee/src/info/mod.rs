@@ -8,6 +8,7 @@ use crate::core::math;
 
 /// Information about a VR headset.
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Properties {
     /// Information about the headset's visuals.
     pub visuals: Visuals,
@@ -17,6 +18,7 @@ pub struct Properties {
 
 /// Information about the visuals
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Visuals {
     /// A headset with one or more lenses.
     ///
@@ -52,6 +54,7 @@ pub enum Visuals {
 /// `DisplayInfo` with the HMD's `DisplayInfo` returned by this library.
 ///
 #[derive(Clone, Debug, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct DisplayInfo {
     /// The monitor name of the VR headset, as reported by HDMI EDID.
     ///
@@ -76,12 +79,33 @@ pub struct DisplayInfo {
     pub supported_resolutions: Vec<(u32, u32)>,
 }
 
+/// What a headset currently reports about itself, besides tracking and
+/// buttons.
+///
+/// Each field is `None` if the headset doesn't report it.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Status {
+    /// Whether the headset is on someone's head.
+    pub worn: Option<bool>,
+    /// Whether the display is on.
+    pub display_active: Option<bool>,
+    /// Whether the video cable is unplugged.
+    pub hdmi_disconnected: Option<bool>,
+    /// Whether the microphone is muted.
+    pub microphone_muted: Option<bool>,
+    /// Whether headphones are plugged in.
+    pub headphone_connected: Option<bool>,
+}
+
 /// A geometric distance.
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Distance { pub(crate) micrometers: u64 }
 
 /// Information about a field of view.
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct FieldOfView {
     /// The horizontal field of view.
     pub horizontal: FieldOfViewAxis,
@@ -91,6 +115,7 @@ pub struct FieldOfView {
 
 /// Information about a field of view axis.
 #[derive(Clone, Debug, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct FieldOfViewAxis {
     /// The minimum field of view in degrees.
     pub minimum_degrees: math::Scalar,
@@ -174,3 +199,15 @@ fn is_subset(a: &[(u32, u32)], subset_of: &[(u32, u32)]) -> bool {
 
 
 impl Eq for DisplayInfo { }
+
+#[cfg(test)]
+mod test {
+    #[cfg(feature = "serde")]
+    #[test]
+    fn properties_round_trip_through_json() {
+        let properties = crate::backend::test_properties();
+        let json = serde_json::to_string(&properties).unwrap();
+
+        assert_eq!(properties, serde_json::from_str(&json).unwrap());
+    }
+}
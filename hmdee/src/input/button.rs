@@ -1,5 +1,6 @@
 /// A button.
 #[derive(Copy, Clone, Debug, PartialEq, PartialOrd, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Button {
     /// The volume up button.
     VolumeUp,
@@ -10,7 +11,8 @@ pub enum Button {
 }
 
 /// The state of an individual button.
-#[derive(Clone, Debug, PartialEq, PartialOrd, Hash)]
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum ButtonState {
     /// The button is up.
     NotPressed,
@@ -32,3 +34,17 @@ impl ButtonState {
     }
 }
 
+
+#[cfg(test)]
+mod test {
+    #[cfg(feature = "serde")]
+    #[test]
+    fn buttons_round_trip_through_json() {
+        use super::*;
+
+        let states = vec![(Button::VolumeUp, ButtonState::Pressed), (Button::Mute, ButtonState::NotPresent)];
+        let json = serde_json::to_string(&states).unwrap();
+
+        assert_eq!(states, serde_json::from_str::<Vec<(Button, ButtonState)>>(&json).unwrap());
+    }
+}
@@ -1,11 +1,16 @@
 //! hmdee VR library.
 //!
 //! * PSVR
+//! * Oculus Rift DK2
+//! * OSVR HDK
+//! * HTC Vive and Vive Pro, orientation only
+//! * Replays of recordings, see `record`
 
 pub use self::backend::HeadMountedDevice;
 pub use self::context::Context;
 pub use self::headset::Headset;
 pub use self::discover::headsets;
+pub use self::shared::SharedHeadset;
 
 // Show reexported crates like normal modules in Rustdoc.
 pub use self::reexports::{core};
@@ -14,7 +19,10 @@ mod reexports {
     pub extern crate hmdee_core as core;
 }
 
-extern crate hidapi;
+#[cfg(feature = "hidapi")] extern crate hidapi;
+#[cfg(feature = "serde")] #[macro_use] extern crate serde;
+#[cfg(any(feature = "serde", feature = "vive"))] extern crate serde_json;
+#[cfg(feature = "vive")] extern crate miniz_oxide;
 
 // Hide this here because we reexport it inside backend module.
 #[cfg(feature = "psvr")] #[doc(hidden)] pub extern crate psvr;
@@ -25,3 +33,5 @@ mod discover;
 mod headset;
 pub mod info;
 pub mod input;
+pub mod record;
+pub mod shared;
@@ -0,0 +1,347 @@
+//! Recording what an application sees from a headset.
+//!
+//! Unlike `psvr::capture`, which works on raw USB traffic, recordings hold
+//! the tracked state that `HeadMountedDevice` reports. They can be played
+//! back with `backend::Replay`.
+
+use crate::{backend::HeadMountedDevice, core::math, info, input, Error};
+
+use std::io::{BufRead, Write};
+use std::time;
+
+/// The header line of a CSV recording.
+const CSV_HEADER: &str = "time,w,i,j,k,volume_up,volume_down,mute,\
+                          angular_velocity_x,angular_velocity_y,angular_velocity_z,\
+                          linear_acceleration_x,linear_acceleration_y,linear_acceleration_z,\
+                          worn,display_active,hdmi_disconnected,microphone_muted,headphone_connected";
+/// How many fields a CSV sample has, and had before the headset status was
+/// recorded.
+const CSV_FIELDS: usize = 19;
+const CSV_FIELDS_WITHOUT_STATUS: usize = 14;
+
+/// A file format for recordings.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Comma separated values, with a header line.
+    Csv,
+    /// One JSON object per line.
+    #[cfg(feature = "serde")]
+    JsonLines,
+}
+
+/// The state of a headset at one point in a recording.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Sample {
+    /// Seconds since the recording started.
+    pub time: f64,
+    /// The orientation quaternion, as `[w, i, j, k]`.
+    pub orientation: [math::Scalar; 4],
+    /// The state of the buttons.
+    pub buttons: Buttons,
+    /// The angular velocity in the head frame, in radians per second.
+    pub angular_velocity: Option<[math::Scalar; 3]>,
+    /// The acceleration in the head frame with gravity removed, in metres
+    /// per second squared.
+    pub linear_acceleration: Option<[math::Scalar; 3]>,
+    /// What the headset reported about itself.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub status: info::Status,
+}
+
+/// The state of every button.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Buttons {
+    pub volume_up: input::ButtonState,
+    pub volume_down: input::ButtonState,
+    pub mute: input::ButtonState,
+}
+
+/// Writes samples from a headset to a recording.
+pub struct Recorder<W: Write> {
+    writer: W,
+    format: Format,
+    started: time::Instant,
+}
+
+impl Sample {
+    /// Takes a sample of a headset's current state.
+    pub fn capture<H>(hmd: &H, time: f64) -> Self
+        where H: HeadMountedDevice + ?Sized {
+        let quaternion = hmd.orientation().quaternion();
+        let array = |v: math::Vector3| [v.x, v.y, v.z];
+
+        Sample {
+            time,
+            orientation: [quaternion.w, quaternion.i, quaternion.j, quaternion.k],
+            buttons: Buttons {
+                volume_up: hmd.button(input::Button::VolumeUp),
+                volume_down: hmd.button(input::Button::VolumeDown),
+                mute: hmd.button(input::Button::Mute),
+            },
+            angular_velocity: hmd.angular_velocity(math::Frame::Head).map(array),
+            linear_acceleration: hmd.linear_acceleration(math::Frame::Head).map(array),
+            status: hmd.status(),
+        }
+    }
+
+    /// Gets the orientation.
+    ///
+    /// Returns the identity if the recorded quaternion is zero.
+    pub fn orientation(&self) -> math::Orientation {
+        let [w, i, j, k] = self.orientation;
+        math::Orientation::from_quaternion(math::Quaternion::new(w, i, j, k)).unwrap_or_default()
+    }
+
+    /// Gets the state of a button.
+    pub fn button(&self, button: input::Button) -> input::ButtonState {
+        match button {
+            input::Button::VolumeUp => self.buttons.volume_up,
+            input::Button::VolumeDown => self.buttons.volume_down,
+            input::Button::Mute => self.buttons.mute,
+        }
+    }
+
+    fn to_csv(&self) -> String {
+        let vector = |v: Option<[math::Scalar; 3]>| match v {
+            Some([x, y, z]) => format!("{},{},{}", x, y, z),
+            None => ",,".to_owned(),
+        };
+        let flag = |flag: Option<bool>| flag.map(|flag| flag.to_string()).unwrap_or_default();
+        let [w, i, j, k] = self.orientation;
+        let status = &self.status;
+
+        format!("{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}", self.time, w, i, j, k,
+                button_to_csv(&self.buttons.volume_up),
+                button_to_csv(&self.buttons.volume_down),
+                button_to_csv(&self.buttons.mute),
+                vector(self.angular_velocity), vector(self.linear_acceleration),
+                flag(status.worn), flag(status.display_active), flag(status.hdmi_disconnected),
+                flag(status.microphone_muted), flag(status.headphone_connected))
+    }
+
+    fn from_csv(line: &str) -> Option<Self> {
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        if fields.len() != CSV_FIELDS && fields.len() != CSV_FIELDS_WITHOUT_STATUS {
+            return None;
+        }
+
+        let scalar = |field: &str| field.parse::<math::Scalar>().ok();
+        let vector = |fields: &[&str]| -> Option<Option<[math::Scalar; 3]>> {
+            if fields.iter().all(|field| field.is_empty()) {
+                Some(None)
+            } else {
+                Some(Some([scalar(fields[0])?, scalar(fields[1])?, scalar(fields[2])?]))
+            }
+        };
+
+        Some(Sample {
+            time: fields[0].parse().ok().filter(|&time| is_valid_time(time))?,
+            orientation: [scalar(fields[1])?, scalar(fields[2])?, scalar(fields[3])?, scalar(fields[4])?],
+            buttons: Buttons {
+                volume_up: button_from_csv(fields[5])?,
+                volume_down: button_from_csv(fields[6])?,
+                mute: button_from_csv(fields[7])?,
+            },
+            angular_velocity: vector(&fields[8..11])?,
+            linear_acceleration: vector(&fields[11..14])?,
+            status: match fields.get(14..) {
+                Some(status) if !status.is_empty() => info::Status {
+                    worn: flag_from_csv(status[0])?,
+                    display_active: flag_from_csv(status[1])?,
+                    hdmi_disconnected: flag_from_csv(status[2])?,
+                    microphone_muted: flag_from_csv(status[3])?,
+                    headphone_connected: flag_from_csv(status[4])?,
+                },
+                _ => info::Status::default(),
+            },
+        })
+    }
+}
+
+impl<W: Write> Recorder<W> {
+    /// Starts a recording.
+    ///
+    /// Sample times are measured from now.
+    pub fn new(mut writer: W, format: Format) -> Result<Self, Error> {
+        if format == Format::Csv {
+            writeln!(writer, "{}", CSV_HEADER)?;
+        }
+
+        Ok(Recorder { writer, format, started: time::Instant::now() })
+    }
+
+    /// Records the current state of a headset.
+    pub fn record<H>(&mut self, hmd: &H) -> Result<(), Error>
+        where H: HeadMountedDevice + ?Sized {
+        let sample = Sample::capture(hmd, self.started.elapsed().as_secs_f64());
+        self.record_sample(&sample)
+    }
+
+    /// Records a sample.
+    pub fn record_sample(&mut self, sample: &Sample) -> Result<(), Error> {
+        let line = match self.format {
+            Format::Csv => sample.to_csv(),
+            #[cfg(feature = "serde")]
+            Format::JsonLines => serde_json::to_string(sample).map_err(Error::communication_error)?,
+        };
+
+        writeln!(self.writer, "{}", line)?;
+        Ok(())
+    }
+
+    /// Finishes the recording and gets back the writer.
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Reads every sample from a recording.
+pub fn read<R: BufRead>(reader: R, format: Format) -> Result<Vec<Sample>, Error> {
+    let mut samples = Vec::new();
+
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() || (format == Format::Csv && line.starts_with("time,")) {
+            continue;
+        }
+
+        let sample = match format {
+            Format::Csv => Sample::from_csv(&line),
+            #[cfg(feature = "serde")]
+            Format::JsonLines => serde_json::from_str::<Sample>(&line).ok().filter(|sample| is_valid_time(sample.time)),
+        };
+        samples.push(sample.ok_or_else(|| {
+            Error::communication_error(format!("line {} of the recording is not a valid sample", number + 1))
+        })?);
+    }
+
+    Ok(samples)
+}
+
+fn button_to_csv(state: &input::ButtonState) -> &'static str {
+    match *state {
+        input::ButtonState::NotPressed => "NotPressed",
+        input::ButtonState::Pressed => "Pressed",
+        input::ButtonState::NotPresent => "NotPresent",
+    }
+}
+
+fn button_from_csv(field: &str) -> Option<input::ButtonState> {
+    match field {
+        "NotPressed" => Some(input::ButtonState::NotPressed),
+        "Pressed" => Some(input::ButtonState::Pressed),
+        "NotPresent" => Some(input::ButtonState::NotPresent),
+        _ => None,
+    }
+}
+
+/// Checks that a sample time can be played back, which it can't be if it is
+/// negative or not finite.
+fn is_valid_time(time: f64) -> bool {
+    time.is_finite() && time >= 0.0
+}
+
+/// Parses a status flag, which is empty if the headset doesn't report it.
+fn flag_from_csv(field: &str) -> Option<Option<bool>> {
+    match field {
+        "" => Some(None),
+        field => field.parse().ok().map(Some),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::f32::consts::FRAC_1_SQRT_2;
+
+    fn example_samples() -> Vec<Sample> {
+        vec![
+            Sample {
+                time: 0.0,
+                orientation: [1.0, 0.0, 0.0, 0.0],
+                buttons: Buttons {
+                    volume_up: input::ButtonState::NotPressed,
+                    volume_down: input::ButtonState::NotPressed,
+                    mute: input::ButtonState::NotPresent,
+                },
+                angular_velocity: None,
+                linear_acceleration: None,
+                status: info::Status::default(),
+            },
+            Sample {
+                time: 0.016,
+                orientation: [FRAC_1_SQRT_2, 0.0, 0.0, FRAC_1_SQRT_2],
+                buttons: Buttons {
+                    volume_up: input::ButtonState::Pressed,
+                    volume_down: input::ButtonState::NotPressed,
+                    mute: input::ButtonState::NotPresent,
+                },
+                angular_velocity: Some([0.0, 0.0, 1.5]),
+                linear_acceleration: Some([0.25, -0.5, 0.0]),
+                status: info::Status { worn: Some(true), display_active: Some(false), ..info::Status::default() },
+            },
+        ]
+    }
+
+    fn round_trip(format: Format) -> Vec<Sample> {
+        let mut recorder = Recorder::new(Vec::new(), format).unwrap();
+        for sample in example_samples() {
+            recorder.record_sample(&sample).unwrap();
+        }
+        let bytes = recorder.finish().unwrap();
+
+        read(&bytes[..], format).unwrap()
+    }
+
+    #[test]
+    fn csv_round_trips() {
+        assert_eq!(example_samples(), round_trip(Format::Csv));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_lines_round_trip() {
+        assert_eq!(example_samples(), round_trip(Format::JsonLines));
+    }
+
+    #[test]
+    fn reports_bad_lines() {
+        let recording = format!("{}\n0,1,0,0,0,Pressed,NotPressed,Sideways,,,,,,,,,,,\n", CSV_HEADER);
+        assert!(read(recording.as_bytes(), Format::Csv).is_err());
+
+        let recording = format!("{}\n0,1,0,0,0,Pressed,NotPressed,NotPressed,,,,,,,maybe,,,,\n", CSV_HEADER);
+        assert!(read(recording.as_bytes(), Format::Csv).is_err());
+    }
+
+    #[test]
+    fn rejects_times_that_cannot_be_played() {
+        for time in &["-1", "NaN", "inf"] {
+            let recording = format!("{}\n{},1,0,0,0,Pressed,NotPressed,NotPressed,,,,,,,,,,,\n", CSV_HEADER, time);
+            assert!(read(recording.as_bytes(), Format::Csv).is_err(), "{}", time);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_lines_rejects_times_that_cannot_be_played() {
+        let mut sample = example_samples().remove(0);
+        sample.time = -1.0;
+        let line = serde_json::to_string(&sample).unwrap();
+
+        assert!(read(line.as_bytes(), Format::JsonLines).is_err());
+    }
+
+    #[test]
+    fn reads_recordings_without_status() {
+        let recording = "time,w,i,j,k,volume_up,volume_down,mute,\
+                         angular_velocity_x,angular_velocity_y,angular_velocity_z,\
+                         linear_acceleration_x,linear_acceleration_y,linear_acceleration_z\n\
+                         0,1,0,0,0,Pressed,NotPressed,NotPressed,,,,,,\n";
+        let samples = read(recording.as_bytes(), Format::Csv).unwrap();
+
+        assert_eq!(info::Status::default(), samples[0].status);
+    }
+}
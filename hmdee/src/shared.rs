@@ -0,0 +1,350 @@
+//! Sharing a headset between threads.
+//!
+//! One thread owns the headset through an `Owner`, updating it and running
+//! commands. Any number of `SharedHeadset` handles read the latest state
+//! without ever waiting for the owner.
+
+use crate::{backend::HeadMountedDevice, core::math, info, input, record, Error};
+
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{self, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::{fmt, hint, ptr, time};
+
+/// The state of a headset, as last published by its owner.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct State {
+    /// The orientation of the headset.
+    pub orientation: math::Orientation,
+    /// The angular velocity in the head frame, in radians per second.
+    pub angular_velocity: Option<math::Vector3>,
+    /// The acceleration in the head frame with gravity removed, in metres
+    /// per second squared.
+    pub linear_acceleration: Option<math::Vector3>,
+    /// The state of the buttons.
+    pub buttons: record::Buttons,
+    /// The status of the headset, such as whether it is being worn.
+    pub status: info::Status,
+    /// The time of the orientation, from when the headset was opened.
+    ///
+    /// See `HeadMountedDevice::pose_timestamp`.
+    pub pose_timestamp: time::Duration,
+    /// When the state was published, or `None` if it never has been.
+    pub updated_at: Option<time::Instant>,
+}
+
+/// A cloneable handle to a headset owned by another thread.
+pub struct SharedHeadset<H> {
+    state: Arc<SeqLock<State>>,
+    commands: mpsc::Sender<Command<H>>,
+}
+
+/// Owns a shared headset, updating it and running commands sent to it.
+pub struct Owner<H> {
+    hmd: H,
+    state: Arc<SeqLock<State>>,
+    commands: mpsc::Receiver<Command<H>>,
+}
+
+/// The reply to a command that has been sent to the owner.
+pub struct Pending<T> {
+    reply: mpsc::Receiver<Result<T, Error>>,
+}
+
+/// A command to run on the owner's thread.
+type Command<H> = Box<dyn FnOnce(&mut H) + Send>;
+
+/// Shares a headset between threads.
+///
+/// The owner should be updated often, from a single thread. The handle can
+/// be cloned and sent to every thread that needs the headset state.
+pub fn share<H: HeadMountedDevice>(hmd: H) -> (Owner<H>, SharedHeadset<H>) {
+    let state = Arc::new(SeqLock::new(State::capture(&hmd, None)));
+    let (sender, receiver) = mpsc::channel();
+
+    (Owner { hmd, state: state.clone(), commands: receiver },
+     SharedHeadset { state, commands: sender })
+}
+
+impl State {
+    fn capture<H: HeadMountedDevice>(hmd: &H, updated_at: Option<time::Instant>) -> Self {
+        State {
+            orientation: hmd.orientation(),
+            angular_velocity: hmd.angular_velocity(math::Frame::Head),
+            linear_acceleration: hmd.linear_acceleration(math::Frame::Head),
+            buttons: record::Buttons {
+                volume_up: hmd.button(input::Button::VolumeUp),
+                volume_down: hmd.button(input::Button::VolumeDown),
+                mute: hmd.button(input::Button::Mute),
+            },
+            status: hmd.status(),
+            pose_timestamp: hmd.pose_timestamp(),
+            updated_at,
+        }
+    }
+}
+
+impl<H> SharedHeadset<H> {
+    /// Gets the latest state of the headset.
+    ///
+    /// This never blocks. If the owner is publishing at the same moment, the
+    /// read is retried until it sees a whole state.
+    pub fn state(&self) -> State {
+        self.state.read()
+    }
+
+    /// Runs a command on the owner's thread, the next time it updates.
+    ///
+    /// Commands from every handle run one at a time, in the order they
+    /// were sent. This can be used for device-specific commands, such as
+    /// setting the PSVR LEDs.
+    pub fn run<F, T>(&self, command: F) -> Pending<T>
+        where F: FnOnce(&mut H) -> Result<T, Error> + Send + 'static,
+              T: Send + 'static {
+        let (sender, reply) = mpsc::channel();
+
+        // If the owner is gone, the reply sender is dropped with the
+        // command and `Pending::wait` reports it.
+        let _ = self.commands.send(Box::new(move |hmd: &mut H| {
+            let _ = sender.send(command(hmd));
+        }));
+        Pending { reply }
+    }
+}
+
+impl<H: HeadMountedDevice> SharedHeadset<H> {
+    /// Powers on the headset.
+    pub fn power_on(&self) -> Pending<()> {
+        self.run(|hmd: &mut H| hmd.power_on())
+    }
+
+    /// Powers off the headset.
+    pub fn power_off(&self) -> Pending<()> {
+        self.run(|hmd: &mut H| hmd.power_off())
+    }
+}
+
+impl<H> Clone for SharedHeadset<H> {
+    fn clone(&self) -> Self {
+        SharedHeadset { state: self.state.clone(), commands: self.commands.clone() }
+    }
+}
+
+impl<H: HeadMountedDevice> Owner<H> {
+    /// Runs any waiting commands, updates the headset with every waiting
+    /// sensor report, and publishes the new state.
+    ///
+    /// Returns the number of sensor frames processed.
+    pub fn update(&mut self) -> Result<usize, Error> {
+        self.run_commands();
+        let frames = self.hmd.update_all()?;
+        self.publish();
+
+        Ok(frames)
+    }
+
+    /// Runs every command that is waiting, oldest first.
+    pub fn run_commands(&mut self) {
+        while let Ok(command) = self.commands.try_recv() {
+            command(&mut self.hmd);
+        }
+    }
+
+    /// Publishes the current state of the headset to every handle.
+    pub fn publish(&mut self) {
+        // Only the owner writes, and it is borrowed mutably.
+        self.state.write(State::capture(&self.hmd, Some(time::Instant::now())));
+    }
+
+    /// Gets the headset.
+    pub fn headset(&self) -> &H { &self.hmd }
+    /// Gets the headset.
+    pub fn headset_mut(&mut self) -> &mut H { &mut self.hmd }
+
+    /// Stops sharing the headset.
+    ///
+    /// Commands that haven't run yet are dropped, and their senders told so.
+    pub fn into_inner(self) -> H { self.hmd }
+}
+
+impl<T> Pending<T> {
+    /// Waits for the command to run.
+    pub fn wait(self) -> Result<T, Error> {
+        self.reply.recv().unwrap_or_else(|_| Err(owner_gone()))
+    }
+
+    /// Gets the result if the command has run, or gets back the pending
+    /// reply if it hasn't yet.
+    pub fn try_wait(self) -> Result<Result<T, Error>, Self> {
+        match self.reply.try_recv() {
+            Ok(result) => Ok(result),
+            Err(mpsc::TryRecvError::Empty) => Err(self),
+            Err(mpsc::TryRecvError::Disconnected) => Ok(Err(owner_gone())),
+        }
+    }
+}
+
+impl<H> fmt::Debug for SharedHeadset<H> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("SharedHeadset").field("state", &self.state()).finish()
+    }
+}
+
+fn owner_gone() -> Error {
+    Error::communication_error("the headset owner was dropped before the command ran")
+}
+
+/// A value with a single writer that many threads can read without locking.
+///
+/// The sequence number is odd while a write is in progress. Readers retry
+/// if it was odd, or changed while they were copying the value.
+struct SeqLock<T> {
+    sequence: AtomicUsize,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: Copy + Send> Sync for SeqLock<T> {}
+
+impl<T: Copy> SeqLock<T> {
+    fn new(value: T) -> Self {
+        SeqLock { sequence: AtomicUsize::new(0), value: UnsafeCell::new(value) }
+    }
+
+    fn read(&self) -> T {
+        loop {
+            let before = self.sequence.load(Ordering::Acquire);
+            if before & 1 == 1 {
+                hint::spin_loop();
+                continue;
+            }
+
+            // The copy may be torn by a concurrent write, in which case the
+            // sequence number check below throws it away. Until then it's
+            // only bytes, since a torn copy needn't be a valid `T`.
+            let value = unsafe { ptr::read_volatile(self.value.get() as *const MaybeUninit<T>) };
+            atomic::fence(Ordering::Acquire);
+
+            if self.sequence.load(Ordering::Relaxed) == before {
+                return unsafe { value.assume_init() };
+            }
+        }
+    }
+
+    /// Writes a new value.
+    ///
+    /// There must never be two writes at the same time.
+    fn write(&self, value: T) {
+        let sequence = self.sequence.load(Ordering::Relaxed);
+        self.sequence.store(sequence.wrapping_add(1), Ordering::Relaxed);
+        atomic::fence(Ordering::Release);
+
+        unsafe { ptr::write_volatile(self.value.get(), value) };
+        self.sequence.store(sequence.wrapping_add(2), Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backend::{self, Replay};
+    use crate::record::{Buttons, Sample};
+    use std::num::NonZeroU64;
+    use std::thread;
+
+    fn replay(count: usize) -> Replay {
+        let samples = (0..count).map(|i| {
+            let orientation = math::Orientation::from_yaw_pitch_roll(i as math::Scalar * 0.01, 0.0, 0.0).quaternion();
+            Sample {
+                time: i as f64,
+                orientation: [orientation.w, orientation.i, orientation.j, orientation.k],
+                buttons: Buttons {
+                    volume_up: input::ButtonState::NotPressed,
+                    volume_down: input::ButtonState::NotPressed,
+                    mute: input::ButtonState::NotPressed,
+                },
+                angular_velocity: None,
+                linear_acceleration: None,
+                status: info::Status { worn: Some(i % 2 == 1), ..info::Status::default() },
+            }
+        }).collect();
+
+        Replay::new(samples, backend::test_properties(), backend::Timing::Stepped)
+    }
+
+    #[test]
+    fn readers_see_published_state() {
+        let (mut owner, shared) = share(replay(3));
+        assert_eq!(None, shared.state().updated_at);
+
+        owner.update().unwrap();
+        owner.update().unwrap();
+
+        let reader = shared.clone();
+        let yaw = thread::spawn(move || reader.state().orientation.yaw_pitch_roll()[0]).join().unwrap();
+        assert!((yaw - 0.01).abs() < 1e-5);
+        assert!(shared.state().updated_at.is_some());
+        assert_eq!(Some(true), shared.state().status.worn);
+        assert_eq!(time::Duration::from_secs(1), shared.state().pose_timestamp);
+    }
+
+    #[test]
+    fn commands_run_on_the_owner_in_order() {
+        let (mut owner, shared) = share(replay(2));
+        let first = shared.run(|replay: &mut Replay| Ok(replay.current().is_some()));
+        let second = shared.power_on();
+
+        let first = first.try_wait().expect_err("ran before the owner updated");
+        owner.update().unwrap();
+
+        assert!(!first.wait().unwrap());
+        second.wait().unwrap();
+
+        drop(owner);
+        assert!(shared.power_off().wait().is_err());
+    }
+
+    #[test]
+    fn reads_are_never_torn() {
+        let lock = Arc::new(SeqLock::new([0u64; 16]));
+        let readers: Vec<_> = (0..4).map(|_| {
+            let lock = lock.clone();
+            thread::spawn(move || {
+                for _ in 0..10_000 {
+                    let value = lock.read();
+                    assert!(value.iter().all(|&v| v == value[0]), "torn read {:?}", value);
+                }
+            })
+        }).collect();
+
+        for i in 0..10_000 {
+            lock.write([i; 16]);
+        }
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn reads_of_values_with_invalid_bit_patterns_are_never_torn() {
+        // Half of a `None` and half of a `Some` isn't a valid value, so only
+        // whole writes may ever be turned into one.
+        let lock = Arc::new(SeqLock::new([None::<NonZeroU64>; 8]));
+        let readers: Vec<_> = (0..4).map(|_| {
+            let lock = lock.clone();
+            thread::spawn(move || {
+                for _ in 0..10_000 {
+                    let value = lock.read();
+                    assert!(value.iter().all(|&v| v == value[0]), "torn read {:?}", value);
+                }
+            })
+        }).collect();
+
+        for i in 0..10_000 {
+            lock.write([NonZeroU64::new(i % 2 * i); 8]);
+        }
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! Benchmarks for encoding commands and decoding sensor frames.
+//!
+//! Each group compares the allocation-free path the client uses against
+//! the one it used before. Encoding also measures `AnyCommand::to_command`,
+//! the general-purpose path the emulator uses.
+//!
+//! Measured with `cargo bench -p psvr` on an x86_64 Xeon, rustc 1.95.
+//!
+//! | group               | benchmark                     | time     |
+//! |---------------------|-------------------------------|----------|
+//! | decode sensor frame | fixed offsets (client)        |  11.9 ns |
+//! | decode sensor frame | streaming reader (before)     |  63.7 ns |
+//! | encode SetHmdLeds   | stack buffer (client)         |   7.0 ns |
+//! | encode SetHmdLeds   | previous client path (before) | 105.7 ns |
+//! | encode SetHmdLeds   | allocated command             |  85.3 ns |
+
+extern crate criterion;
+extern crate psvr;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use psvr::command::{self, Command};
+use psvr::sensor::{self, Readable};
+use psvr::{protocol, wire};
+
+fn example_frame() -> [u8; sensor::FRAME_SIZE] {
+    let mut raw = [0; sensor::FRAME_SIZE];
+    for (i, b) in raw.iter_mut().enumerate() {
+        *b = (i as u8).wrapping_mul(37);
+    }
+    raw
+}
+
+fn decode_sensor_frame(c: &mut Criterion) {
+    let raw = example_frame();
+    let mut group = c.benchmark_group("decode sensor frame");
+
+    group.bench_function("fixed offsets", |b| {
+        b.iter(|| sensor::Readout::from_frame(black_box(&raw)))
+    });
+    // What `Readout::read_bytes` did before it used the fixed offsets.
+    group.bench_function("streaming reader", |b| {
+        b.iter(|| sensor::Readout::read(&mut wire::Reader::new(black_box(&raw))).unwrap())
+    });
+
+    group.finish();
+}
+
+fn encode_command(c: &mut Criterion) {
+    let leds = command::SetHmdLeds { led_mask: 0x1ff, values: [100; 9], reserved: [0; 5] };
+    let mut group = c.benchmark_group("encode SetHmdLeds");
+
+    group.bench_function("stack buffer", |b| {
+        let mut buf = [0; protocol::MAX_COMMAND_SIZE];
+        b.iter(|| black_box(&leds).encode(0, &mut buf).unwrap())
+    });
+    // What `Psvr::send_command` did before it used a stack buffer.
+    group.bench_function("previous client path", |b| {
+        b.iter(|| {
+            let leds = black_box(&leds);
+            let payload = leds.payload_bytes();
+            let command = protocol::Command {
+                header: protocol::CommandHeader {
+                    id: command::SetHmdLeds::ID,
+                    magic: protocol::COMMAND_MAGIC,
+                    status: 0,
+                    length: payload.len() as u8,
+                },
+                payload,
+            };
+            command.raw_bytes().to_owned()
+        })
+    });
+    group.bench_function("allocated command", |b| {
+        b.iter(|| command::AnyCommand::from(black_box(&leds).clone()).to_command(0).unwrap().raw_bytes())
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, decode_sensor_frame, encode_command);
+criterion_main!(benches);
@@ -0,0 +1,172 @@
+//! Decodes usbmon captures of PSVR traffic.
+//!
+//! ```text
+//! psvr-decode [OPTIONS] <capture.pcap|capture.pcapng>
+//!
+//! Options:
+//!   --device BUS.ADDRESS            treat a device as a PSVR, for captures
+//!                                   that don't include enumeration
+//!   --endpoint ENDPOINT=INTERFACE   map an endpoint address (e.g. 0x83) to an
+//!                                   interface number, for captures that don't
+//!                                   include enumeration
+//!   --interface INTERFACE           only show traffic for an interface number,
+//!                                   may be repeated
+//! ```
+
+extern crate psvr;
+extern crate failure;
+
+use psvr::capture::{self, Direction, Payload};
+use psvr::{command, protocol, sensor, usb};
+
+use std::{env, fs, process};
+
+const USAGE: &str = "usage: psvr-decode [--device BUS.ADDRESS] [--endpoint ENDPOINT=INTERFACE] [--interface INTERFACE] <capture>";
+
+/// Command line options.
+struct Options {
+    capture_path: String,
+    devices: Vec<(u16, u8)>,
+    endpoints: Vec<(u8, usb::Interface)>,
+    interfaces: Vec<usb::Interface>,
+}
+
+fn main() {
+    match run() {
+        Ok(..) => (),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        },
+    }
+}
+
+fn run() -> Result<(), failure::Error> {
+    let options = parse_options(env::args().skip(1))?;
+    let raw = fs::read(&options.capture_path)?;
+
+    let mut decoder = capture::Decoder::new();
+    for &(bus, device) in options.devices.iter() {
+        decoder.add_psvr(bus, device);
+
+        for &(endpoint, interface) in options.endpoints.iter() {
+            decoder.add_endpoint(bus, device, endpoint, interface);
+        }
+    }
+
+    let packets = capture::read_packets(&raw)?;
+    let start = packets.first().map(|p| p.timestamp).unwrap_or_default();
+
+    for packet in packets.iter() {
+        let event = match decoder.decode(packet)? {
+            Some(event) => event,
+            None => continue,
+        };
+
+        if !options.interfaces.is_empty() && !options.interfaces.contains(&event.interface) {
+            continue;
+        }
+
+        let elapsed = event.timestamp.checked_sub(start).unwrap_or_default();
+        let direction = match event.direction {
+            Direction::HostToDevice => "->",
+            Direction::DeviceToHost => "<-",
+        };
+
+        print!("{:>12.6} {}.{:03} {:?} {} ", elapsed.as_secs_f64(), event.bus, event.device, event.interface, direction);
+
+        match event.payload {
+            Payload::Command { header, command: command::AnyCommand::Unknown { payload, .. } } => {
+                println!("unknown command");
+                print_annotated_command(&header, &payload);
+            },
+            Payload::Command { header, command } => {
+                println!("{:?} (status 0x{:02x})", command, header.status);
+            },
+            Payload::BadMagic { header, payload } => {
+                println!("command with bad magic");
+                print_annotated_command(&header, &payload);
+            },
+            Payload::Sensor(readout) => print_readout(&readout),
+            Payload::Unknown(data) => {
+                println!("undecoded {} bytes", data.len());
+                print_hex(0, &data);
+            },
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints a command header with its fields labelled, followed by its payload.
+fn print_annotated_command(header: &protocol::CommandHeader, payload: &[u8]) {
+    println!("    0000: {:02x}                      id", header.id);
+    println!("    0001: {:02x}                      status", header.status);
+    println!("    0002: {:02x}                      magic{}", header.magic,
+             if header.magic == protocol::COMMAND_MAGIC { "" } else { " (expected aa)" });
+    println!("    0003: {:02x}                      length ({} bytes)", header.length, header.length);
+    print_hex(protocol::COMMAND_HEADER_SIZE, payload);
+}
+
+/// Prints a hex dump, eight bytes per line.
+fn print_hex(start_offset: usize, data: &[u8]) {
+    for (i, chunk) in data.chunks(8).enumerate() {
+        let bytes: Vec<_> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+        println!("    {:04x}: {}", start_offset + i * 8, bytes.join(" "));
+    }
+}
+
+fn print_readout(readout: &sensor::Readout) {
+    let instant = &readout.instants[0];
+
+    println!("sensor volume={} buttons={:?} status={:?} gyro=[{}, {}, {}] accel=[{}, {}, {}]",
+             readout.volume, readout.buttons, readout.status,
+             instant.gyroscope_raw.x, instant.gyroscope_raw.y, instant.gyroscope_raw.z,
+             instant.accelerometer_raw.x, instant.accelerometer_raw.y, instant.accelerometer_raw.z);
+}
+
+fn parse_options<I>(mut args: I) -> Result<Options, failure::Error>
+    where I: Iterator<Item=String> {
+    let mut capture_path = None;
+    let mut options = Options { capture_path: String::new(), devices: Vec::new(), endpoints: Vec::new(), interfaces: Vec::new() };
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| failure::format_err!("missing value for '{}'\n{}", arg, USAGE));
+
+        match &arg[..] {
+            "--device" => {
+                let value = value()?;
+                let mut parts = value.splitn(2, '.');
+                let bus = parts.next().unwrap_or("").parse()?;
+                let device = parts.next().ok_or_else(|| failure::format_err!("device must be written as BUS.ADDRESS"))?.parse()?;
+                options.devices.push((bus, device));
+            },
+            "--endpoint" => {
+                let value = value()?;
+                let mut parts = value.splitn(2, '=');
+                let endpoint = parse_u8(parts.next().unwrap_or(""))?;
+                let interface = parts.next().ok_or_else(|| failure::format_err!("endpoint must be written as ENDPOINT=INTERFACE"))?;
+                options.endpoints.push((endpoint, usb::Interface::from_i32(interface.parse()?)?));
+            },
+            "--interface" => options.interfaces.push(usb::Interface::from_i32(value()?.parse()?)?),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            },
+            _ if capture_path.is_none() && !arg.starts_with("--") => capture_path = Some(arg),
+            _ => return Err(failure::format_err!("unexpected argument '{}'\n{}", arg, USAGE)),
+        }
+    }
+
+    options.capture_path = capture_path.ok_or_else(|| failure::format_err!("no capture file given\n{}", USAGE))?;
+    Ok(options)
+}
+
+/// Parses a decimal or `0x`-prefixed hexadecimal byte.
+fn parse_u8(s: &str) -> Result<u8, failure::Error> {
+    if let Some(hex) = s.strip_prefix("0x") {
+        Ok(u8::from_str_radix(hex, 16)?)
+    } else {
+        Ok(s.parse()?)
+    }
+}
@@ -0,0 +1,385 @@
+//! Decoding of usbmon captures of PSVR traffic.
+//!
+//! Captures can be taken on Linux with Wireshark or `tcpdump` on a
+//! `usbmonN` interface, and saved in either the pcap or pcapng format.
+//!
+//! The decoder follows USB enumeration in the capture to find out which
+//! device is a PSVR, and which of its endpoints belong to which
+//! `usb::Interface`. If the capture was started after the headset was plugged
+//! in, the device and endpoints can be registered by hand instead.
+
+pub use self::pcap::{read_packets, Packet};
+
+pub mod pcap;
+pub mod usbmon;
+
+use crate::{command, protocol, sensor, usb};
+use hmdee_core::Error;
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// The standard USB `GET_DESCRIPTOR` request.
+const REQUEST_GET_DESCRIPTOR: u8 = 0x06;
+/// The HID class `SET_REPORT` request.
+const REQUEST_HID_SET_REPORT: u8 = 0x09;
+/// Device-to-host, standard, device recipient.
+const REQUEST_TYPE_STANDARD_DEVICE_IN: u8 = 0x80;
+/// Host-to-device, class, interface recipient.
+const REQUEST_TYPE_CLASS_INTERFACE_OUT: u8 = 0x21;
+
+const DESCRIPTOR_TYPE_DEVICE: u8 = 0x01;
+const DESCRIPTOR_TYPE_CONFIGURATION: u8 = 0x02;
+const DESCRIPTOR_TYPE_INTERFACE: u8 = 0x04;
+const DESCRIPTOR_TYPE_ENDPOINT: u8 = 0x05;
+
+/// The direction data travelled in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// From the host computer to the PSVR.
+    HostToDevice,
+    /// From the PSVR to the host computer.
+    DeviceToHost,
+}
+
+/// A decoded piece of PSVR traffic.
+#[derive(Clone, Debug)]
+pub struct Event {
+    /// When the traffic was captured.
+    pub timestamp: Duration,
+    /// The USB bus the PSVR is on.
+    pub bus: u16,
+    /// The USB device address of the PSVR.
+    pub device: u8,
+    /// The PSVR interface the traffic belongs to.
+    pub interface: usb::Interface,
+    /// Which way the traffic travelled.
+    pub direction: Direction,
+    /// The decoded traffic.
+    pub payload: Payload,
+}
+
+/// The contents of PSVR traffic.
+#[derive(Clone, Debug)]
+pub enum Payload {
+    /// A command or command reply.
+    Command {
+        /// The command header.
+        header: protocol::CommandHeader,
+        /// The decoded command.
+        command: command::AnyCommand,
+    },
+    /// A report shaped like a command, but without the command magic byte.
+    BadMagic {
+        /// The command header.
+        header: protocol::CommandHeader,
+        /// The payload, as given by the length in the header.
+        payload: Vec<u8>,
+    },
+    /// A sensor report.
+    Sensor(sensor::Readout),
+    /// Data that could not be decoded.
+    Unknown(Vec<u8>),
+}
+
+/// Decodes PSVR traffic from usbmon packets.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    /// The devices seen so far, keyed by `(bus, device address)`.
+    devices: HashMap<(u16, u8), Device>,
+    /// Descriptor requests awaiting completion, keyed by URB ID.
+    pending_descriptor_requests: HashMap<u64, u8>,
+}
+
+/// What the decoder knows about a USB device.
+#[derive(Debug, Default)]
+struct Device {
+    /// True if this device is a PSVR.
+    is_psvr: bool,
+    /// The interface number that owns each endpoint address.
+    endpoint_interfaces: HashMap<u8, i32>,
+}
+
+impl Decoder {
+    /// Creates a new decoder.
+    pub fn new() -> Self {
+        Decoder::default()
+    }
+
+    /// Treats a device as a PSVR, even if its enumeration was not captured.
+    pub fn add_psvr(&mut self, bus: u16, device: u8) {
+        self.devices.entry((bus, device)).or_default().is_psvr = true;
+    }
+
+    /// Records that an endpoint belongs to an interface, even if the
+    /// configuration descriptor was not captured.
+    ///
+    /// The endpoint address must include the `0x80` direction bit for IN endpoints.
+    pub fn add_endpoint(&mut self, bus: u16, device: u8, endpoint: u8, interface: usb::Interface) {
+        self.devices.entry((bus, device)).or_default()
+            .endpoint_interfaces.insert(endpoint, interface as i32);
+    }
+
+    /// Decodes a captured packet.
+    ///
+    /// Returns `None` for traffic that is not PSVR traffic, or that carries no data.
+    pub fn decode(&mut self, packet: &pcap::Packet) -> Result<Option<Event>, Error> {
+        let usb_packet = usbmon::Packet::parse(packet.link_type, &packet.data)?;
+
+        let (interface_number, data) = match usb_packet.transfer_type {
+            usbmon::TransferType::Control => match self.observe_control(&usb_packet) {
+                Some(hid_report) => hid_report,
+                None => return Ok(None),
+            },
+            usbmon::TransferType::Interrupt | usbmon::TransferType::Bulk => {
+                let device = match self.devices.get(&(usb_packet.bus, usb_packet.device)) {
+                    Some(device) if device.is_psvr => device,
+                    _ => return Ok(None),
+                };
+
+                match device.endpoint_interfaces.get(&usb_packet.endpoint) {
+                    Some(&interface_number) => (interface_number, usb_packet.data),
+                    None => return Ok(None),
+                }
+            },
+            usbmon::TransferType::Isochronous => return Ok(None),
+        };
+
+        // OUT data is captured on submission, IN data on completion.
+        let has_data = match usb_packet.event_type {
+            usbmon::EventType::Submission => !usb_packet.is_in(),
+            usbmon::EventType::Completion => usb_packet.is_in(),
+            usbmon::EventType::Error => false,
+        };
+        if !has_data || data.is_empty() {
+            return Ok(None);
+        }
+
+        let interface = usb::Interface::from_i32(interface_number)?;
+        let direction = if usb_packet.is_in() { Direction::DeviceToHost } else { Direction::HostToDevice };
+
+        Ok(Some(Event {
+            timestamp: packet.timestamp,
+            bus: usb_packet.bus,
+            device: usb_packet.device,
+            interface,
+            direction,
+            payload: decode_payload(interface, data),
+        }))
+    }
+
+    /// Follows enumeration on the control endpoint.
+    ///
+    /// Returns the interface number and data if the packet is a HID report
+    /// sent to a PSVR over the control endpoint.
+    fn observe_control<'a>(&mut self, packet: &usbmon::Packet<'a>) -> Option<(i32, &'a [u8])> {
+        match (packet.event_type, packet.setup) {
+            (usbmon::EventType::Submission, Some(setup)) => {
+                let (request_type, request) = (setup[0], setup[1]);
+                let value = u16::from_le_bytes([setup[2], setup[3]]);
+                let index = u16::from_le_bytes([setup[4], setup[5]]);
+
+                if request_type == REQUEST_TYPE_STANDARD_DEVICE_IN && request == REQUEST_GET_DESCRIPTOR {
+                    self.pending_descriptor_requests.insert(packet.urb_id, (value >> 8) as u8);
+                } else if request_type == REQUEST_TYPE_CLASS_INTERFACE_OUT && request == REQUEST_HID_SET_REPORT &&
+                    self.is_psvr(packet.bus, packet.device) {
+                    return Some((index as i32, packet.data));
+                }
+            },
+            (usbmon::EventType::Completion, _) => {
+                if let Some(descriptor_type) = self.pending_descriptor_requests.remove(&packet.urb_id) {
+                    let device = self.devices.entry((packet.bus, packet.device)).or_default();
+
+                    match descriptor_type {
+                        DESCRIPTOR_TYPE_DEVICE => device.observe_device_descriptor(packet.data),
+                        DESCRIPTOR_TYPE_CONFIGURATION => device.observe_configuration_descriptor(packet.data),
+                        _ => (),
+                    }
+                }
+            },
+            _ => (),
+        }
+
+        None
+    }
+
+    fn is_psvr(&self, bus: u16, device: u8) -> bool {
+        self.devices.get(&(bus, device)).map(|d| d.is_psvr).unwrap_or(false)
+    }
+}
+
+impl Device {
+    fn observe_device_descriptor(&mut self, raw: &[u8]) {
+        if raw.len() >= 12 && raw[1] == DESCRIPTOR_TYPE_DEVICE {
+            let vendor_id = u16::from_le_bytes([raw[8], raw[9]]);
+            let product_id = u16::from_le_bytes([raw[10], raw[11]]);
+
+            self.is_psvr = vendor_id == usb::PSVR_VID && product_id == usb::PSVR_PID;
+        }
+    }
+
+    fn observe_configuration_descriptor(&mut self, mut raw: &[u8]) {
+        let mut current_interface = None;
+
+        // The configuration descriptor is followed by all of its interface
+        // and endpoint descriptors, each prefixed by its length and type.
+        while raw.len() >= 2 {
+            let (length, descriptor_type) = (raw[0] as usize, raw[1]);
+            if length < 2 || length > raw.len() {
+                break;
+            }
+
+            match descriptor_type {
+                DESCRIPTOR_TYPE_INTERFACE if length >= 3 => current_interface = Some(raw[2] as i32),
+                DESCRIPTOR_TYPE_ENDPOINT if length >= 3 => if let Some(interface) = current_interface {
+                    self.endpoint_interfaces.insert(raw[2], interface);
+                },
+                _ => (),
+            }
+
+            raw = &raw[length..];
+        }
+    }
+}
+
+/// Decodes data sent to or from a PSVR interface.
+fn decode_payload(interface: usb::Interface, data: &[u8]) -> Payload {
+    use crate::sensor::Readable;
+
+    match interface {
+        usb::Interface::HidSensor if data.len() == sensor::FRAME_SIZE => {
+            let mut frame = [0; sensor::FRAME_SIZE];
+            frame.copy_from_slice(data);
+
+            match sensor::Readout::read_bytes(&frame) {
+                Ok(readout) => Payload::Sensor(readout),
+                Err(..) => Payload::Unknown(data.to_owned()),
+            }
+        },
+        usb::Interface::HidControl | usb::Interface::HidControl2 => match protocol::Command::parse(data) {
+            Ok(raw) if raw.header.validate().is_err() => Payload::BadMagic { header: raw.header, payload: raw.payload },
+            Ok(raw) => match command::AnyCommand::from_command(&raw) {
+                Ok(command) => Payload::Command { header: raw.header, command },
+                Err(..) => Payload::Unknown(data.to_owned()),
+            },
+            Err(..) => Payload::Unknown(data.to_owned()),
+        },
+        _ => Payload::Unknown(data.to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const BUS: u16 = 3;
+    const DEVICE: u8 = 7;
+    const SENSOR_ENDPOINT: u8 = 0x83;
+    const CONTROL_ENDPOINT: u8 = 0x04;
+
+    fn usbmon(urb_id: u64, event: u8, transfer_type: u8, endpoint: u8, setup: Option<[u8; 8]>, data: &[u8]) -> pcap::Packet {
+        let mut raw = Vec::new();
+        raw.extend_from_slice(&urb_id.to_le_bytes());
+        raw.extend_from_slice(&[event, transfer_type, endpoint, DEVICE]);
+        raw.extend_from_slice(&BUS.to_le_bytes());
+        raw.extend_from_slice(&[if setup.is_some() { 0 } else { b'-' }, 0]);
+        raw.extend_from_slice(&[0; 16]); // timestamp, status
+        raw.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        raw.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        raw.extend_from_slice(&setup.unwrap_or([0; 8]));
+        raw.extend_from_slice(data);
+
+        pcap::Packet { timestamp: Duration::from_secs(1), link_type: pcap::LINKTYPE_USB_LINUX, data: raw }
+    }
+
+    fn enumerate(decoder: &mut Decoder) {
+        let device_descriptor = [18, 1, 0, 2, 0, 0, 0, 64, 0x4c, 0x05, 0xaf, 0x09, 0, 1, 1, 2, 3, 1];
+        let configuration_descriptor = [
+            9, 2, 50, 0, 2, 1, 0, 0x80, 250,
+            9, 4, 4, 0, 1, 3, 0, 0, 0, // interface 4
+            7, 5, SENSOR_ENDPOINT, 3, 64, 0, 1,
+            9, 4, 5, 0, 1, 3, 0, 0, 0, // interface 5
+            7, 5, CONTROL_ENDPOINT, 3, 64, 0, 1,
+        ];
+
+        for &(urb_id, descriptor_type, data) in &[(1, DESCRIPTOR_TYPE_DEVICE, &device_descriptor[..]),
+                                                      (2, DESCRIPTOR_TYPE_CONFIGURATION, &configuration_descriptor[..])] {
+            let setup = [REQUEST_TYPE_STANDARD_DEVICE_IN, REQUEST_GET_DESCRIPTOR, 0, descriptor_type, 0, 0, 0xff, 0];
+            assert!(decoder.decode(&usbmon(urb_id, b'S', 2, 0x80, Some(setup), &[])).unwrap().is_none());
+            assert!(decoder.decode(&usbmon(urb_id, b'C', 2, 0x80, None, data)).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn ignores_traffic_before_enumeration() {
+        let mut decoder = Decoder::new();
+        let packet = usbmon(9, b'C', 1, SENSOR_ENDPOINT, None, &[0; 64]);
+
+        assert!(decoder.decode(&packet).unwrap().is_none());
+    }
+
+    #[test]
+    fn decodes_sensor_reports_after_enumeration() {
+        let mut decoder = Decoder::new();
+        enumerate(&mut decoder);
+
+        let mut frame = [0; sensor::FRAME_SIZE];
+        frame[0] = 0b1000; // mute
+        let event = decoder.decode(&usbmon(9, b'C', 1, SENSOR_ENDPOINT, None, &frame)).unwrap().unwrap();
+
+        assert_eq!(usb::Interface::HidSensor, event.interface);
+        assert_eq!(Direction::DeviceToHost, event.direction);
+        match event.payload {
+            Payload::Sensor(readout) => assert!(readout.buttons.mute),
+            payload => panic!("expected a sensor readout but got {:?}", payload),
+        }
+    }
+
+    #[test]
+    fn decodes_control_writes() {
+        let mut decoder = Decoder::new();
+        decoder.add_psvr(BUS, DEVICE);
+        decoder.add_endpoint(BUS, DEVICE, CONTROL_ENDPOINT, usb::Interface::HidControl);
+
+        let write = [0x17, 0, 0xAA, 4, 1, 0, 0, 0];
+        let event = decoder.decode(&usbmon(9, b'S', 1, CONTROL_ENDPOINT, None, &write)).unwrap().unwrap();
+
+        assert_eq!(usb::Interface::HidControl, event.interface);
+        assert_eq!(Direction::HostToDevice, event.direction);
+        match event.payload {
+            Payload::Command { command, .. } => assert_eq!(command::AnyCommand::SetPower(command::SetPower { on: true }), command),
+            payload => panic!("expected a command but got {:?}", payload),
+        }
+    }
+
+    #[test]
+    fn leaves_unknown_commands_undecoded() {
+        let mut decoder = Decoder::new();
+        enumerate(&mut decoder);
+
+        let write = [0x1b, 0, 0xAA, 2, 0xde, 0xad];
+        let event = decoder.decode(&usbmon(9, b'S', 1, CONTROL_ENDPOINT, None, &write)).unwrap().unwrap();
+
+        match event.payload {
+            Payload::Command { command, .. } => assert_eq!(command::AnyCommand::Unknown { id: 0x1b, payload: vec![0xde, 0xad] }, command),
+            payload => panic!("expected an unknown command but got {:?}", payload),
+        }
+    }
+
+    #[test]
+    fn keeps_commands_with_bad_magic() {
+        let mut decoder = Decoder::new();
+        enumerate(&mut decoder);
+
+        let write = [0x17, 0, 0xAB, 1, 1];
+        let event = decoder.decode(&usbmon(9, b'S', 1, CONTROL_ENDPOINT, None, &write)).unwrap().unwrap();
+
+        match event.payload {
+            Payload::BadMagic { header, payload } => {
+                assert_eq!(0xAB, header.magic);
+                assert_eq!(vec![1], payload);
+            },
+            payload => panic!("expected a command with bad magic but got {:?}", payload),
+        }
+    }
+}
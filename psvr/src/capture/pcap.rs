@@ -0,0 +1,324 @@
+//! Readers for the pcap and pcapng capture file formats.
+//!
+//! Only the parts of the formats needed to pull packets out of a
+//! usbmon capture are supported.
+
+use hmdee_core::Error;
+
+use std::time::Duration;
+
+/// The classic pcap magic number with microsecond timestamps.
+const PCAP_MAGIC_MICROSECONDS: u32 = 0xa1b2_c3d4;
+/// The classic pcap magic number with nanosecond timestamps.
+const PCAP_MAGIC_NANOSECONDS: u32 = 0xa1b2_3c4d;
+/// The size of the classic pcap global header.
+const PCAP_GLOBAL_HEADER_SIZE: usize = 24;
+/// The size of the classic pcap per-record header.
+const PCAP_RECORD_HEADER_SIZE: usize = 16;
+
+/// The pcapng section header block type.
+const PCAPNG_SECTION_HEADER: u32 = 0x0a0d_0d0a;
+/// The pcapng interface description block type.
+const PCAPNG_INTERFACE_DESCRIPTION: u32 = 0x0000_0001;
+/// The pcapng simple packet block type.
+const PCAPNG_SIMPLE_PACKET: u32 = 0x0000_0003;
+/// The pcapng enhanced packet block type.
+const PCAPNG_ENHANCED_PACKET: u32 = 0x0000_0006;
+/// The pcapng byte-order magic, as found in section headers.
+const PCAPNG_BYTE_ORDER_MAGIC: u32 = 0x1a2b_3c4d;
+/// The `if_tsresol` interface description option.
+const PCAPNG_OPTION_IF_TSRESOL: u16 = 9;
+
+/// Linux usbmon, with the 48-byte packet header.
+pub const LINKTYPE_USB_LINUX: u32 = 189;
+/// Linux usbmon, with the 64-byte memory-mapped packet header.
+pub const LINKTYPE_USB_LINUX_MMAPPED: u32 = 220;
+
+/// A packet read from a capture file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Packet {
+    /// The time the packet was captured, relative to the UNIX epoch.
+    pub timestamp: Duration,
+    /// The link-layer header type of the packet.
+    pub link_type: u32,
+    /// The captured bytes.
+    pub data: Vec<u8>,
+}
+
+/// Reads every packet from a pcap or pcapng file.
+pub fn read_packets(raw: &[u8]) -> Result<Vec<Packet>, Error> {
+    if raw.len() < 4 {
+        return Err(Error::communication_error("capture file is too small to contain a header"));
+    }
+
+    if read_u32(raw, 0, false) == PCAPNG_SECTION_HEADER {
+        read_pcapng(raw)
+    } else {
+        read_pcap(raw)
+    }
+}
+
+/// Reads a classic pcap file.
+fn read_pcap(raw: &[u8]) -> Result<Vec<Packet>, Error> {
+    let (big_endian, nanoseconds) = match (read_u32(raw, 0, false), read_u32(raw, 0, true)) {
+        (PCAP_MAGIC_MICROSECONDS, _) => (false, false),
+        (PCAP_MAGIC_NANOSECONDS, _) => (false, true),
+        (_, PCAP_MAGIC_MICROSECONDS) => (true, false),
+        (_, PCAP_MAGIC_NANOSECONDS) => (true, true),
+        (magic, _) => return Err(Error::communication_error(
+            format!("unknown capture file magic number 0x{:08x}", magic))),
+    };
+
+    let header = slice(raw, 0, PCAP_GLOBAL_HEADER_SIZE)?;
+    let link_type = read_u32(header, 20, big_endian);
+
+    let mut packets = Vec::new();
+    let mut offset = PCAP_GLOBAL_HEADER_SIZE;
+
+    while offset < raw.len() {
+        let record = slice(raw, offset, PCAP_RECORD_HEADER_SIZE)?;
+        let seconds = read_u32(record, 0, big_endian) as u64;
+        let fraction = read_u32(record, 4, big_endian);
+        let captured_length = read_u32(record, 8, big_endian) as usize;
+
+        let data = slice(raw, offset + PCAP_RECORD_HEADER_SIZE, captured_length)?;
+        let timestamp = if nanoseconds {
+            Duration::new(seconds, fraction)
+        } else {
+            Duration::new(seconds, 0) + Duration::from_micros(fraction as u64)
+        };
+
+        packets.push(Packet { timestamp, link_type, data: data.to_owned() });
+        offset += PCAP_RECORD_HEADER_SIZE + captured_length;
+    }
+
+    Ok(packets)
+}
+
+/// Information about a pcapng capture interface.
+struct Interface {
+    link_type: u32,
+    /// The timestamp resolution, as `(base, exponent)`, meaning
+    /// each tick is `base ^ -exponent` seconds.
+    resolution: (u64, u32),
+}
+
+/// Reads a pcapng file.
+fn read_pcapng(raw: &[u8]) -> Result<Vec<Packet>, Error> {
+    let mut packets = Vec::new();
+    let mut interfaces: Vec<Interface> = Vec::new();
+    let mut big_endian = false;
+    let mut offset = 0;
+
+    while offset < raw.len() {
+        // The section header carries the byte order for everything after it,
+        // so it needs to be inspected before the block length can be trusted.
+        if read_u32(slice(raw, offset, 4)?, 0, false) == PCAPNG_SECTION_HEADER {
+            let magic = slice(raw, offset + 8, 4)?;
+            big_endian = match (read_u32(magic, 0, false), read_u32(magic, 0, true)) {
+                (PCAPNG_BYTE_ORDER_MAGIC, _) => false,
+                (_, PCAPNG_BYTE_ORDER_MAGIC) => true,
+                (magic, _) => return Err(Error::communication_error(
+                    format!("unknown pcapng byte-order magic 0x{:08x}", magic))),
+            };
+            interfaces.clear();
+        }
+
+        let block_header = slice(raw, offset, 8)?;
+        let block_type = read_u32(block_header, 0, big_endian);
+        let block_length = read_u32(block_header, 4, big_endian) as usize;
+
+        if block_length < 12 || block_length & 0b11 != 0 {
+            return Err(Error::communication_error(
+                format!("invalid pcapng block length {} at offset {}", block_length, offset)));
+        }
+
+        // The block body, excluding the type, length, and trailing length.
+        let body = slice(raw, offset + 8, block_length - 12)?;
+
+        match block_type {
+            PCAPNG_INTERFACE_DESCRIPTION => {
+                let link_type = read_u16(slice(body, 0, 2)?, 0, big_endian) as u32;
+                let resolution = interface_resolution(&body[8.min(body.len())..], big_endian)?;
+                interfaces.push(Interface { link_type, resolution });
+            },
+            PCAPNG_ENHANCED_PACKET => {
+                let fields = slice(body, 0, 20)?;
+                let interface = interface(&interfaces, read_u32(fields, 0, big_endian))?;
+                let ticks = ((read_u32(fields, 4, big_endian) as u64) << 32) |
+                    read_u32(fields, 8, big_endian) as u64;
+                let captured_length = read_u32(fields, 12, big_endian) as usize;
+
+                packets.push(Packet {
+                    timestamp: ticks_to_duration(ticks, interface.resolution),
+                    link_type: interface.link_type,
+                    data: slice(body, 20, captured_length)?.to_owned(),
+                });
+            },
+            PCAPNG_SIMPLE_PACKET => {
+                let interface = interface(&interfaces, 0)?;
+                let original_length = read_u32(slice(body, 0, 4)?, 0, big_endian) as usize;
+                let captured_length = original_length.min(body.len() - 4);
+
+                packets.push(Packet {
+                    timestamp: Duration::from_secs(0),
+                    link_type: interface.link_type,
+                    data: slice(body, 4, captured_length)?.to_owned(),
+                });
+            },
+            // Skip statistics, name resolution, and custom blocks.
+            _ => (),
+        }
+
+        offset += block_length;
+    }
+
+    Ok(packets)
+}
+
+/// Looks up a previously described pcapng interface.
+fn interface(interfaces: &[Interface], id: u32) -> Result<&Interface, Error> {
+    interfaces.get(id as usize).ok_or_else(|| Error::communication_error(
+        format!("pcapng packet refers to undescribed interface {}", id)))
+}
+
+/// Finds the timestamp resolution in a list of interface description options.
+fn interface_resolution(mut options: &[u8], big_endian: bool) -> Result<(u64, u32), Error> {
+    // The default resolution is microseconds.
+    let mut resolution = (10, 6);
+
+    while options.len() >= 4 {
+        let code = read_u16(options, 0, big_endian);
+        let length = read_u16(options, 2, big_endian) as usize;
+        let value = slice(options, 4, length)?;
+
+        match code {
+            0 => break, // opt_endofopt
+            PCAPNG_OPTION_IF_TSRESOL if length >= 1 => {
+                let exponent = (value[0] & 0x7f) as u32;
+                resolution = if value[0] & 0x80 == 0 { (10, exponent) } else { (2, exponent) };
+            },
+            _ => (),
+        }
+
+        // Option values are padded to 32 bits.
+        let padded_length = (length + 3) & !3;
+        options = &options[(4 + padded_length).min(options.len())..];
+    }
+
+    Ok(resolution)
+}
+
+/// Converts a number of timestamp ticks into a duration.
+fn ticks_to_duration(ticks: u64, (base, exponent): (u64, u32)) -> Duration {
+    let ticks_per_second = base.saturating_pow(exponent).max(1);
+    let seconds = ticks / ticks_per_second;
+    let remainder = ticks % ticks_per_second;
+    let nanoseconds = (remainder as u128 * 1_000_000_000 / ticks_per_second as u128) as u32;
+
+    Duration::new(seconds, nanoseconds)
+}
+
+/// Gets a subslice, failing if the capture is truncated.
+fn slice(raw: &[u8], offset: usize, length: usize) -> Result<&[u8], Error> {
+    raw.get(offset..offset.saturating_add(length)).ok_or_else(|| Error::communication_error(
+        format!("capture truncated: expected {} bytes at offset {}", length, offset)))
+}
+
+fn read_u16(raw: &[u8], offset: usize, big_endian: bool) -> u16 {
+    let bytes = [raw[offset], raw[offset + 1]];
+    if big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) }
+}
+
+fn read_u32(raw: &[u8], offset: usize, big_endian: bool) -> u32 {
+    let bytes = [raw[offset], raw[offset + 1], raw[offset + 2], raw[offset + 3]];
+    if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pcap_file(records: &[(u32, u32, &[u8])]) -> Vec<u8> {
+        let mut raw = Vec::new();
+        raw.extend_from_slice(&PCAP_MAGIC_MICROSECONDS.to_le_bytes());
+        raw.extend_from_slice(&[2, 0, 4, 0]); // version 2.4
+        raw.extend_from_slice(&[0; 8]); // timezone, accuracy
+        raw.extend_from_slice(&65535u32.to_le_bytes());
+        raw.extend_from_slice(&LINKTYPE_USB_LINUX_MMAPPED.to_le_bytes());
+
+        for &(seconds, microseconds, data) in records {
+            raw.extend_from_slice(&seconds.to_le_bytes());
+            raw.extend_from_slice(&microseconds.to_le_bytes());
+            raw.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            raw.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            raw.extend_from_slice(data);
+        }
+        raw
+    }
+
+    fn pcapng_block(block_type: u32, body: &[u8]) -> Vec<u8> {
+        let length = (12 + body.len()) as u32;
+        let mut raw = Vec::new();
+        raw.extend_from_slice(&block_type.to_le_bytes());
+        raw.extend_from_slice(&length.to_le_bytes());
+        raw.extend_from_slice(body);
+        raw.extend_from_slice(&length.to_le_bytes());
+        raw
+    }
+
+    #[test]
+    fn reads_pcap_records() {
+        let raw = pcap_file(&[(10, 500_000, &[1, 2, 3]), (11, 0, &[4])]);
+        let packets = read_packets(&raw).unwrap();
+
+        assert_eq!(vec![
+            Packet { timestamp: Duration::from_millis(10_500), link_type: LINKTYPE_USB_LINUX_MMAPPED, data: vec![1, 2, 3] },
+            Packet { timestamp: Duration::from_secs(11), link_type: LINKTYPE_USB_LINUX_MMAPPED, data: vec![4] },
+        ], packets);
+    }
+
+    #[test]
+    fn rejects_truncated_pcap_records() {
+        let mut raw = pcap_file(&[(10, 0, &[1, 2, 3, 4])]);
+        raw.pop();
+
+        assert!(read_packets(&raw).is_err());
+    }
+
+    #[test]
+    fn reads_pcapng_enhanced_packets() {
+        let mut section = Vec::new();
+        section.extend_from_slice(&PCAPNG_BYTE_ORDER_MAGIC.to_le_bytes());
+        section.extend_from_slice(&[1, 0, 0, 0]); // version 1.0
+        section.extend_from_slice(&u64::MAX.to_le_bytes()); // unknown section length
+
+        let mut interface = Vec::new();
+        interface.extend_from_slice(&(LINKTYPE_USB_LINUX as u16).to_le_bytes());
+        interface.extend_from_slice(&[0, 0]);
+        interface.extend_from_slice(&0u32.to_le_bytes());
+        // if_tsresol = 10^-9
+        interface.extend_from_slice(&PCAPNG_OPTION_IF_TSRESOL.to_le_bytes());
+        interface.extend_from_slice(&1u16.to_le_bytes());
+        interface.extend_from_slice(&[9, 0, 0, 0]);
+        interface.extend_from_slice(&[0, 0, 0, 0]);
+
+        let ticks: u64 = 2_000_000_123;
+        let mut packet = Vec::new();
+        packet.extend_from_slice(&0u32.to_le_bytes());
+        packet.extend_from_slice(&((ticks >> 32) as u32).to_le_bytes());
+        packet.extend_from_slice(&(ticks as u32).to_le_bytes());
+        packet.extend_from_slice(&3u32.to_le_bytes());
+        packet.extend_from_slice(&3u32.to_le_bytes());
+        packet.extend_from_slice(&[7, 8, 9, 0]); // padded to 32 bits
+
+        let mut raw = pcapng_block(PCAPNG_SECTION_HEADER, &section);
+        raw.extend(pcapng_block(PCAPNG_INTERFACE_DESCRIPTION, &interface));
+        raw.extend(pcapng_block(PCAPNG_ENHANCED_PACKET, &packet));
+
+        let packets = read_packets(&raw).unwrap();
+        assert_eq!(vec![
+            Packet { timestamp: Duration::new(2, 123), link_type: LINKTYPE_USB_LINUX, data: vec![7, 8, 9] },
+        ], packets);
+    }
+}
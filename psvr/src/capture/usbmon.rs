@@ -0,0 +1,137 @@
+//! The Linux usbmon packet format.
+//!
+//! Every captured packet starts with a header describing the USB request
+//! block (URB) it belongs to, followed by the captured transfer data.
+//!
+//! ```text
+//! struct {
+//!     uint64_t id;          // URB identifier, shared by submission and completion
+//!     uint8_t type;         // 'S'ubmission, 'C'ompletion, 'E'rror
+//!     uint8_t xfer_type;    // 0 isochronous, 1 interrupt, 2 control, 3 bulk
+//!     uint8_t epnum;        // endpoint number, with 0x80 for IN
+//!     uint8_t devnum;
+//!     uint16_t busnum;
+//!     int8_t flag_setup;    // zero if `setup` is valid
+//!     int8_t flag_data;     // zero if data is present
+//!     int64_t ts_sec;
+//!     int32_t ts_usec;
+//!     int32_t status;
+//!     uint32_t length;
+//!     uint32_t len_cap;
+//!     uint8_t setup[8];
+//!     // The memory-mapped variant has 16 more bytes of isochronous details here.
+//! } header;
+//! ```
+
+use crate::capture::pcap;
+use hmdee_core::Error;
+
+/// The size of the usbmon header.
+pub const HEADER_SIZE: usize = 48;
+/// The size of the memory-mapped usbmon header.
+pub const HEADER_SIZE_MMAPPED: usize = 64;
+
+/// The bit set on IN endpoint addresses.
+pub const ENDPOINT_DIRECTION_IN: u8 = 0x80;
+
+/// A kind of usbmon event.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EventType {
+    /// A request was submitted to the device.
+    Submission,
+    /// A request was completed by the device.
+    Completion,
+    /// A request failed.
+    Error,
+}
+
+/// A USB transfer type.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TransferType {
+    Isochronous,
+    Interrupt,
+    Control,
+    Bulk,
+}
+
+/// A parsed usbmon packet.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Packet<'a> {
+    /// The URB identifier, shared between a submission and its completion.
+    pub urb_id: u64,
+    pub event_type: EventType,
+    pub transfer_type: TransferType,
+    /// The endpoint address, including the direction bit.
+    pub endpoint: u8,
+    /// The device address on the bus.
+    pub device: u8,
+    /// The bus number.
+    pub bus: u16,
+    /// The control setup packet, if this is a control submission.
+    pub setup: Option<[u8; 8]>,
+    /// The captured transfer data.
+    pub data: &'a [u8],
+}
+
+impl<'a> Packet<'a> {
+    /// Parses a usbmon packet from a captured frame.
+    pub fn parse(link_type: u32, raw: &'a [u8]) -> Result<Self, Error> {
+        let header_size = match link_type {
+            pcap::LINKTYPE_USB_LINUX => HEADER_SIZE,
+            pcap::LINKTYPE_USB_LINUX_MMAPPED => HEADER_SIZE_MMAPPED,
+            _ => return Err(Error::communication_error(
+                format!("link type {} is not a usbmon capture", link_type))),
+        };
+
+        if raw.len() < header_size {
+            return Err(Error::communication_error(
+                format!("usbmon packet of {} bytes is smaller than its {} byte header", raw.len(), header_size)));
+        }
+
+        let event_type = match raw[8] {
+            b'S' => EventType::Submission,
+            b'C' => EventType::Completion,
+            b'E' => EventType::Error,
+            other => return Err(Error::communication_error(
+                format!("unknown usbmon event type 0x{:02x}", other))),
+        };
+
+        let transfer_type = match raw[9] {
+            0 => TransferType::Isochronous,
+            1 => TransferType::Interrupt,
+            2 => TransferType::Control,
+            3 => TransferType::Bulk,
+            other => return Err(Error::communication_error(
+                format!("unknown usbmon transfer type {}", other))),
+        };
+
+        let mut urb_id = [0; 8];
+        urb_id.copy_from_slice(&raw[0..8]);
+        let mut setup = [0; 8];
+        setup.copy_from_slice(&raw[40..48]);
+
+        let captured_length = u32::from_le_bytes([raw[36], raw[37], raw[38], raw[39]]) as usize;
+        let data = &raw[header_size..];
+
+        Ok(Packet {
+            urb_id: u64::from_le_bytes(urb_id),
+            event_type,
+            transfer_type,
+            endpoint: raw[10],
+            device: raw[11],
+            bus: u16::from_le_bytes([raw[12], raw[13]]),
+            setup: if raw[14] == 0 { Some(setup) } else { None },
+            data: &data[..captured_length.min(data.len())],
+        })
+    }
+
+    /// Checks if this packet travels from the device to the host.
+    pub fn is_in(&self) -> bool {
+        self.endpoint & ENDPOINT_DIRECTION_IN != 0
+    }
+
+    /// Gets the endpoint number, without the direction bit.
+    pub fn endpoint_number(&self) -> u8 {
+        self.endpoint & !ENDPOINT_DIRECTION_IN
+    }
+}
@@ -1,23 +1,55 @@
-use crate::{command, inertia, protocol, sensor, usb};
-use hmdee_core::{math, Error};
+use crate::{command, inertia, protocol, sensor, transport, wire};
+#[cfg(feature = "hidapi")] use crate::usb;
+use hmdee_core::{fusion, math, Error};
 
 use std;
-use hidapi;
-use na;
+#[cfg(feature = "hidapi")] use hidapi;
+
+/// The largest report the control interface sends.
+const CONTROL_REPORT_SIZE: usize = 64;
+/// How long to wait for a reply to `ReadDeviceInfo`.
+const DEVICE_INFO_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+/// How long to wait for the processor unit to acknowledge a command.
+const ACK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
 
 /// A PSVR device connected via USB.
 ///
-/// * `'a'` is the lifetime
+/// Use `split` to send commands from one thread while another thread
+/// reads the sensors.
 pub struct Psvr {
+    control: ControlHalf,
+    sensor: SensorHalf,
+}
+
+/// The half of a PSVR that sends commands and receives control reports.
+pub struct ControlHalf {
     /// The USB HID control interface.
-    control_device: hidapi::HidDevice,
+    control_device: Box<dyn transport::Transport>,
+}
+
+/// The half of a PSVR that reads the sensors and runs sensor fusion.
+pub struct SensorHalf {
     /// The USB HID sensor interface.
-    sensor_device: hidapi::HidDevice,
-    /// The inertia sensor.
-    inertia_sensor: inertia::Sensor,
+    sensor_device: Box<dyn transport::Transport>,
+    /// Sensor fusion.
+    fusion: fusion::Fusion,
+    /// Samples waiting to be fed to sensor fusion.
+    ///
+    /// This is kept around so that draining reports doesn't allocate.
+    pending_samples: Vec<fusion::ImuSample>,
+}
+
+/// Every sensor report that was waiting to be read.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SensorUpdate {
+    /// The newest readout.
+    pub readout: sensor::Readout,
+    /// The number of sensor frames read, including the newest.
+    pub frames: usize,
 }
 
 /// Get an iterator over all PSVRs on the system.
+#[cfg(feature = "hidapi")]
 pub fn iter(hidapi: &hidapi::HidApi) -> Result<Iter, Error> {
     Ok(Iter {
         psvr_infos: discover::all(hidapi)?.into_iter(),
@@ -26,11 +58,13 @@ pub fn iter(hidapi: &hidapi::HidApi) -> Result<Iter, Error> {
 }
 
 /// An iterator over PSVR USB devices.
+#[cfg(feature = "hidapi")]
 pub struct Iter<'a> {
     hidapi: &'a hidapi::HidApi,
     psvr_infos: std::vec::IntoIter<discover::PsvrInfo>,
 }
 
+#[cfg(feature = "hidapi")]
 impl<'a> Iterator for Iter<'a> {
     type Item = Result<Psvr, Error>;
 
@@ -47,6 +81,7 @@ impl<'a> Iterator for Iter<'a> {
 }
 
 /// Opens an arbitrary connected PSVR device.
+#[cfg(feature = "hidapi")]
 pub fn get(hidapi: &hidapi::HidApi)
     -> Result<Option<Psvr>, Error> {
     match iter(hidapi)?.next() {
@@ -57,6 +92,7 @@ pub fn get(hidapi: &hidapi::HidApi)
 
 impl Psvr {
     /// Connects to a discovered PSVR device.
+    #[cfg(feature = "hidapi")]
     fn connect<'a>(psvr_info: &discover::PsvrInfo,
                    hidapi: &'a hidapi::HidApi) -> Result<Self, Error> {
         let control_device_info = psvr_info.interface_device_info(usb::Interface::HidControl)
@@ -67,67 +103,325 @@ impl Psvr {
         let control_device = hidapi.open_path(&control_device_info.path()).map_err(Error::communication_error)?;
         let sensor_device = hidapi.open_path(&sensor_device_info.path()).map_err(Error::communication_error)?;
 
-        Ok(Psvr {
-            inertia_sensor: inertia::Sensor::new(),
-            control_device, sensor_device,
-        })
+        Ok(Psvr::from_transports(Box::new(control_device), Box::new(sensor_device)))
+    }
+
+    /// Creates a PSVR client that talks over arbitrary transports.
+    ///
+    /// This can be used to talk to an emulated processor unit.
+    pub fn from_transports(control_device: Box<dyn transport::Transport>,
+                           sensor_device: Box<dyn transport::Transport>) -> Self {
+        Psvr {
+            control: ControlHalf { control_device },
+            sensor: SensorHalf {
+                sensor_device,
+                fusion: inertia::fusion(),
+                pending_samples: Vec::new(),
+            },
+        }
+    }
+
+    /// Splits the PSVR into halves that can be used from different threads.
+    ///
+    /// A thread blocked waiting for sensor reports then doesn't stop
+    /// another thread from sending commands.
+    pub fn split(self) -> (SensorHalf, ControlHalf) {
+        (self.sensor, self.control)
     }
 
+    /// Joins the halves of a PSVR back together.
+    ///
+    /// The halves should come from the same `split`, otherwise commands
+    /// and sensor reports will go to different headsets.
+    pub fn rejoin(sensor: SensorHalf, control: ControlHalf) -> Self {
+        Psvr { control, sensor }
+    }
+
+    /// Gets the control half.
+    pub fn control(&self) -> &ControlHalf { &self.control }
+    /// Gets the control half.
+    pub fn control_mut(&mut self) -> &mut ControlHalf { &mut self.control }
+    /// Gets the sensor half.
+    pub fn sensor(&self) -> &SensorHalf { &self.sensor }
+    /// Gets the sensor half.
+    pub fn sensor_mut(&mut self) -> &mut SensorHalf { &mut self.sensor }
+
     /// Sends a command.
     pub fn send_command<C>(&mut self,
                            command: &C) -> Result<(), Error>
         where C: command::Command {
-        let payload = command.payload_bytes();
-
-        // Build command with specified ID and payload.
-        let command = protocol::Command {
-            header: protocol::CommandHeader {
-                id: C::ID,
-                magic: 0xAA,
-                status: 0,
-                length: payload.len() as u8,
-            },
-            payload: payload,
+        self.control.send_command(command)
+    }
+
+    /// Sends a command with an arbitrary ID and payload.
+    ///
+    /// See `ControlHalf::send_raw_command`.
+    pub fn send_raw_command(&mut self,
+                            id: u8,
+                            payload: &[u8]) -> Result<(), Error> {
+        self.control.send_raw_command(id, payload)
+    }
+
+    /// Sends a command and waits for the processor unit to accept it.
+    ///
+    /// This has only been verified against the emulator. See
+    /// `ControlHalf::send_command_acked`.
+    pub fn send_command_acked<C>(&mut self,
+                                 command: &C) -> Result<(), Error>
+        where C: command::Command {
+        self.control.send_command_acked(command)
+    }
+
+    /// Sends a command with an arbitrary ID and payload, and waits for the
+    /// processor unit to accept it.
+    ///
+    /// See `send_command_acked`.
+    pub fn send_raw_command_acked(&mut self,
+                                  id: u8,
+                                  payload: &[u8]) -> Result<(), Error> {
+        self.control.send_raw_command_acked(id, payload)
+    }
+
+    /// Receives a report from the control interface.
+    ///
+    /// Returns `None` if nothing arrives within the timeout.
+    pub fn receive_command(&mut self,
+                           timeout_milliseconds: i32) -> Result<Option<protocol::Command>, Error> {
+        self.control.receive_command(timeout_milliseconds)
+    }
+
+    /// Receives the next command with the given ID from the control interface.
+    ///
+    /// See `ControlHalf::receive_reply`.
+    pub fn receive_reply(&mut self,
+                         id: u8,
+                         timeout: std::time::Duration) -> Result<Option<protocol::Command>, Error> {
+        self.control.receive_reply(id, timeout)
+    }
+
+    /// Reads the device information from the processor unit.
+    pub fn read_device_info(&mut self) -> Result<command::DeviceInfo, Error> {
+        self.control.read_device_info()
+    }
+
+    /// Receives sensor data.
+    pub fn receive_sensor(&mut self) -> Result<sensor::Readout, Error> {
+        self.sensor.receive_sensor()
+    }
+
+    /// Receives every sensor report waiting to be read.
+    ///
+    /// See `SensorHalf::receive_all_sensors`.
+    pub fn receive_all_sensors(&mut self) -> Result<SensorUpdate, Error> {
+        self.sensor.receive_all_sensors()
+    }
+
+    /// Powers on the PSVR.
+    pub fn power_on(&mut self) -> Result<(), Error> {
+        self.control.power_on()
+    }
+
+    /// Powers off the PSVR.
+    pub fn power_off(&mut self) -> Result<(), Error> {
+        self.control.power_off()
+    }
+
+    /// Sets the state of the power.
+    pub fn set_power(&mut self, on: bool) -> Result<(), Error> {
+        self.control.set_power(on)
+    }
+
+    pub fn vr_mode(&mut self) -> Result<(), Error> {
+        self.control.vr_mode()
+    }
+
+    /// Switches between VR mode and cinematic mode.
+    pub fn set_vr_mode(&mut self, vr_mode: bool) -> Result<(), Error> {
+        self.control.set_vr_mode(vr_mode)
+    }
+
+    /// Enables VR tracking.
+    pub fn vr_tracking(&mut self) -> Result<(), Error> {
+        self.control.vr_tracking()
+    }
+
+    /// Powers off the PSVR and disconnects from it.
+    pub fn close(mut self) -> Result<(), Error> {
+        self.send_command(&command::SetPower { on: false }).map(|_| ())
+    }
+
+    /// Gets the orientation of the PSVR headset.
+    pub fn orientation(&self) -> math::Orientation {
+        self.sensor.orientation()
+    }
+
+    /// Checks if the headset is sitting still.
+    ///
+    /// See `hmdee_core::fusion::Fusion::is_stationary`.
+    pub fn is_stationary(&self) -> bool {
+        self.sensor.is_stationary()
+    }
+
+    /// Gets the current estimate of the gyroscope bias, in radians per second.
+    pub fn gyroscope_bias(&self) -> math::Vector3 {
+        self.sensor.gyroscope_bias()
+    }
+
+    /// Gets the angular velocity of the headset, in radians per second.
+    pub fn angular_velocity(&self, frame: math::Frame) -> Option<math::Vector3> {
+        self.sensor.angular_velocity(frame)
+    }
+
+    /// Gets the acceleration of the headset with gravity removed, in metres
+    /// per second squared.
+    pub fn linear_acceleration(&self, frame: math::Frame) -> Option<math::Vector3> {
+        self.sensor.linear_acceleration(frame)
+    }
+
+    /// Gets the orientation of the headset at a point in time.
+    ///
+    /// See `hmdee_core::fusion::Fusion::orientation_at`.
+    pub fn orientation_at(&self, time: std::time::Instant) -> Option<math::Orientation> {
+        self.sensor.orientation_at(time)
+    }
+}
+
+impl ControlHalf {
+    /// Sends a command.
+    pub fn send_command<C>(&mut self,
+                           command: &C) -> Result<(), Error>
+        where C: command::Command {
+        let mut buf = [0; protocol::MAX_COMMAND_SIZE];
+        let length = command.encode(0, &mut buf)?;
+
+        self.send_raw(&buf[..length])
+    }
+
+    /// Sends a command with an arbitrary ID and payload.
+    ///
+    /// This is useful for experimenting with commands this library
+    /// does not know about.
+    pub fn send_raw_command(&mut self,
+                            id: u8,
+                            payload: &[u8]) -> Result<(), Error> {
+        if payload.len() > protocol::MAX_PAYLOAD_SIZE {
+            return Err(wire::Error::PayloadTooLarge { id, length: payload.len() }.into());
+        }
+
+        let header = protocol::CommandHeader {
+            id,
+            magic: protocol::COMMAND_MAGIC,
+            status: 0,
+            length: payload.len() as u8,
         };
 
-        self.send_raw(&command.raw_bytes())
+        let mut buf = [0; protocol::MAX_COMMAND_SIZE];
+        let mut write = wire::Writer::new(&mut buf);
+        write.write_slice(&header.to_bytes())?;
+        write.write_slice(payload)?;
+        let length = write.position();
+
+        self.send_raw(&buf[..length])
+    }
+
+    /// Sends a command and waits for the processor unit to accept it.
+    ///
+    /// The reply is the report with the same ID as the command. Its status
+    /// byte is checked, and any status but success gives
+    /// `Error::CommandRejected`. Other reports that arrive first are dropped.
+    ///
+    /// This has only been verified against the emulator. No capture of a
+    /// real processor unit shows which commands it replies to, or what its
+    /// status bytes mean, so on a real unit this may wait out the timeout
+    /// and fail for a command that was carried out.
+    pub fn send_command_acked<C>(&mut self,
+                                 command: &C) -> Result<(), Error>
+        where C: command::Command {
+        self.send_command(command)?;
+        self.wait_for_ack(C::ID)
+    }
+
+    /// Sends a command with an arbitrary ID and payload, and waits for the
+    /// processor unit to accept it.
+    ///
+    /// See `send_command_acked`.
+    pub fn send_raw_command_acked(&mut self,
+                                  id: u8,
+                                  payload: &[u8]) -> Result<(), Error> {
+        self.send_raw_command(id, payload)?;
+        self.wait_for_ack(id)
+    }
+
+    /// Waits for the reply to a command and checks its status.
+    fn wait_for_ack(&mut self, id: u8) -> Result<(), Error> {
+        match self.receive_reply(id, ACK_TIMEOUT)? {
+            Some(reply) => reply.header.reply_status().check(id),
+            None => Err(Error::communication_error(format!("PSVR did not acknowledge command 0x{:02x}", id))),
+        }
     }
 
     /// Sends raw data.
     fn send_raw(&mut self,
                 data: &[u8]) -> Result<(), Error> {
-        self.control_device.write(&data.to_owned()).map_err(Error::communication_error)?;
+        self.control_device.write(data)?;
         Ok(())
     }
 
-    /// Receives sensor data.
-    pub fn receive_sensor(&mut self) -> Result<sensor::Readout, Error> {
-        use self::sensor::Readable;
+    /// Receives a report from the control interface.
+    ///
+    /// Returns `None` if nothing arrives within the timeout.
+    pub fn receive_command(&mut self,
+                           timeout_milliseconds: i32) -> Result<Option<protocol::Command>, Error> {
+        let mut buf = [0; CONTROL_REPORT_SIZE];
+        let bytes_read = self.control_device.read_timeout(&mut buf, timeout_milliseconds)?;
+
+        if bytes_read == 0 {
+            return Ok(None);
+        }
 
-        loop {
-            let mut buf: [u8; sensor::FRAME_SIZE] = [0; 64];
-            let bytes_read = self.sensor_device.read_timeout(&mut buf, 1).map_err(Error::communication_error)?;
-
-            if bytes_read <= 1 {
-                continue; // We need more than the report ID.
-            } if bytes_read != sensor::FRAME_SIZE {
-                return Err(Error::CommunicationError {
-                    message: format!("read psvr sensor frame of {} bytes but should be {} bytes", bytes_read, sensor::FRAME_SIZE),
-                });
+        Ok(Some(protocol::Command::parse(&buf[..bytes_read])?))
+    }
+
+    /// Receives a report from the control interface, if it is a command.
+    ///
+    /// Returns `None` if nothing arrives within the timeout, or if what does
+    /// isn't a command, so that waiting for a reply isn't cut short by other
+    /// reports sharing the interface.
+    fn receive_parsable_command(&mut self,
+                                timeout_milliseconds: i32) -> Result<Option<protocol::Command>, Error> {
+        let mut buf = [0; CONTROL_REPORT_SIZE];
+        let bytes_read = self.control_device.read_timeout(&mut buf, timeout_milliseconds)?;
+
+        Ok(protocol::Command::parse(&buf[..bytes_read]).ok()
+            .filter(|command| command.header.validate().is_ok()))
+    }
+
+    /// Receives the next command with the given ID from the control interface.
+    ///
+    /// Reports with other IDs, and reports that aren't commands, are dropped.
+    /// Returns `None` if no such command arrives within the timeout.
+    pub fn receive_reply(&mut self,
+                         id: u8,
+                         timeout: std::time::Duration) -> Result<Option<protocol::Command>, Error> {
+        let deadline = std::time::Instant::now() + timeout;
+        while let Some(remaining) = deadline.checked_duration_since(std::time::Instant::now()) {
+            if let Some(reply) = self.receive_parsable_command(remaining.as_millis() as i32)? {
+                if reply.header.id == id {
+                    return Ok(Some(reply));
+                }
             }
+        }
 
-            let readout = sensor::Readout::read_bytes(&buf)?;
+        Ok(None)
+    }
 
-            // FIXME: perhaps we should interpolate between the thingse
-            for instant in readout.instants.iter().take(1) {
-                let (g,a) = (instant.gyroscope(), instant.accelerometer());
+    /// Reads the device information from the processor unit.
+    pub fn read_device_info(&mut self) -> Result<command::DeviceInfo, Error> {
+        self.send_command(&command::ReadDeviceInfo)?;
 
-                self.inertia_sensor.update(&inertia::Instant {
-                    gyroscope: na::Vector3::new(g.x as _, g.y as _, g.z as _),
-                    accelerometer: na::Vector3::new(a.x as _, a.y as _, a.z as _),
-                });
-            }
-            return Ok(readout);
+        match self.receive_reply(<command::DeviceInfo as command::Command>::ID, DEVICE_INFO_TIMEOUT)? {
+            Some(reply) => Ok(command::Command::decode(&reply.payload)?),
+            None => Err(Error::communication_error("PSVR did not reply with its device information")),
         }
     }
 
@@ -147,25 +441,117 @@ impl Psvr {
     }
 
     pub fn vr_mode(&mut self) -> Result<(), Error> {
-        self.send_command(&command::SetVrMode { vr_mode: true })
+        self.set_vr_mode(true)
+    }
+
+    /// Switches between VR mode and cinematic mode.
+    pub fn set_vr_mode(&mut self, vr_mode: bool) -> Result<(), Error> {
+        self.send_command(&command::SetVrMode { vr_mode })
     }
 
     /// Enables VR trawcking.
     pub fn vr_tracking(&mut self) -> Result<(), Error> {
         self.send_command(&command::EnableVrTracking)
     }
+}
 
-    /// Powers off the PSVR and disconnects from it.
-    pub fn close(mut self) -> Result<(), Error> {
-        self.send_command(&command::SetPower { on: false }).map(|_| ())
+impl SensorHalf {
+    /// Receives sensor data.
+    pub fn receive_sensor(&mut self) -> Result<sensor::Readout, Error> {
+        let readout = self.wait_for_sensor_frame()?;
+
+        self.pending_samples.clear();
+        self.pending_samples.extend(readout.instants.iter().map(inertia::imu_sample));
+        self.fusion.update_batch_at(&self.pending_samples, self.sensor_device.now());
+        Ok(readout)
+    }
+
+    /// Receives every sensor report waiting to be read.
+    ///
+    /// This waits for at least one report, and then reads reports until
+    /// none are left. Every instant of every report is fed to sensor
+    /// fusion, so the orientation doesn't fall behind when reports are
+    /// read less often than the headset sends them.
+    pub fn receive_all_sensors(&mut self) -> Result<SensorUpdate, Error> {
+        let mut readout = self.wait_for_sensor_frame()?;
+        let mut frames = 1;
+
+        self.pending_samples.clear();
+        self.pending_samples.extend(readout.instants.iter().map(inertia::imu_sample));
+
+        while let Some(newer) = self.read_sensor_frame(0)? {
+            readout = newer;
+            frames += 1;
+            self.pending_samples.extend(readout.instants.iter().map(inertia::imu_sample));
+        }
+
+        self.fusion.update_batch_at(&self.pending_samples, self.sensor_device.now());
+        Ok(SensorUpdate { readout, frames })
+    }
+
+    /// Waits until a sensor frame arrives.
+    fn wait_for_sensor_frame(&mut self) -> Result<sensor::Readout, Error> {
+        loop {
+            if let Some(readout) = self.read_sensor_frame(1)? {
+                return Ok(readout);
+            }
+        }
+    }
+
+    /// Reads a sensor frame, if one arrives within the timeout.
+    fn read_sensor_frame(&mut self,
+                         timeout_milliseconds: i32) -> Result<Option<sensor::Readout>, Error> {
+        let mut buf = [0; sensor::FRAME_SIZE];
+        let bytes_read = self.sensor_device.read_timeout(&mut buf, timeout_milliseconds)?;
+
+        if bytes_read <= 1 {
+            return Ok(None); // We need more than the report ID.
+        } if bytes_read != sensor::FRAME_SIZE {
+            return Err(Error::CommunicationError {
+                message: format!("read psvr sensor frame of {} bytes but should be {} bytes", bytes_read, sensor::FRAME_SIZE),
+            });
+        }
+
+        Ok(Some(sensor::Readout::from_frame(&buf)))
     }
 
     /// Gets the orientation of the PSVR headset.
-    pub fn orientation(&self) -> math::Quaternion {
-        self.inertia_sensor.hmd_orientation()
+    pub fn orientation(&self) -> math::Orientation {
+        self.fusion.orientation()
+    }
+
+    /// Checks if the headset is sitting still.
+    ///
+    /// See `hmdee_core::fusion::Fusion::is_stationary`.
+    pub fn is_stationary(&self) -> bool {
+        self.fusion.is_stationary()
+    }
+
+    /// Gets the current estimate of the gyroscope bias, in radians per second.
+    pub fn gyroscope_bias(&self) -> math::Vector3 {
+        self.fusion.gyroscope_bias()
+    }
+
+    /// Gets the angular velocity of the headset, in radians per second.
+    pub fn angular_velocity(&self, frame: math::Frame) -> Option<math::Vector3> {
+        self.fusion.angular_velocity(frame)
+    }
+
+    /// Gets the acceleration of the headset with gravity removed, in metres
+    /// per second squared.
+    pub fn linear_acceleration(&self, frame: math::Frame) -> Option<math::Vector3> {
+        self.fusion.linear_acceleration(frame)
+    }
+
+    /// Gets the orientation of the headset at a point in time.
+    ///
+    /// See `hmdee_core::fusion::Fusion::orientation_at`.
+    pub fn orientation_at(&self, time: std::time::Instant) -> Option<math::Orientation> {
+        self.fusion.orientation_at(time)
     }
 }
 
+#[cfg(feature = "hidapi")]
 mod discover {
     use hmdee_core::Error;
     use crate::usb;
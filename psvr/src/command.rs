@@ -1,28 +1,71 @@
 //! The supported set of PSVR commands;
 
-use std::io::prelude::*;
-use std::io;
-
-use crate::usb::ByteOrder;
-use byteorder::{WriteBytesExt};
+use crate::{protocol, wire};
 
 /// A command that can be sent to the PSVR.
 pub trait Command {
     const ID: u8;
 
-    fn write_payload(&self, write: &mut dyn Write) -> io::Result<()>;
+    fn write_payload(&self, write: &mut wire::Writer) -> Result<(), wire::Error>;
+
+    /// Reads the command back from its payload.
+    fn read_payload(read: &mut wire::Reader) -> Result<Self, wire::Error> where Self: Sized;
+
+    /// Decodes a command, making sure the whole payload is used.
+    fn decode(payload: &[u8]) -> Result<Self, wire::Error> where Self: Sized {
+        let wrong_length = wire::Error::PayloadLength { id: Self::ID, length: payload.len() };
+        let mut read = wire::Reader::new(payload);
+        let command = Self::read_payload(&mut read).map_err(|_| wrong_length)?;
+
+        if read.remaining() != 0 {
+            return Err(wrong_length);
+        }
+
+        Ok(command)
+    }
+
+    /// Encodes the command, along with its header, into a buffer.
+    ///
+    /// Returns the number of bytes used.
+    fn encode(&self, status: u8, buf: &mut [u8]) -> Result<usize, wire::Error> {
+        if buf.len() < protocol::COMMAND_HEADER_SIZE {
+            return Err(wire::Error::UnexpectedEnd { needed: protocol::COMMAND_HEADER_SIZE, available: buf.len() });
+        }
+        let (header, payload) = buf.split_at_mut(protocol::COMMAND_HEADER_SIZE);
+
+        let mut write = wire::Writer::new(payload);
+        self.write_payload(&mut write)?;
+        let length = write.position();
+
+        if length > protocol::MAX_PAYLOAD_SIZE {
+            return Err(wire::Error::PayloadTooLarge { id: Self::ID, length });
+        }
+
+        header.copy_from_slice(&protocol::CommandHeader {
+            id: Self::ID,
+            status,
+            magic: protocol::COMMAND_MAGIC,
+            length: length as u8,
+        }.to_bytes());
+
+        Ok(protocol::COMMAND_HEADER_SIZE + length)
+    }
 
     /// Gets the raw bytes that make up the payload.
+    #[cfg(feature = "std")]
     fn payload_bytes(&self) -> Vec<u8> {
-        let mut buffer = io::Cursor::new(Vec::new());
+        let mut buffer = [0; protocol::MAX_PAYLOAD_SIZE];
+        let mut write = wire::Writer::new(&mut buffer);
 
-        self.write_payload(&mut buffer).expect("encountered IO error while writing to memory");
-        buffer.into_inner()
+        self.write_payload(&mut write).expect("command payload is too large");
+        let length = write.position();
+        buffer[..length].to_vec()
     }
 }
 
 /// Tells the PSVR to turn power off or on.
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct SetPower {
     /// `1` for on, `0` for off.
     pub on: bool,
@@ -31,10 +74,12 @@ pub struct SetPower {
 /// Enables VR tracking.
 // NOTE: This command can probably be generalised to a 'set tracking on/off' command somehow.
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct EnableVrTracking;
 
 /// Enables or disables VR mode.
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct SetVrMode {
     /// Whether VR mode is disabled.
     pub vr_mode: bool,
@@ -42,10 +87,12 @@ pub struct SetVrMode {
 
 /// Turns the black box off.
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct BoxOff;
 
 /// Sets the cinematic configuration.
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct SetCinematicConfiguration {
     pub mask: u8,
     pub screen_size: u8,
@@ -61,6 +108,7 @@ pub struct SetCinematicConfiguration {
 
 /// Sets the state of a LED on the HMD.
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct SetHmdLeds {
     pub led_mask: u16,
     pub values: [u8; 9],
@@ -68,77 +116,290 @@ pub struct SetHmdLeds {
 }
 
 /// Reads defice information.
+///
+/// The processor unit replies with a `DeviceInfo`.
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct ReadDeviceInfo;
 
+/// Device information, sent by the processor unit in reply to `ReadDeviceInfo`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DeviceInfo {
+    pub reserved0: [u8; 7],
+    pub firmware_version_minor: u8,
+    pub firmware_version_major: u8,
+    pub reserved1: [u8; 3],
+    /// The serial number, as ASCII padded with nul bytes.
+    pub serial_number: [u8; 16],
+    pub reserved2: [u8; 20],
+}
+
+#[cfg(feature = "std")]
+macro_rules! any_command {
+    ( $( $command:ident ),* ) => {
+        /// Any command, known or unknown.
+        ///
+        /// Replies from the processor unit share the command format, and so
+        /// can also be represented.
+        #[derive(Clone, Debug, PartialEq, Eq)]
+        #[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+        pub enum AnyCommand {
+            $( $command($command), )*
+            /// A command this library does not know about.
+            Unknown {
+                id: u8,
+                payload: Vec<u8>,
+            },
+        }
+
+        impl AnyCommand {
+            /// Decodes a command from its ID and payload.
+            ///
+            /// Known commands must have exactly the payload they are written with.
+            pub fn decode(id: u8, payload: &[u8]) -> Result<Self, wire::Error> {
+                match id {
+                    $( $command::ID => $command::decode(payload).map(AnyCommand::$command), )*
+                    _ => Ok(AnyCommand::Unknown { id, payload: payload.to_owned() }),
+                }
+            }
+
+            /// Gets the ID of the command.
+            pub fn id(&self) -> u8 {
+                match *self {
+                    $( AnyCommand::$command(..) => $command::ID, )*
+                    AnyCommand::Unknown { id, .. } => id,
+                }
+            }
+
+            /// Gets the raw bytes that make up the payload.
+            pub fn payload_bytes(&self) -> Vec<u8> {
+                match *self {
+                    $( AnyCommand::$command(ref command) => command.payload_bytes(), )*
+                    AnyCommand::Unknown { ref payload, .. } => payload.clone(),
+                }
+            }
+        }
+
+        $(
+            impl From<$command> for AnyCommand {
+                fn from(command: $command) -> Self { AnyCommand::$command(command) }
+            }
+        )*
+    };
+}
+
+#[cfg(feature = "std")]
+any_command!(SetPower, EnableVrTracking, SetVrMode, BoxOff, SetCinematicConfiguration,
+             SetHmdLeds, ReadDeviceInfo, DeviceInfo);
+
+#[cfg(feature = "std")]
+impl AnyCommand {
+    /// Parses a header and command from raw bytes.
+    ///
+    /// This validates the header, and ignores any bytes after the length
+    /// given in the header.
+    pub fn parse(raw: &[u8]) -> Result<(protocol::CommandHeader, Self), wire::Error> {
+        let command = protocol::Command::parse(raw)?;
+        command.header.validate()?;
+        let any_command = AnyCommand::from_command(&command)?;
+
+        Ok((command.header, any_command))
+    }
+
+    /// Decodes a command from its low-level representation.
+    pub fn from_command(command: &protocol::Command) -> Result<Self, wire::Error> {
+        AnyCommand::decode(command.header.id, &command.payload)
+    }
+
+    /// Builds the low-level representation of the command.
+    ///
+    /// Fails if the payload of an unknown command is too long for its
+    /// length to fit in the header.
+    pub fn to_command(&self, status: u8) -> Result<protocol::Command, wire::Error> {
+        let payload = self.payload_bytes();
+        if payload.len() > protocol::MAX_PAYLOAD_SIZE {
+            return Err(wire::Error::PayloadTooLarge { id: self.id(), length: payload.len() });
+        }
+
+        Ok(protocol::Command {
+            header: protocol::CommandHeader {
+                id: self.id(),
+                status,
+                magic: protocol::COMMAND_MAGIC,
+                length: payload.len() as u8,
+            },
+            payload,
+        })
+    }
+}
+
 impl Command for SetPower {
     const ID: u8 = 0x17;
 
-    fn write_payload(&self, write: &mut dyn Write) -> io::Result<()> {
-        write.write_u32::<ByteOrder>(if self.on { 1 } else { 0 })
+    fn write_payload(&self, write: &mut wire::Writer) -> Result<(), wire::Error> {
+        write.write_u32(if self.on { 1 } else { 0 })
+    }
+
+    fn read_payload(read: &mut wire::Reader) -> Result<Self, wire::Error> {
+        Ok(SetPower { on: read.read_u32()? != 0 })
     }
 }
 
 impl Command for EnableVrTracking {
     const ID: u8 = 0x11;
 
-    fn write_payload(&self, write: &mut dyn Write) -> io::Result<()> {
-        write.write_u32::<ByteOrder>(0xFFFFFF00)?;
-        write.write_u32::<ByteOrder>(0x00000000)?;
+    fn write_payload(&self, write: &mut wire::Writer) -> Result<(), wire::Error> {
+        write.write_u32(0xFFFFFF00)?;
+        write.write_u32(0x00000000)?;
         Ok(())
     }
+
+    fn read_payload(read: &mut wire::Reader) -> Result<Self, wire::Error> {
+        read.read_u32()?;
+        read.read_u32()?;
+        Ok(EnableVrTracking)
+    }
 }
 
 impl Command for SetVrMode {
     const ID: u8 = 0x23;
 
-    fn write_payload(&self, write: &mut dyn Write) -> io::Result<()> {
-        write.write_u32::<ByteOrder>(if self.vr_mode { 1 } else { 0 })
+    fn write_payload(&self, write: &mut wire::Writer) -> Result<(), wire::Error> {
+        write.write_u32(if self.vr_mode { 1 } else { 0 })
+    }
+
+    fn read_payload(read: &mut wire::Reader) -> Result<Self, wire::Error> {
+        Ok(SetVrMode { vr_mode: read.read_u32()? != 0 })
     }
 }
 
 impl Command for BoxOff {
     const ID: u8 = 0x13;
 
-    fn write_payload(&self, write: &mut dyn Write) -> io::Result<()> {
-        write.write_u32::<ByteOrder>(1)
+    fn write_payload(&self, write: &mut wire::Writer) -> Result<(), wire::Error> {
+        write.write_u32(1)
+    }
+
+    fn read_payload(read: &mut wire::Reader) -> Result<Self, wire::Error> {
+        read.read_u32()?;
+        Ok(BoxOff)
     }
 }
 
 impl Command for SetCinematicConfiguration {
     const ID: u8 = 0x21;
 
-    fn write_payload(&self, write: &mut dyn Write) -> io::Result<()> {
+    fn write_payload(&self, write: &mut wire::Writer) -> Result<(), wire::Error> {
         write.write_u8(self.mask)?;
         write.write_u8(self.screen_size)?;
         write.write_u8(self.screen_distance)?;
         write.write_u8(self.ipd)?;
-        write.write_all(&self.reserved0)?;
+        write.write_slice(&self.reserved0)?;
         write.write_u8(self.brightness)?;
         write.write_u8(self.mic_volume)?;
-        write.write_all(&self.reserved1)?;
+        write.write_slice(&self.reserved1)?;
         write.write_u8(if self.unknown { 1 } else { 0 })?;
         write.write_u8(self.reserved2)
     }
+
+    fn read_payload(read: &mut wire::Reader) -> Result<Self, wire::Error> {
+        let mask = read.read_u8()?;
+        let screen_size = read.read_u8()?;
+        let screen_distance = read.read_u8()?;
+        let ipd = read.read_u8()?;
+        let reserved0: [u8; 6] = read.read_array()?;
+        let brightness = read.read_u8()?;
+        let mic_volume = read.read_u8()?;
+        let reserved1: [u8; 2] = read.read_array()?;
+        let unknown = read.read_u8()? != 0;
+        let reserved2 = read.read_u8()?;
+
+        Ok(SetCinematicConfiguration {
+            mask, screen_size, screen_distance, ipd, reserved0,
+            brightness, mic_volume, reserved1, unknown, reserved2,
+        })
+    }
 }
 
 impl Command for SetHmdLeds {
     const ID: u8 = 0x15;
 
-    fn write_payload(&self, write: &mut dyn Write) -> io::Result<()> {
-        write.write_u16::<ByteOrder>(self.led_mask)?;
-        write.write_all(&self.values)?;
-        write.write_all(&self.reserved)
+    fn write_payload(&self, write: &mut wire::Writer) -> Result<(), wire::Error> {
+        write.write_u16(self.led_mask)?;
+        write.write_slice(&self.values)?;
+        write.write_slice(&self.reserved)
+    }
+
+    fn read_payload(read: &mut wire::Reader) -> Result<Self, wire::Error> {
+        let led_mask = read.read_u16()?;
+        let values: [u8; 9] = read.read_array()?;
+        let reserved: [u8; 5] = read.read_array()?;
+
+        Ok(SetHmdLeds { led_mask, values, reserved })
     }
 }
 
 impl Command for ReadDeviceInfo {
     const ID: u8 = 0x81;
 
-    fn write_payload(&self, write: &mut dyn Write) -> io::Result<()> {
+    fn write_payload(&self, write: &mut wire::Writer) -> Result<(), wire::Error> {
         let reserved: [u8; 7] = [0; 7];
         write.write_u8(0x80)?;
-        write.write_all(&reserved)
+        write.write_slice(&reserved)
+    }
+
+    fn read_payload(read: &mut wire::Reader) -> Result<Self, wire::Error> {
+        read.skip(8)?;
+        Ok(ReadDeviceInfo)
+    }
+}
+
+impl Command for DeviceInfo {
+    const ID: u8 = 0x80;
+
+    fn write_payload(&self, write: &mut wire::Writer) -> Result<(), wire::Error> {
+        write.write_slice(&self.reserved0)?;
+        write.write_u8(self.firmware_version_minor)?;
+        write.write_u8(self.firmware_version_major)?;
+        write.write_slice(&self.reserved1)?;
+        write.write_slice(&self.serial_number)?;
+        write.write_slice(&self.reserved2)
+    }
+
+    fn read_payload(read: &mut wire::Reader) -> Result<Self, wire::Error> {
+        let reserved0: [u8; 7] = read.read_array()?;
+        let firmware_version_minor = read.read_u8()?;
+        let firmware_version_major = read.read_u8()?;
+        let reserved1: [u8; 3] = read.read_array()?;
+        let serial_number: [u8; 16] = read.read_array()?;
+        let reserved2: [u8; 20] = read.read_array()?;
+
+        Ok(DeviceInfo {
+            reserved0, firmware_version_minor, firmware_version_major,
+            reserved1, serial_number, reserved2,
+        })
+    }
+}
+
+impl SetCinematicConfiguration {
+    /// The mask bit that applies `screen_size`.
+    pub const MASK_SCREEN_SIZE: u8 = 1 << 1;
+    /// The mask bit that applies `screen_distance`.
+    pub const MASK_SCREEN_DISTANCE: u8 = 1 << 2;
+    /// The mask bit that applies `ipd`.
+    pub const MASK_IPD: u8 = 1 << 3;
+    /// The mask bit that applies `brightness`.
+    pub const MASK_BRIGHTNESS: u8 = 1 << 6;
+    /// The mask bit that applies `mic_volume`.
+    pub const MASK_MIC_VOLUME: u8 = 1 << 7;
+}
+
+#[cfg(feature = "std")]
+impl DeviceInfo {
+    /// Gets the serial number as a string.
+    pub fn serial_number(&self) -> String {
+        String::from_utf8_lossy(&self.serial_number).trim_end_matches('\0').to_owned()
     }
 }
 
@@ -174,5 +435,71 @@ mod invariants {
 
         assert_eq!(16, c.payload_bytes().len());
     }
+
+    #[test]
+    fn any_command_round_trips() {
+        let commands: Vec<AnyCommand> = vec![
+            SetPower { on: true }.into(),
+            EnableVrTracking.into(),
+            SetVrMode { vr_mode: false }.into(),
+            BoxOff.into(),
+            SetCinematicConfiguration {
+                mask: 0x3f, screen_size: 40, screen_distance: 50, ipd: 63,
+                reserved0: [1, 2, 3, 4, 5, 6], brightness: 20, mic_volume: 5,
+                reserved1: [7, 8], unknown: true, reserved2: 9,
+            }.into(),
+            SetHmdLeds { led_mask: 0x1ff, values: [100; 9], reserved: [0; 5] }.into(),
+            ReadDeviceInfo.into(),
+            DeviceInfo {
+                reserved0: [0; 7], firmware_version_minor: 0x12, firmware_version_major: 0x03,
+                reserved1: [0; 3], serial_number: *b"0123456789ABCDEF", reserved2: [0; 20],
+            }.into(),
+            AnyCommand::Unknown { id: 0x1b, payload: vec![1, 2, 3] },
+        ];
+
+        for command in commands {
+            let raw = command.to_command(0).unwrap().raw_bytes();
+            let (header, decoded) = AnyCommand::parse(&raw).expect("failed to parse command");
+
+            assert_eq!(command.id(), header.id);
+            assert_eq!(command, decoded);
+        }
+    }
+
+    #[test]
+    fn encode_matches_command_bytes() {
+        let command = SetHmdLeds { led_mask: 0x1ff, values: [100; 9], reserved: [0; 5] };
+        let mut buf = [0; 64];
+        let length = command.encode(3, &mut buf).unwrap();
+
+        assert_eq!(&AnyCommand::from(command.clone()).to_command(3).unwrap().raw_bytes()[..], &buf[..length]);
+        assert_eq!(Ok(command), SetHmdLeds::decode(&buf[protocol::COMMAND_HEADER_SIZE..length]));
+        assert!(SetPower { on: true }.encode(0, &mut buf[..6]).is_err());
+    }
+
+    #[test]
+    fn to_command_rejects_payloads_too_long_for_the_header() {
+        let command = AnyCommand::Unknown { id: 0x1b, payload: vec![0; protocol::MAX_PAYLOAD_SIZE + 1] };
+        assert_eq!(Err(wire::Error::PayloadTooLarge { id: 0x1b, length: 256 }), command.to_command(0));
+
+        let command = AnyCommand::Unknown { id: 0x1b, payload: vec![0; protocol::MAX_PAYLOAD_SIZE] };
+        assert_eq!(255, command.to_command(0).unwrap().header.length);
+    }
+
+    #[test]
+    fn any_command_rejects_wrong_payload_length() {
+        assert!(AnyCommand::decode(SetPower::ID, &[1, 0, 0]).is_err());
+        assert!(AnyCommand::decode(SetPower::ID, &[1, 0, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn any_command_rejects_bad_magic() {
+        assert_eq!(Err(wire::Error::BadMagic { id: BoxOff::ID, magic: 0 }), AnyCommand::parse(&[BoxOff::ID, 0, 0, 0]));
+    }
+
+    #[test]
+    fn any_command_rejects_length_past_end() {
+        assert!(AnyCommand::parse(&[SetPower::ID, 0, protocol::COMMAND_MAGIC, 4, 1, 0]).is_err());
+    }
 }
 
@@ -0,0 +1,715 @@
+//! An emulated PSVR processor unit.
+//!
+//! The emulator understands the same control commands as the real processor
+//! unit, keeps track of the power, VR, cinematic, and LED state they change,
+//! and generates sensor reports from a scripted head motion timeline.
+//!
+//! This allows a `Psvr` to be exercised end-to-end without a headset.
+//!
+//! ```
+//! use psvr::emulator::{Emulator, Timeline};
+//!
+//! let emulator = Emulator::new(Timeline::at_rest());
+//! let mut psvr = emulator.connect();
+//!
+//! psvr.power_on().unwrap();
+//! assert!(emulator.state().powered);
+//! ```
+
+use crate::{command, protocol, sensor, transport, wire, Psvr};
+use crate::sensor::Writable;
+use hmdee_core::Error;
+use hmdee_core::math::{Scalar, Vector3};
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{self, Duration};
+use na::UnitQuaternion;
+
+/// How often the emulated processor unit sends a sensor report.
+pub const FRAME_PERIOD: Duration = Duration::from_millis(2);
+/// How many control replies are kept before the oldest is dropped, like a
+/// HID input buffer that nobody reads.
+const MAX_QUEUED_REPLIES: usize = 32;
+
+/// The status the emulator replies with to a command ID it doesn't know.
+///
+/// What a real processor unit replies with isn't known.
+pub const STATUS_UNKNOWN_COMMAND: u8 = 0x01;
+/// The status the emulator replies with to a command whose payload is the
+/// wrong size.
+pub const STATUS_BAD_LENGTH: u8 = 0x02;
+
+/// How sensor reports are paced.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Pacing {
+    /// Reports are sent at the same rate as the real processor unit.
+    ///
+    /// Readers wait for each report, so sensor fusion sees realistic timings.
+    RealTime,
+    /// Reports are sent as fast as they are read.
+    ///
+    /// The emulator keeps its own clock rather than using the system's.
+    /// Reads that wait move it straight on to the next report, and
+    /// `Emulator::advance` moves it on as if time had passed. Reports that
+    /// fall due in between wait to be read, so draining them can be tested
+    /// without sleeping.
+    Unpaced,
+}
+
+/// The state of the emulated processor unit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct State {
+    /// Whether the headset is powered on.
+    pub powered: bool,
+    /// Whether the headset is in VR mode, rather than cinematic mode.
+    pub vr_mode: bool,
+    /// Whether VR tracking has been enabled.
+    pub vr_tracking: bool,
+    /// The cinematic mode configuration.
+    ///
+    /// The mask is not used, only the values it has applied.
+    pub cinematic: command::SetCinematicConfiguration,
+    /// The brightness of each LED.
+    pub leds: [u8; 9],
+    /// The information sent in reply to `ReadDeviceInfo`.
+    pub device_info: command::DeviceInfo,
+    /// The buttons being pressed.
+    pub buttons: sensor::Buttons,
+    /// The audio volume.
+    pub volume: u8,
+    /// Whether the headset is being worn.
+    pub worn: bool,
+    /// Whether the microphone is muted.
+    pub microphone_muted: bool,
+    /// Whether headphones are plugged in.
+    pub headphone_connected: bool,
+    /// Every command received, in order.
+    pub commands: Vec<command::AnyCommand>,
+}
+
+/// A scripted head motion.
+///
+/// The head rotates at a constant rate between keyframes, and holds the
+/// orientation of the last keyframe forever after it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Timeline {
+    /// Keyframes in time order.
+    keyframes: Vec<Keyframe>,
+}
+
+/// A head orientation at a point in time.
+type Keyframe = (Duration, UnitQuaternion<Scalar>);
+
+/// An emulated PSVR processor unit.
+///
+/// Cloning an emulator gives another handle to the same processor unit.
+#[derive(Clone)]
+pub struct Emulator {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    inner: Mutex<Inner>,
+    /// Signalled when a reply is queued on the control interface.
+    reply_queued: Condvar,
+}
+
+struct Inner {
+    state: State,
+    timeline: Timeline,
+    pacing: Pacing,
+    /// When the timeline started.
+    started: time::Instant,
+    /// The timeline time of the next sensor report.
+    next_frame: Duration,
+    /// The timeline time by the emulator's own clock, when unpaced.
+    clock: Duration,
+    /// The status tick bit, which flips every report.
+    tick: bool,
+    /// Replies waiting to be read from the control interface.
+    replies: VecDeque<Vec<u8>>,
+}
+
+/// The control interface of an emulated processor unit.
+struct ControlTransport {
+    shared: Arc<Shared>,
+}
+
+/// The sensor interface of an emulated processor unit.
+struct SensorTransport {
+    shared: Arc<Shared>,
+}
+
+impl Emulator {
+    /// Creates a new emulated processor unit, powered off, which will
+    /// follow the given head motion.
+    pub fn new(timeline: Timeline) -> Self {
+        Emulator {
+            shared: Arc::new(Shared {
+                inner: Mutex::new(Inner {
+                    state: State::default(),
+                    timeline,
+                    pacing: Pacing::RealTime,
+                    started: time::Instant::now(),
+                    next_frame: Duration::from_secs(0),
+                    clock: Duration::from_secs(0),
+                    tick: false,
+                    replies: VecDeque::new(),
+                }),
+                reply_queued: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Sets how sensor reports are paced.
+    pub fn set_pacing(&self, pacing: Pacing) {
+        self.lock().pacing = pacing;
+    }
+
+    /// Moves the clock of an unpaced emulator forward, as if the sensors
+    /// weren't read for `duration`.
+    ///
+    /// This has no effect on real time pacing.
+    pub fn advance(&self, duration: Duration) {
+        self.lock().clock += duration;
+    }
+
+    /// Gets a snapshot of the processor unit state.
+    pub fn state(&self) -> State {
+        self.lock().state.clone()
+    }
+
+    /// Changes the processor unit state.
+    ///
+    /// This can be used to press buttons, or put the headset on.
+    pub fn update_state<F>(&self, f: F)
+        where F: FnOnce(&mut State) {
+        f(&mut self.lock().state)
+    }
+
+    /// Gets a transport for the HID control interface.
+    pub fn control_transport(&self) -> Box<dyn transport::Transport> {
+        Box::new(ControlTransport { shared: self.shared.clone() })
+    }
+
+    /// Gets a transport for the HID sensor interface.
+    pub fn sensor_transport(&self) -> Box<dyn transport::Transport> {
+        Box::new(SensorTransport { shared: self.shared.clone() })
+    }
+
+    /// Connects a PSVR client to the emulated processor unit.
+    pub fn connect(&self) -> Psvr {
+        Psvr::from_transports(self.control_transport(), self.sensor_transport())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.shared.lock()
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        // A panic while holding the lock cannot leave the state half-updated
+        // in a way that matters to an emulator.
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl State {
+    /// Applies a command to the state.
+    ///
+    /// Returns the reply the processor unit sends, if any.
+    fn apply(&mut self, command: &command::AnyCommand) -> Option<command::AnyCommand> {
+        use crate::command::AnyCommand;
+
+        self.commands.push(command.clone());
+
+        match *command {
+            AnyCommand::SetPower(ref c) => self.powered = c.on,
+            AnyCommand::EnableVrTracking(..) => self.vr_tracking = true,
+            AnyCommand::SetVrMode(ref c) => self.vr_mode = c.vr_mode,
+            AnyCommand::BoxOff(..) => {
+                self.powered = false;
+                self.vr_tracking = false;
+            },
+            AnyCommand::SetCinematicConfiguration(ref c) => {
+                use crate::command::SetCinematicConfiguration as Config;
+
+                if c.mask & Config::MASK_SCREEN_SIZE != 0 { self.cinematic.screen_size = c.screen_size }
+                if c.mask & Config::MASK_SCREEN_DISTANCE != 0 { self.cinematic.screen_distance = c.screen_distance }
+                if c.mask & Config::MASK_IPD != 0 { self.cinematic.ipd = c.ipd }
+                if c.mask & Config::MASK_BRIGHTNESS != 0 { self.cinematic.brightness = c.brightness }
+                if c.mask & Config::MASK_MIC_VOLUME != 0 { self.cinematic.mic_volume = c.mic_volume }
+            },
+            AnyCommand::SetHmdLeds(ref c) => {
+                for (i, led) in self.leds.iter_mut().enumerate() {
+                    if c.led_mask & (1 << i) != 0 {
+                        *led = c.values[i];
+                    }
+                }
+            },
+            AnyCommand::ReadDeviceInfo(..) => return Some(self.device_info.clone().into()),
+            // The processor unit only sends device information, it doesn't receive it.
+            AnyCommand::DeviceInfo(..) | AnyCommand::Unknown { .. } => (),
+        }
+
+        None
+    }
+}
+
+impl Inner {
+    /// Queues a report on the control interface.
+    fn queue_reply(&mut self, reply: Vec<u8>) {
+        if self.replies.len() == MAX_QUEUED_REPLIES {
+            self.replies.pop_front();
+        }
+        self.replies.push_back(reply);
+    }
+
+    /// Generates the next sensor report.
+    fn next_readout(&mut self) -> sensor::Readout {
+        let frame_time = self.next_frame;
+        self.next_frame += FRAME_PERIOD;
+        self.tick = !self.tick;
+
+        let instants = [
+            self.timeline.instant_at(frame_time),
+            self.timeline.instant_at(frame_time + FRAME_PERIOD / 2),
+        ];
+
+        sensor::Readout {
+            buttons: self.state.buttons,
+            volume: self.state.volume,
+            status: sensor::Status {
+                worn: self.state.worn,
+                display_active: self.state.powered,
+                hdmi_disconnected: false,
+                microphone_muted: self.state.microphone_muted,
+                headphone_connected: self.state.headphone_connected,
+                tick: self.tick,
+            },
+            instants,
+        }
+    }
+}
+
+impl transport::Transport for ControlTransport {
+    fn write(&mut self, data: &[u8]) -> Result<usize, Error> {
+        let raw = protocol::Command::parse(data)?;
+        raw.header.validate()?;
+        let mut inner = self.shared.lock();
+
+        let (status, reply) = match command::AnyCommand::from_command(&raw) {
+            Ok(command) => {
+                let reply = inner.state.apply(&command);
+                match command {
+                    command::AnyCommand::Unknown { .. } => (STATUS_UNKNOWN_COMMAND, reply),
+                    _ => (protocol::STATUS_SUCCESS, reply),
+                }
+            },
+            Err(wire::Error::PayloadLength { .. }) => (STATUS_BAD_LENGTH, None),
+            Err(e) => return Err(e.into()),
+        };
+
+        // Every command is acknowledged with a report of the same ID, before
+        // any reply that carries data. This is the emulator's own convention,
+        // which `send_command_acked` relies on; no capture of a real unit
+        // shows which commands it acknowledges.
+        let ack = protocol::CommandHeader { id: raw.header.id, status, magic: protocol::COMMAND_MAGIC, length: 0 };
+        inner.queue_reply(ack.raw_bytes());
+        if let Some(reply) = reply {
+            inner.queue_reply(reply.to_command(0)?.raw_bytes());
+        }
+        self.shared.reply_queued.notify_all();
+
+        Ok(data.len())
+    }
+
+    fn read_timeout(&mut self, buf: &mut [u8], timeout_milliseconds: i32) -> Result<usize, Error> {
+        let mut inner = self.shared.lock();
+        let deadline = if timeout_milliseconds < 0 {
+            None
+        } else {
+            Some(time::Instant::now() + Duration::from_millis(timeout_milliseconds as u64))
+        };
+
+        loop {
+            if let Some(reply) = inner.replies.pop_front() {
+                let length = reply.len().min(buf.len());
+                buf[..length].copy_from_slice(&reply[..length]);
+                return Ok(length);
+            }
+
+            inner = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(time::Instant::now()) {
+                    Some(remaining) => self.shared.reply_queued.wait_timeout(inner, remaining)
+                        .unwrap_or_else(|e| e.into_inner()).0,
+                    None => return Ok(0),
+                },
+                None => self.shared.reply_queued.wait(inner).unwrap_or_else(|e| e.into_inner()),
+            };
+        }
+    }
+}
+
+impl transport::Transport for SensorTransport {
+    fn write(&mut self, _: &[u8]) -> Result<usize, Error> {
+        Err(Error::communication_error("the PSVR sensor interface does not accept writes"))
+    }
+
+    fn read_timeout(&mut self, buf: &mut [u8], timeout_milliseconds: i32) -> Result<usize, Error> {
+        let due = {
+            let mut inner = self.shared.lock();
+            match inner.pacing {
+                Pacing::RealTime => Some(inner.started + inner.next_frame),
+                Pacing::Unpaced if inner.next_frame <= inner.clock => None,
+                Pacing::Unpaced if timeout_milliseconds == 0 => return Ok(0),
+                Pacing::Unpaced => {
+                    inner.clock = inner.next_frame;
+                    None
+                },
+            }
+        };
+
+        if let Some(wait) = due.and_then(|due| due.checked_duration_since(time::Instant::now())) {
+            if timeout_milliseconds >= 0 && wait > Duration::from_millis(timeout_milliseconds as u64) {
+                std::thread::sleep(Duration::from_millis(timeout_milliseconds as u64));
+                return Ok(0);
+            }
+            std::thread::sleep(wait);
+        }
+
+        let frame = self.shared.lock().next_readout().write_bytes();
+        let length = frame.len().min(buf.len());
+        buf[..length].copy_from_slice(&frame[..length]);
+        Ok(length)
+    }
+
+    fn now(&self) -> time::Instant {
+        let inner = self.shared.lock();
+        match inner.pacing {
+            Pacing::RealTime => time::Instant::now(),
+            Pacing::Unpaced => inner.started + inner.clock,
+        }
+    }
+}
+
+impl Timeline {
+    /// Creates a timeline that starts at an orientation.
+    pub fn new(orientation: UnitQuaternion<Scalar>) -> Self {
+        Timeline { keyframes: vec![(Duration::from_secs(0), orientation)] }
+    }
+
+    /// Creates a timeline where the head never moves.
+    pub fn at_rest() -> Self {
+        Timeline::new(UnitQuaternion::identity())
+    }
+
+    /// Rotates the head to an orientation over a period of time.
+    pub fn then_rotate_to(mut self, duration: Duration, orientation: UnitQuaternion<Scalar>) -> Self {
+        let end = self.end() + duration;
+        self.keyframes.push((end, orientation));
+        self
+    }
+
+    /// Holds the head still for a period of time.
+    pub fn then_hold(self, duration: Duration) -> Self {
+        let orientation = self.keyframes[self.keyframes.len() - 1].1;
+        self.then_rotate_to(duration, orientation)
+    }
+
+    /// Gets the time of the last keyframe.
+    pub fn end(&self) -> Duration {
+        self.keyframes[self.keyframes.len() - 1].0
+    }
+
+    /// Gets the head orientation at a point in time.
+    pub fn orientation_at(&self, time: Duration) -> UnitQuaternion<Scalar> {
+        match self.segment_at(time) {
+            Some((start, end)) => {
+                let t = (time - start.0).as_secs_f32() / (end.0 - start.0).as_secs_f32();
+                start.1.slerp(&end.1, t)
+            },
+            None => self.keyframes.iter().rev().find(|k| k.0 <= time).unwrap_or(&self.keyframes[0]).1,
+        }
+    }
+
+    /// Gets the angular velocity of the head at a point in time, in radians
+    /// per second, in the head's frame of reference.
+    pub fn angular_velocity_at(&self, time: Duration) -> Vector3 {
+        match self.segment_at(time) {
+            Some((start, end)) => {
+                let rotation = start.1.inverse() * end.1;
+                rotation.scaled_axis() / (end.0 - start.0).as_secs_f32()
+            },
+            None => Vector3::zeros(),
+        }
+    }
+
+    /// Gets what the inertia sensor would read at a point in time.
+    pub fn instant_at(&self, time: Duration) -> sensor::InertiaInstant {
+        // At rest, the accelerometer reads 1g upwards.
+        let gravity = self.orientation_at(time).inverse() * Vector3::z();
+
+        sensor::InertiaInstant::from_vectors(&self.angular_velocity_at(time), &gravity)
+    }
+
+    /// Gets the keyframes either side of a point in time, if the head is moving.
+    fn segment_at(&self, time: Duration) -> Option<(&Keyframe, &Keyframe)> {
+        self.keyframes.windows(2)
+            .find(|pair| pair[0].0 <= time && time < pair[1].0)
+            .map(|pair| (&pair[0], &pair[1]))
+    }
+}
+
+impl Default for State {
+    fn default() -> Self {
+        State {
+            powered: false,
+            vr_mode: false,
+            vr_tracking: false,
+            cinematic: command::SetCinematicConfiguration {
+                mask: 0,
+                screen_size: 0,
+                screen_distance: 0,
+                ipd: 0,
+                reserved0: [0; 6],
+                brightness: 0,
+                mic_volume: 0,
+                reserved1: [0; 2],
+                unknown: false,
+                reserved2: 0,
+            },
+            leds: [0; 9],
+            device_info: command::DeviceInfo {
+                reserved0: [0; 7],
+                firmware_version_minor: 10,
+                firmware_version_major: 1,
+                reserved1: [0; 3],
+                serial_number: *b"EMULATED\0\0\0\0\0\0\0\0",
+                reserved2: [0; 20],
+            },
+            buttons: sensor::Buttons { plus: false, minus: false, mute: false },
+            volume: 0,
+            worn: true,
+            microphone_muted: false,
+            headphone_connected: false,
+            commands: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::command::Command;
+    use std::f32::consts::FRAC_PI_2;
+
+    fn unpaced(timeline: Timeline) -> Emulator {
+        let emulator = Emulator::new(timeline);
+        emulator.set_pacing(Pacing::Unpaced);
+        emulator
+    }
+
+    #[test]
+    fn power_and_modes_follow_commands() {
+        let emulator = unpaced(Timeline::at_rest());
+        let mut psvr = emulator.connect();
+
+        psvr.power_on().unwrap();
+        psvr.vr_mode().unwrap();
+        psvr.vr_tracking().unwrap();
+
+        let state = emulator.state();
+        assert!(state.powered && state.vr_mode && state.vr_tracking);
+        assert_eq!(3, state.commands.len());
+
+        psvr.set_vr_mode(false).unwrap();
+        psvr.power_off().unwrap();
+
+        let state = emulator.state();
+        assert!(!state.powered && !state.vr_mode);
+    }
+
+    #[test]
+    fn leds_follow_mask() {
+        let emulator = unpaced(Timeline::at_rest());
+        let mut psvr = emulator.connect();
+
+        psvr.send_command(&command::SetHmdLeds { led_mask: 0b1_0000_0001, values: [50; 9], reserved: [0; 5] }).unwrap();
+        assert_eq!([50, 0, 0, 0, 0, 0, 0, 0, 50], emulator.state().leds);
+    }
+
+    #[test]
+    fn answers_read_device_info() {
+        let emulator = unpaced(Timeline::at_rest());
+        let mut psvr = emulator.connect();
+
+        let info = psvr.read_device_info().unwrap();
+        assert_eq!("EMULATED", info.serial_number());
+    }
+
+    #[test]
+    fn reports_state_through_status_bits() {
+        let emulator = unpaced(Timeline::at_rest());
+        let mut psvr = emulator.connect();
+
+        psvr.power_on().unwrap();
+        emulator.update_state(|state| state.buttons.mute = true);
+
+        let readout = psvr.receive_sensor().unwrap();
+        assert!(readout.status.display_active);
+        assert!(readout.status.worn);
+        assert!(readout.buttons.mute);
+    }
+
+    #[test]
+    fn at_rest_reads_gravity_only() {
+        let instant = Timeline::at_rest().instant_at(Duration::from_secs(1));
+
+        assert!(instant.gyroscope().norm() < 1e-3);
+        assert!((instant.accelerometer() - Vector3::z()).norm() < 1e-2);
+    }
+
+    #[test]
+    fn timeline_interpolates_between_keyframes() {
+        let quarter_turn = UnitQuaternion::from_axis_angle(&Vector3::z_axis(), FRAC_PI_2);
+        let timeline = Timeline::at_rest().then_rotate_to(Duration::from_secs(1), quarter_turn);
+
+        assert!((timeline.orientation_at(Duration::from_millis(500)).angle() - FRAC_PI_2 / 2.0).abs() < 1e-4);
+        assert!((timeline.angular_velocity_at(Duration::from_millis(500)) - Vector3::z() * FRAC_PI_2).norm() < 1e-4);
+        assert_eq!(quarter_turn, timeline.orientation_at(Duration::from_secs(2)));
+        assert_eq!(Vector3::zeros(), timeline.angular_velocity_at(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn psvr_tracks_scripted_motion() {
+        let quarter_turn = UnitQuaternion::from_axis_angle(&Vector3::z_axis(), FRAC_PI_2);
+        let timeline = Timeline::at_rest()
+            .then_rotate_to(Duration::from_millis(250), quarter_turn)
+            .then_hold(Duration::from_millis(50));
+        let end = timeline.end();
+
+        let emulator = unpaced(timeline);
+        let mut psvr = emulator.connect();
+        psvr.power_on().unwrap();
+
+        for _ in 0..=end.as_millis() / FRAME_PERIOD.as_millis() {
+            psvr.receive_sensor().unwrap();
+        }
+
+        let orientation = psvr.orientation();
+        let axis = orientation.axis().expect("the headset did not turn");
+
+        assert!((axis.into_inner() - Vector3::z()).norm() < 0.05, "turned around {:?}", axis);
+        assert!((orientation.angle() - FRAC_PI_2).abs() < 0.15,
+                "expected a quarter turn but turned {} radians", orientation.angle());
+    }
+
+    #[test]
+    fn receive_all_sensors_drains_queued_reports() {
+        let emulator = unpaced(Timeline::at_rest());
+        let mut psvr = emulator.connect();
+
+        psvr.receive_sensor().unwrap();
+        emulator.advance(FRAME_PERIOD * 10);
+
+        assert_eq!(10, psvr.receive_all_sensors().unwrap().frames);
+        assert_eq!(1, psvr.receive_all_sensors().unwrap().frames);
+    }
+
+    #[test]
+    fn draining_keeps_up_with_a_slow_reader() {
+        let quarter_turn = UnitQuaternion::from_axis_angle(&Vector3::z_axis(), FRAC_PI_2);
+        let timeline = Timeline::at_rest()
+            .then_rotate_to(Duration::from_millis(250), quarter_turn)
+            .then_hold(Duration::from_millis(50));
+        let end = timeline.end();
+
+        let emulator = unpaced(timeline);
+        let mut psvr = emulator.connect();
+        // Sensor fusion only starts timing from its first update.
+        psvr.receive_all_sensors().unwrap();
+
+        // An application rendering at 40 frames per second.
+        let render_period = Duration::from_millis(25);
+        for _ in 0..=end.as_millis() / render_period.as_millis() {
+            emulator.advance(render_period);
+            assert!(psvr.receive_all_sensors().unwrap().frames >= 12);
+        }
+
+        let orientation = psvr.orientation();
+        assert!((orientation.angle() - FRAC_PI_2).abs() < 0.15,
+                "expected a quarter turn but turned {} radians", orientation.angle());
+    }
+
+    #[test]
+    fn halves_work_from_different_threads() {
+        let emulator = unpaced(Timeline::at_rest());
+        let (mut sensor, mut control) = emulator.connect().split();
+
+        let reader = std::thread::spawn(move || {
+            for _ in 0..10 {
+                sensor.receive_sensor().unwrap();
+            }
+            sensor
+        });
+        control.power_on().unwrap();
+        control.set_vr_mode(true).unwrap();
+
+        let mut psvr = Psvr::rejoin(reader.join().unwrap(), control);
+        assert!(psvr.receive_sensor().unwrap().status.display_active);
+        assert!(emulator.state().vr_mode);
+    }
+
+    #[test]
+    fn acknowledges_commands() {
+        let emulator = unpaced(Timeline::at_rest());
+        let mut psvr = emulator.connect();
+
+        psvr.send_command_acked(&command::SetPower { on: true }).unwrap();
+        assert!(emulator.state().powered);
+
+        match psvr.send_raw_command_acked(0x7f, &[1, 2]) {
+            Err(Error::CommandRejected { command: 0x7f, ref reason }) => assert_eq!("status 0x01", reason),
+            result => panic!("unknown command gave {:?}", result),
+        }
+        match psvr.send_raw_command_acked(command::SetPower::ID, &[1, 0, 0]) {
+            Err(Error::CommandRejected { command, ref reason }) if command == command::SetPower::ID => {
+                assert_eq!("status 0x02", reason);
+            },
+            result => panic!("bad length gave {:?}", result),
+        }
+
+        // The reply to `ReadDeviceInfo` comes after its acknowledgement.
+        psvr.send_command_acked(&command::ReadDeviceInfo).unwrap();
+        assert_eq!("EMULATED", psvr.read_device_info().unwrap().serial_number());
+    }
+
+    #[test]
+    fn waiting_for_a_reply_skips_reports_that_are_not_commands() {
+        let emulator = unpaced(Timeline::at_rest());
+        let mut psvr = emulator.connect();
+        // A header that claims a payload the report doesn't have.
+        let truncated = vec![command::SetPower::ID, 0, protocol::COMMAND_MAGIC, 8];
+
+        emulator.lock().queue_reply(truncated.clone());
+        psvr.send_command_acked(&command::SetPower { on: true }).unwrap();
+
+        emulator.lock().queue_reply(truncated);
+        assert_eq!("EMULATED", psvr.read_device_info().unwrap().serial_number());
+    }
+
+    #[test]
+    fn receiving_a_reply_skips_other_commands() {
+        let emulator = unpaced(Timeline::at_rest());
+        let mut psvr = emulator.connect();
+        psvr.send_raw_command(command::SetPower::ID, &[1, 0, 0, 0]).unwrap();
+        psvr.send_raw_command(command::SetVrMode::ID, &[1, 0, 0, 0]).unwrap();
+
+        let reply = psvr.receive_reply(command::SetVrMode::ID, Duration::from_secs(1)).unwrap().unwrap();
+        assert_eq!(command::SetVrMode::ID, reply.header.id);
+        assert_eq!(None, psvr.receive_reply(command::SetPower::ID, Duration::from_millis(10)).unwrap());
+    }
+}
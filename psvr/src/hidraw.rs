@@ -0,0 +1,319 @@
+//! Talks to the PSVR through Linux hidraw device nodes.
+//!
+//! The PSVR's HID interfaces are found by walking sysfs and opened as plain
+//! files, so neither the HIDAPI C library nor libudev is needed.
+
+use crate::{transport::Transport, usb, Psvr};
+use hmdee_core::Error;
+
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use libc;
+
+/// Where sysfs lists the hidraw devices.
+pub const SYSFS_CLASS_DIR: &str = "/sys/class/hidraw";
+/// Where the hidraw device nodes live.
+pub const DEV_DIR: &str = "/dev";
+
+/// The hidraw devices known to sysfs.
+#[derive(Clone, Debug)]
+pub struct Sysfs {
+    class_dir: PathBuf,
+    dev_dir: PathBuf,
+}
+
+/// A PSVR HID interface exposed as a hidraw device.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Node {
+    /// The device node, such as `/dev/hidraw3`.
+    pub path: PathBuf,
+    /// The USB interface behind the node.
+    pub interface: usb::Interface,
+    /// The sysfs directory of the USB device the interface belongs to.
+    ///
+    /// Every interface of the same PSVR shares this.
+    pub usb_device: PathBuf,
+}
+
+/// The hidraw device nodes of a single PSVR.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PsvrNodes {
+    /// The node of the HID control interface.
+    pub control: PathBuf,
+    /// The node of the HID sensor interface.
+    pub sensor: PathBuf,
+}
+
+/// An open hidraw device node.
+#[derive(Debug)]
+pub struct Device {
+    file: File,
+}
+
+/// Opens an arbitrary connected PSVR.
+pub fn get() -> Result<Option<Psvr>, Error> {
+    Sysfs::new().psvrs()?.first().map(PsvrNodes::connect).transpose()
+}
+
+impl Sysfs {
+    /// Looks at the system's sysfs and `/dev`.
+    pub fn new() -> Self {
+        Sysfs::at(SYSFS_CLASS_DIR, DEV_DIR)
+    }
+
+    /// Looks at a sysfs hidraw class directory and a device node directory
+    /// somewhere else, such as a fake tree for testing.
+    pub fn at<C, D>(class_dir: C, dev_dir: D) -> Self
+        where C: Into<PathBuf>, D: Into<PathBuf> {
+        Sysfs { class_dir: class_dir.into(), dev_dir: dev_dir.into() }
+    }
+
+    /// Finds every PSVR HID interface, ordered by device node.
+    ///
+    /// Devices that can't be looked at, such as one unplugged during the
+    /// scan, are skipped.
+    pub fn nodes(&self) -> Result<Vec<Node>, Error> {
+        let mut nodes = Vec::new();
+
+        for entry in fs::read_dir(&self.class_dir)?.flatten() {
+            if let Ok(Some(node)) = self.node(&entry.file_name()) {
+                nodes.push(node);
+            }
+        }
+
+        nodes.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(nodes)
+    }
+
+    /// Finds every connected PSVR that has both HID interfaces.
+    pub fn psvrs(&self) -> Result<Vec<PsvrNodes>, Error> {
+        let mut usb_devices: BTreeMap<PathBuf, (Option<PathBuf>, Option<PathBuf>)> = BTreeMap::new();
+
+        for node in self.nodes()? {
+            let (control, sensor) = usb_devices.entry(node.usb_device).or_default();
+            match node.interface {
+                usb::Interface::HidControl => *control = Some(node.path),
+                usb::Interface::HidSensor => *sensor = Some(node.path),
+                _ => (),
+            }
+        }
+
+        Ok(usb_devices.into_values().filter_map(|nodes| match nodes {
+            (Some(control), Some(sensor)) => Some(PsvrNodes { control, sensor }),
+            _ => None,
+        }).collect())
+    }
+
+    /// Describes a hidraw device, if it is a PSVR interface.
+    fn node(&self, name: &OsStr) -> Result<Option<Node>, Error> {
+        // The `device` link goes to the HID device. Its parent is the USB
+        // interface, and the parent of that is the USB device.
+        let hid_device = fs::canonicalize(self.class_dir.join(name).join("device"))?;
+        let uevent = fs::read_to_string(hid_device.join("uevent"))?;
+
+        if parse_hid_id(&uevent) != Some((usb::PSVR_VID, usb::PSVR_PID)) {
+            return Ok(None);
+        }
+
+        let usb_interface = parent(&hid_device)?;
+        let number = fs::read_to_string(usb_interface.join("bInterfaceNumber"))?;
+        let number = i32::from_str_radix(number.trim(), 16).map_err(|_| {
+            Error::communication_error(format!("'{}' has a malformed interface number", usb_interface.display()))
+        })?;
+
+        Ok(Some(Node {
+            path: self.dev_dir.join(name),
+            interface: usb::Interface::from_i32(number)?,
+            usb_device: parent(usb_interface)?.to_owned(),
+        }))
+    }
+}
+
+impl Default for Sysfs {
+    fn default() -> Self { Sysfs::new() }
+}
+
+impl PsvrNodes {
+    /// Opens both nodes and connects to the PSVR.
+    pub fn connect(&self) -> Result<Psvr, Error> {
+        let control = Device::open(&self.control)?;
+        let sensor = Device::open(&self.sensor)?;
+
+        Ok(Psvr::from_transports(Box::new(control), Box::new(sensor)))
+    }
+}
+
+impl Device {
+    /// Opens a hidraw device node for reading and writing.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        Ok(Device::from_file(file))
+    }
+
+    /// Uses an already open file as a hidraw device.
+    pub fn from_file(file: File) -> Self {
+        Device { file }
+    }
+}
+
+impl Transport for Device {
+    fn write(&mut self, data: &[u8]) -> Result<usize, Error> {
+        self.file.write_all(data)?;
+        Ok(data.len())
+    }
+
+    fn read_timeout(&mut self, buf: &mut [u8], timeout_milliseconds: i32) -> Result<usize, Error> {
+        let mut poll_fd = libc::pollfd { fd: self.file.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+
+        // A negative timeout makes `poll` wait forever, as the trait asks.
+        // If a signal interrupts the wait, it starts over with the full timeout.
+        let ready = loop {
+            let ready = unsafe { libc::poll(&mut poll_fd, 1, timeout_milliseconds) };
+            if ready >= 0 {
+                break ready;
+            }
+
+            let error = io::Error::last_os_error();
+            if error.kind() != io::ErrorKind::Interrupted {
+                return Err(error.into());
+            }
+        };
+
+        if ready == 0 {
+            return Ok(0);
+        }
+        Ok(self.file.read(buf)?)
+    }
+}
+
+/// Gets the vendor and product ID from a HID device's `uevent` file.
+///
+/// The ID is on a line like `HID_ID=0003:0000054C:000009AF`.
+fn parse_hid_id(uevent: &str) -> Option<(u16, u16)> {
+    let id = uevent.lines().find_map(|line| line.strip_prefix("HID_ID="))?;
+    let mut parts = id.trim().split(':').skip(1);
+    let vendor = u16::from_str_radix(parts.next()?, 16).ok()?;
+    let product = u16::from_str_radix(parts.next()?, 16).ok()?;
+
+    Some((vendor, product))
+}
+
+fn parent(path: &Path) -> Result<&Path, Error> {
+    path.parent().ok_or_else(|| Error::communication_error(format!("'{}' has no parent in sysfs", path.display())))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use std::os::unix::net::UnixDatagram;
+    use std::os::unix::io::OwnedFd;
+
+    /// A fake sysfs and `/dev` that is deleted when dropped.
+    struct FakeTree {
+        root: PathBuf,
+    }
+
+    impl FakeTree {
+        fn new(name: &str) -> Self {
+            let root = std::env::temp_dir().join(format!("psvr-hidraw-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(root.join("class")).unwrap();
+            fs::create_dir_all(root.join("dev")).unwrap();
+            FakeTree { root }
+        }
+
+        /// Adds a hidraw device, as the kernel lays it out.
+        fn add(&self, name: &str, usb_device: &str, interface: u8, hid_id: &str) {
+            let usb_interface = self.root.join("devices").join(usb_device).join(format!("{}:1.{}", usb_device, interface));
+            let hid_device = usb_interface.join(format!("0003:{}.0001", name));
+
+            fs::create_dir_all(hid_device.join("hidraw").join(name)).unwrap();
+            fs::write(usb_interface.join("bInterfaceNumber"), format!("{:02x}\n", interface)).unwrap();
+            fs::write(hid_device.join("uevent"), format!("DRIVER=hid-generic\nHID_ID={}\nHID_NAME=Sony\n", hid_id)).unwrap();
+            symlink(hid_device.join("hidraw").join(name), self.root.join("class").join(name)).unwrap();
+            symlink(&hid_device, hid_device.join("hidraw").join(name).join("device")).unwrap();
+            fs::write(self.root.join("dev").join(name), b"").unwrap();
+        }
+
+        fn sysfs(&self) -> Sysfs {
+            Sysfs::at(self.root.join("class"), self.root.join("dev"))
+        }
+    }
+
+    impl Drop for FakeTree {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    const PSVR_ID: &str = "0003:0000054C:000009AF";
+
+    #[test]
+    fn parses_hid_ids() {
+        assert_eq!(Some((0x054c, 0x09af)), parse_hid_id("DRIVER=hid-generic\nHID_ID=0003:0000054C:000009AF\n"));
+        assert_eq!(None, parse_hid_id("DRIVER=hid-generic\n"));
+    }
+
+    #[test]
+    fn finds_psvr_interfaces() {
+        let tree = FakeTree::new("interfaces");
+        tree.add("hidraw0", "1-1", 0, "0003:0000046D:0000C52B");
+        tree.add("hidraw1", "1-2", 4, PSVR_ID);
+        tree.add("hidraw2", "1-2", 5, PSVR_ID);
+
+        let nodes = tree.sysfs().nodes().unwrap();
+
+        assert_eq!(vec![usb::Interface::HidSensor, usb::Interface::HidControl],
+                   nodes.iter().map(|node| node.interface).collect::<Vec<_>>());
+        assert_eq!(tree.root.join("dev").join("hidraw1"), nodes[0].path);
+    }
+
+    #[test]
+    fn skips_unreadable_devices() {
+        let tree = FakeTree::new("unreadable");
+        tree.add("hidraw0", "1-2", 4, PSVR_ID);
+        // Unplugged between listing the class directory and following it.
+        symlink(tree.root.join("gone"), tree.root.join("class").join("hidraw1")).unwrap();
+
+        let nodes = tree.sysfs().nodes().unwrap();
+
+        assert_eq!(vec![tree.root.join("dev").join("hidraw0")], nodes.iter().map(|node| node.path.clone()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn groups_interfaces_by_usb_device() {
+        let tree = FakeTree::new("grouping");
+        tree.add("hidraw0", "1-2", 4, PSVR_ID);
+        tree.add("hidraw1", "3-1", 5, PSVR_ID);
+        tree.add("hidraw2", "1-2", 5, PSVR_ID);
+        // Only has a sensor interface, so can't be used.
+        tree.add("hidraw3", "4-1", 4, PSVR_ID);
+
+        let psvrs = tree.sysfs().psvrs().unwrap();
+        let dev = tree.root.join("dev");
+
+        assert_eq!(vec![PsvrNodes { control: dev.join("hidraw2"), sensor: dev.join("hidraw0") }], psvrs);
+        psvrs[0].connect().unwrap();
+    }
+
+    #[test]
+    fn reads_time_out() {
+        let (device, other) = UnixDatagram::pair().unwrap();
+        let mut device = Device::from_file(File::from(OwnedFd::from(device)));
+        let mut buf = [0; 64];
+
+        assert_eq!(0, device.read_timeout(&mut buf, 10).unwrap());
+
+        other.send(&[1, 2, 3]).unwrap();
+        assert_eq!(3, device.read_timeout(&mut buf, 10).unwrap());
+        assert_eq!([1, 2, 3], buf[..3]);
+
+        assert_eq!(2, device.write(&[4, 5]).unwrap());
+        assert_eq!(2, other.recv(&mut buf).unwrap());
+    }
+}
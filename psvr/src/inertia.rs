@@ -10,97 +10,73 @@
 //!   * Sensorscope
 //!   * Accelerometer
 //!   * There is no magnetometer. If there was, the PSVR would have 9 degrees of freedom
+//!
+//! The fusion itself is done by `hmdee_core::fusion`. This module only
+//! describes the PSVR's sensor to it.
+
+use crate::sensor;
+use hmdee_core::fusion::{self, ImuSample};
+use hmdee_core::math::Scalar;
 
-use hmdee_core::math::{Quaternion, Scalar, Vector3};
-use ahrs::{self, Ahrs};
-use delta;
+use std::time::Duration;
 
 /// How many samples are taken per second.
 const SAMPLE_FREQUENCY: u32 = 120;
-/// How many seconds inbetween samples.
-const SAMPLE_PERIOD: f32 = 1.0 / SAMPLE_FREQUENCY as f32;
 /// The Madgwick beta constant for the PSVR.
 const MADGWICK_BETA_ANTI_DRIFT: f32 = 0.125;
 const MADGWICK_BETA_STEADINESS: Scalar = 0.035;
 
-/// Inertia information at a point in time.
-#[derive(Clone, Debug, PartialEq, PartialOrd)]
-pub struct Instant {
-    /// The gyroscope inertia inforamation.
-    pub gyroscope: Vector3,
-    /// The accelerometer inertia inforamation.
-    pub accelerometer: Vector3,
-}
-
-/// An inertia sensor.
-#[derive(Debug)]
-pub struct Sensor {
-    integrators: Integrators,
-    /// The timer.
-    timer: delta::Timer,
+/// How many samples the orientation history keeps.
+///
+/// This covers a second of head movement.
+pub const HISTORY_LENGTH: usize = SAMPLE_FREQUENCY as usize;
+/// How far past the newest sample `orientation_at` will extrapolate.
+pub const MAX_EXTRAPOLATION: Duration = Duration::from_millis(50);
+
+/// The largest bias the gyroscope can plausibly have, in rad/s.
+///
+/// The BMI055 is specified to within ±1°/s, plus drift with temperature.
+const MAX_GYRO_BIAS: Scalar = 0.05;
+
+/// Gets the fusion configuration for the PSVR's IMU.
+pub fn config() -> fusion::Config {
+    fusion::Config {
+        sample_rate: SAMPLE_FREQUENCY as Scalar,
+        beta_anti_drift: MADGWICK_BETA_ANTI_DRIFT,
+        beta_steadiness: MADGWICK_BETA_STEADINESS,
+        max_gyro_bias: MAX_GYRO_BIAS,
+        history_length: HISTORY_LENGTH,
+        max_extrapolation: MAX_EXTRAPOLATION,
+    }
 }
 
-#[derive(Debug)]
-struct Integrators {
-    /// A Madgwick filter optimized for its anti-drift qualities.
-    anti_drift: ahrs::Madgwick<f32>,
-    /// A Madgwick filter optimized for its steadying qualities.
-    steadiness: ahrs::Madgwick<Scalar>,
+/// Starts sensor fusion for a PSVR.
+pub fn fusion() -> fusion::Fusion {
+    fusion::Fusion::new(config())
 }
 
-impl Sensor {
-    /// Creates a new inertia sensor.
-    pub fn new() -> Self {
-        Sensor {
-            integrators: Integrators {
-                anti_drift: ahrs::Madgwick::new(SAMPLE_PERIOD, MADGWICK_BETA_ANTI_DRIFT),
-                steadiness: ahrs::Madgwick::new(SAMPLE_PERIOD, MADGWICK_BETA_STEADINESS),
-            },
-            timer: delta::Timer::new(),
-        }
-    }
-
-    /// Updates the inertia sensor.
-    pub fn update(&mut self, instant: &Instant) {
-        let delta = self.timer.mark_secs();
-
-        // Change the sample period of the existing Madgwick object.
-        // The library doesn't directly support dynamic periods.
-        self.integrators.anti_drift = ahrs::Madgwick::new_with_quat(delta as _, MADGWICK_BETA_ANTI_DRIFT, self.integrators.anti_drift.quat);
-        self.integrators.steadiness = ahrs::Madgwick::new_with_quat(delta as _, MADGWICK_BETA_ANTI_DRIFT, self.integrators.steadiness.quat);
-
-        self.integrators.anti_drift.update_imu(
-            &instant.gyroscope.into(),
-            &instant.accelerometer.into(),
-        ).expect("failed to run anti-drift madgiwck filter");
-
-        self.integrators.steadiness.update_imu(
-            &instant.gyroscope.into(),
-            &instant.accelerometer.into(),
-        ).expect("failed to run steadiness madgiwck filter");
-    }
-
-    /// Gets the current orientation of the PSVR headset.
-    pub fn hmd_orientation(&self) -> Quaternion {
-        use na::geometry::UnitQuaternion;
-
-        // FIXME: t parameter should be calculated on-the-fly.
-        // https://github.com/dylanmckay/psvr-protocol/issues/14#issuecomment-435378326
-        let t = 0.5;
-
-        let anti_drift = UnitQuaternion::new_normalize(self.integrators.anti_drift.quat);
-        let steadiness = UnitQuaternion::new_normalize(self.integrators.steadiness.quat);
-        UnitQuaternion::slerp(&anti_drift, &steadiness, t).quaternion().clone()
+/// Converts a sensor readout instant into sensor fusion input.
+///
+/// See `sensor::InertiaInstant::gyroscope` and `accelerometer` for the
+/// units and axes.
+pub fn imu_sample(instant: &sensor::InertiaInstant) -> ImuSample {
+    ImuSample {
+        gyroscope: instant.gyroscope(),
+        accelerometer: instant.accelerometer(),
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use hmdee_core::math::Vector3;
 
     #[test]
-    fn can_create_sensor() {
-        let _ = Sensor::new();
+    fn level_headset_reads_gravity_up() {
+        let level = sensor::InertiaInstant::from_vectors(&Vector3::zeros(), &Vector3::z());
+        let sample = imu_sample(&level);
+
+        assert_eq!(Vector3::zeros(), sample.gyroscope);
+        assert!((sample.accelerometer - Vector3::z()).norm() < 1e-2);
     }
 }
-
@@ -1,20 +1,31 @@
 //! PlayStation VR client library.
+//!
+//! Without the default `std` feature, only the allocation-free protocol
+//! types are available: the `sensor` frame parser, the `command` encoders,
+//! and the low-level `protocol` and `wire` formats. These can be used on
+//! microcontrollers under `#![no_std]`.
+
+#![cfg_attr(not(feature = "std"), no_std)]
 
 extern crate hmdee_core;
 
-extern crate ahrs;
 extern crate byteorder;
-extern crate delta;
-pub extern crate hidapi;
+#[cfg(feature = "hidapi")] pub extern crate hidapi;
+#[cfg(feature = "hidraw")] extern crate libc;
 extern crate nalgebra as na;
+#[cfg(feature = "serde")] #[macro_use] extern crate serde;
 
-pub use self::client::*;
+#[cfg(feature = "std")] pub use self::client::*;
 
-mod client;
+#[cfg(feature = "std")] pub mod capture;
+#[cfg(feature = "std")] mod client;
 pub mod command;
-pub mod inertia;
+#[cfg(feature = "std")] pub mod emulator;
+#[cfg(all(feature = "hidraw", target_os = "linux"))] pub mod hidraw;
+#[cfg(feature = "std")] pub mod inertia;
 pub mod protocol;
+#[cfg(feature = "std")] pub mod queue;
 pub mod sensor;
-mod usb;
-
-
+#[cfg(feature = "std")] pub mod transport;
+pub mod usb;
+pub mod wire;
@@ -1,10 +1,22 @@
 //! The low-level protocol types.
 
+use crate::wire;
+
 /// The size of the command header.
 pub const COMMAND_HEADER_SIZE: usize = 4;
+/// The largest payload a command header can describe.
+pub const MAX_PAYLOAD_SIZE: usize = u8::MAX as usize;
+/// The largest a whole command can be.
+pub const MAX_COMMAND_SIZE: usize = COMMAND_HEADER_SIZE + MAX_PAYLOAD_SIZE;
+/// The magic byte every command header carries.
+pub const COMMAND_MAGIC: u8 = 0xAA;
+
+/// The status byte of a reply to a command that was carried out.
+pub const STATUS_SUCCESS: u8 = 0x00;
 
 /// The header for a command message.
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct CommandHeader {
     pub id: u8,
     pub status: u8,
@@ -12,23 +24,118 @@ pub struct CommandHeader {
     pub length: u8,
 }
 
-#[derive(Clone)]
+/// What the status byte of a reply says about the command it answers.
+///
+/// The status bytes the processor unit replies with aren't publicly
+/// documented, and only success has been seen from a real unit, so every
+/// other status is kept as it is.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Status {
+    /// The command was carried out.
+    Success,
+    /// A status whose meaning isn't known.
+    Unknown(u8),
+}
+
+#[cfg(feature = "std")]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Command {
     pub header: CommandHeader,
     pub payload: Vec<u8>,
 }
 
 impl CommandHeader {
+    /// Parses a command header from the start of raw bytes.
+    ///
+    /// The magic byte isn't checked, so that reports which aren't commands
+    /// can still be looked at. See `validate`.
+    pub fn parse(raw: &[u8]) -> Result<Self, wire::Error> {
+        let [id, status, magic, length] = wire::Reader::new(raw).read_array()?;
+
+        Ok(CommandHeader { id, status, magic, length })
+    }
+
+    /// Checks that the header carries the command magic byte.
+    pub fn validate(&self) -> Result<(), wire::Error> {
+        if self.magic != COMMAND_MAGIC {
+            return Err(wire::Error::BadMagic { id: self.id, magic: self.magic });
+        }
+
+        Ok(())
+    }
+
+    /// Parses a command header, and borrows the payload that follows it.
+    ///
+    /// Any bytes after the length given in the header are ignored, as
+    /// HID reports are padded out to a fixed size.
+    pub fn split(raw: &[u8]) -> Result<(Self, &[u8]), wire::Error> {
+        let header = CommandHeader::parse(raw)?;
+        let payload = wire::Reader::new(&raw[COMMAND_HEADER_SIZE..]).read_slice(header.length as usize)?;
+
+        Ok((header, payload))
+    }
+
+    /// Gets what the status byte says, if this header is from a reply.
+    pub fn reply_status(&self) -> Status {
+        Status::from_byte(self.status)
+    }
+
+    /// Gets the raw bytes of the header.
+    pub fn to_bytes(&self) -> [u8; COMMAND_HEADER_SIZE] {
+        [self.id, self.status, self.magic, self.length]
+    }
+
+    #[cfg(feature = "std")]
     pub fn raw_bytes(&self) -> Vec<u8> {
-        vec![self.id, self.status, self.magic, self.length]
+        self.to_bytes().to_vec()
+    }
+}
+
+impl Status {
+    /// Interprets a status byte.
+    pub fn from_byte(status: u8) -> Self {
+        match status {
+            STATUS_SUCCESS => Status::Success,
+            status => Status::Unknown(status),
+        }
+    }
+
+    /// Gets the status byte.
+    pub fn to_byte(self) -> u8 {
+        match self {
+            Status::Success => STATUS_SUCCESS,
+            Status::Unknown(status) => status,
+        }
+    }
+
+    /// Turns the status of the reply to a command into a result.
+    #[cfg(feature = "std")]
+    pub fn check(self, command: u8) -> Result<(), hmdee_core::Error> {
+        match self {
+            Status::Success => Ok(()),
+            Status::Unknown(status) => Err(hmdee_core::Error::command_rejected(command, format!("status 0x{:02x}", status))),
+        }
     }
 }
 
+#[cfg(feature = "std")]
 impl Command {
+    /// Parses a command from raw bytes, without checking the magic byte.
+    ///
+    /// Any bytes after the length given in the header are ignored, as
+    /// HID reports are padded out to a fixed size.
+    pub fn parse(raw: &[u8]) -> Result<Self, wire::Error> {
+        let (header, payload) = CommandHeader::split(raw)?;
+
+        Ok(Command { header, payload: payload.to_owned() })
+    }
+
     pub fn raw_bytes(&self) -> Vec<u8> {
         let mut raw_bytes = Vec::new();
 
-        raw_bytes.extend(self.header.raw_bytes());
+        raw_bytes.extend(self.header.to_bytes());
         raw_bytes.extend(self.payload.iter());
 
         raw_bytes
@@ -38,7 +145,7 @@ impl Command {
 #[cfg(test)]
 mod test {
     use super::*;
-    use std::mem::size_of;
+    use core::mem::size_of;
 
     #[test]
     fn command_header_size_matches_constant() {
@@ -59,5 +166,41 @@ mod test {
         assert_eq!(&[0x69, 123, 88, 5, 5, 4, 3, 2, 1], &command.raw_bytes()[..]);
     }
 
+    #[test]
+    fn command_parse_is_inverse_of_raw_bytes() {
+        let raw = [0x69, 123, COMMAND_MAGIC, 5, 5, 4, 3, 2, 1, 0, 0];
+        let command = Command::parse(&raw).unwrap();
+
+        assert_eq!(&raw[..9], &command.raw_bytes()[..]);
+        assert!(Command::parse(&raw[..8]).is_err());
+    }
+
+    #[test]
+    fn command_parse_passes_bad_magic_through() {
+        let command = Command::parse(&[0x17, 0, 0xAB, 0]).unwrap();
+
+        assert_eq!(0xAB, command.header.magic);
+        assert_eq!(Err(wire::Error::BadMagic { id: 0x17, magic: 0xAB }), command.header.validate());
+        assert_eq!(Ok(()), CommandHeader::parse(&[0x17, 0, COMMAND_MAGIC, 0]).unwrap().validate());
+    }
+
+    #[test]
+    fn split_borrows_payload() {
+        let raw = [0x69, 0, COMMAND_MAGIC, 2, 7, 8, 0, 0];
+        let (header, payload) = CommandHeader::split(&raw).unwrap();
+
+        assert_eq!(0x69, header.id);
+        assert_eq!(&[7, 8], payload);
+        assert_eq!(Err(wire::Error::UnexpectedEnd { needed: 4, available: 3 }), CommandHeader::split(&raw[..3]));
+    }
+
+    #[test]
+    fn status_round_trips() {
+        for status in 0..=u8::MAX {
+            assert_eq!(status, Status::from_byte(status).to_byte());
+        }
+        assert_eq!(Status::Unknown(2), CommandHeader::parse(&[0x17, 2, COMMAND_MAGIC, 0]).unwrap().reply_status());
+    }
+
 
 }
@@ -0,0 +1,440 @@
+//! Pacing commands so that the processor unit doesn't miss any.
+//!
+//! The processor unit ignores some commands that arrive too soon after
+//! another. A `CommandQueue` holds commands back until enough time has
+//! passed, drops commands that a newer one makes pointless, and can keep
+//! resending a command until the sensor status reports show it took effect.
+
+use crate::command::{self, AnyCommand};
+use crate::{sensor, ControlHalf, Psvr};
+use hmdee_core::Error;
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use std::{fmt, thread};
+
+/// The shortest time between two commands, by default.
+pub const DEFAULT_MIN_INTERVAL: Duration = Duration::from_millis(20);
+/// How long to wait for a command to take effect before resending it, by default.
+pub const DEFAULT_RETRY_INTERVAL: Duration = Duration::from_millis(500);
+/// How many times a command is sent before giving up on it, by default.
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 5;
+
+/// Checks a sensor status report to see if a command took effect.
+pub type Confirmation = Box<dyn Fn(&sensor::Status) -> bool + Send>;
+
+/// Commands waiting to be sent to a PSVR.
+pub struct CommandQueue {
+    queued: VecDeque<Queued>,
+    min_interval: Duration,
+    retry_interval: Duration,
+    max_attempts: u32,
+    /// When the last command was sent.
+    last_sent: Option<Instant>,
+    /// Confirmed commands that never took effect.
+    given_up: Vec<AnyCommand>,
+}
+
+struct Queued {
+    command: AnyCommand,
+    /// If set, the command is resent until this accepts a status report.
+    confirmation: Option<Confirmation>,
+    attempts: u32,
+    sent_at: Option<Instant>,
+}
+
+impl CommandQueue {
+    /// Creates an empty queue with the default timing.
+    pub fn new() -> Self {
+        CommandQueue {
+            queued: VecDeque::new(),
+            min_interval: DEFAULT_MIN_INTERVAL,
+            retry_interval: DEFAULT_RETRY_INTERVAL,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            last_sent: None,
+            given_up: Vec::new(),
+        }
+    }
+
+    /// Sets the shortest time between two commands.
+    pub fn set_min_interval(&mut self, min_interval: Duration) {
+        self.min_interval = min_interval;
+    }
+
+    /// Sets how long to wait for a confirmed command to take effect before
+    /// resending it, and how many times to send it before giving up.
+    pub fn set_retries(&mut self, retry_interval: Duration, max_attempts: u32) {
+        self.retry_interval = retry_interval;
+        self.max_attempts = max_attempts.max(1);
+    }
+
+    /// Queues a command to be sent once.
+    ///
+    /// Any queued command that this one supersedes is dropped.
+    pub fn push<C: Into<AnyCommand>>(&mut self, command: C) {
+        self.enqueue(command.into(), None);
+    }
+
+    /// Queues a command that is resent until a status report confirms it.
+    ///
+    /// Commands behind it wait until it is confirmed, so they are still
+    /// sent in order.
+    pub fn push_confirmed<C, F>(&mut self, command: C, confirmation: F)
+        where C: Into<AnyCommand>, F: Fn(&sensor::Status) -> bool + Send + 'static {
+        self.enqueue(command.into(), Some(Box::new(confirmation)));
+    }
+
+    /// Queues a command that turns the power on or off, until the display
+    /// reports that it is.
+    pub fn push_set_power(&mut self, on: bool) {
+        self.push_confirmed(command::SetPower { on }, move |status| status.display_active == on);
+    }
+
+    /// Gets the number of commands waiting.
+    pub fn len(&self) -> usize {
+        self.queued.len()
+    }
+
+    /// Checks if every command has been sent and confirmed.
+    pub fn is_empty(&self) -> bool {
+        self.queued.is_empty()
+    }
+
+    /// Gets the commands waiting, oldest first.
+    pub fn commands(&self) -> impl Iterator<Item = &AnyCommand> {
+        self.queued.iter().map(|queued| &queued.command)
+    }
+
+    /// Takes the confirmed commands that were given up on, oldest first.
+    ///
+    /// A confirmed command is given up on once it has been sent as many
+    /// times as allowed without a status report confirming it.
+    pub fn take_given_up(&mut self) -> Vec<AnyCommand> {
+        std::mem::take(&mut self.given_up)
+    }
+
+    /// Drops the command being waited on if a status report confirms it.
+    pub fn confirm(&mut self, status: &sensor::Status) {
+        let confirmed = match self.queued.front() {
+            Some(&Queued { confirmation: Some(ref confirmation), sent_at: Some(_), .. }) => confirmation(status),
+            _ => false,
+        };
+
+        if confirmed {
+            self.queued.pop_front();
+        }
+    }
+
+    /// Sends the next command, if it is due.
+    ///
+    /// Returns whether a command was sent. A confirmed command that has
+    /// been sent as many times as allowed without taking effect is dropped,
+    /// and kept for `take_given_up`.
+    pub fn poll(&mut self, control: &mut ControlHalf) -> Result<bool, Error> {
+        self.poll_at(control, Instant::now())
+    }
+
+    /// Sends the next command if it is due at the given time.
+    pub fn poll_at(&mut self, control: &mut ControlHalf, now: Instant) -> Result<bool, Error> {
+        loop {
+            match self.due() {
+                Some(Some(due)) if due > now => return Ok(false),
+                None => return Ok(false),
+                _ => (),
+            }
+
+            let gave_up = self.queued.front().is_some_and(|front| {
+                front.confirmation.is_some() && front.attempts >= self.max_attempts
+            });
+            if !gave_up {
+                break;
+            }
+            let command = self.queued.pop_front().unwrap().command;
+            self.given_up.push(command);
+        }
+
+        let front = self.queued.front_mut().expect("a command is due so the queue is not empty");
+        control.send_raw_command(front.command.id(), &front.command.payload_bytes())?;
+        front.attempts += 1;
+        front.sent_at = Some(now);
+        self.last_sent = Some(now);
+
+        if front.confirmation.is_none() {
+            self.queued.pop_front();
+        }
+        Ok(true)
+    }
+
+    /// Gets when the next command should be sent, if there is one.
+    pub fn next_due(&self) -> Option<Instant> {
+        self.due().map(|due| due.unwrap_or_else(Instant::now))
+    }
+
+    /// Gets when the next command should be sent, or `Some(None)` if it can
+    /// be sent straight away.
+    fn due(&self) -> Option<Option<Instant>> {
+        let front = self.queued.front()?;
+        let paced = self.last_sent.map(|last_sent| last_sent + self.min_interval);
+        let retry = front.sent_at.map(|sent_at| sent_at + self.retry_interval);
+
+        Some(match (paced, retry) {
+            (Some(paced), Some(retry)) => Some(paced.max(retry)),
+            (time, None) | (None, time) => time,
+        })
+    }
+
+    /// Sends every queued command, waiting for each to be due.
+    ///
+    /// Sensor reports are read while waiting for confirmations, so this
+    /// should not be used while another thread owns the sensor half.
+    ///
+    /// Fails if a confirmed command is given up on. It is still kept for
+    /// `take_given_up`.
+    pub fn flush(&mut self, psvr: &mut Psvr) -> Result<(), Error> {
+        while let Some(due) = self.next_due() {
+            let waiting_for_confirmation = self.queued.front().is_some_and(|front| {
+                front.confirmation.is_some() && front.sent_at.is_some()
+            });
+
+            if waiting_for_confirmation {
+                let readout = psvr.receive_sensor()?;
+                self.confirm(&readout.status);
+            } else if let Some(wait) = due.checked_duration_since(Instant::now()) {
+                thread::sleep(wait);
+            }
+
+            self.poll(psvr.control_mut())?;
+        }
+
+        match self.given_up.last() {
+            Some(command) => Err(Error::communication_error(format!(
+                "PSVR did not confirm command 0x{:02x} after {} attempts", command.id(), self.max_attempts))),
+            None => Ok(()),
+        }
+    }
+
+    fn enqueue(&mut self, command: AnyCommand, confirmation: Option<Confirmation>) {
+        let mut command = command;
+
+        // Anything the new command overrides can go, keeping what the new
+        // command leaves alone. A command that has already been sent but not
+        // confirmed goes too, as the new one is what should take effect.
+        // Nothing may move past a command that doesn't set state, as it may
+        // depend on what was sent before it.
+        let mut i = 0;
+        while i < self.queued.len() {
+            let adjacent = i + 1 == self.queued.len();
+            let crosses_other_commands = self.queued.range(i + 1..).any(|queued| !sets_state(&queued.command));
+            let merged = if crosses_other_commands { None } else { coalesce(&self.queued[i].command, &command, adjacent) };
+
+            match merged {
+                Some(merged) => {
+                    command = merged;
+                    self.queued.remove(i);
+                },
+                None => i += 1,
+            }
+        }
+
+        self.queued.push_back(Queued { command, confirmation, attempts: 0, sent_at: None });
+    }
+}
+
+impl Default for CommandQueue {
+    fn default() -> Self { CommandQueue::new() }
+}
+
+impl fmt::Debug for CommandQueue {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        fmt.debug_struct("CommandQueue")
+            .field("commands", &self.commands().collect::<Vec<_>>())
+            .field("min_interval", &self.min_interval)
+            .field("retry_interval", &self.retry_interval)
+            .field("max_attempts", &self.max_attempts)
+            .finish()
+    }
+}
+
+/// Checks if a command only sets state, independently of the commands
+/// around it.
+fn sets_state(command: &AnyCommand) -> bool {
+    matches!(command, AnyCommand::SetPower(..) | AnyCommand::SetVrMode(..) |
+             AnyCommand::SetHmdLeds(..) | AnyCommand::SetCinematicConfiguration(..))
+}
+
+/// Works out the single command to send instead of an older command
+/// followed by a newer one.
+///
+/// Only commands that set state can be merged across other commands that
+/// set state, as the state they set doesn't depend on what comes between.
+/// Anything else is only merged with a copy of itself straight before it,
+/// so that no command moves past another.
+///
+/// Returns `None` if both commands need to be sent.
+fn coalesce(older: &AnyCommand, newer: &AnyCommand, adjacent: bool) -> Option<AnyCommand> {
+    match (older, newer) {
+        (AnyCommand::SetPower(..), AnyCommand::SetPower(..)) |
+        (AnyCommand::SetVrMode(..), AnyCommand::SetVrMode(..)) => Some(newer.clone()),
+        (AnyCommand::SetHmdLeds(older), AnyCommand::SetHmdLeds(newer)) => {
+            let mut merged = older.clone();
+            for (i, value) in merged.values.iter_mut().enumerate() {
+                if newer.led_mask & (1 << i) != 0 {
+                    *value = newer.values[i];
+                }
+            }
+            merged.led_mask |= newer.led_mask;
+            merged.reserved = newer.reserved;
+
+            Some(merged.into())
+        },
+        (AnyCommand::SetCinematicConfiguration(older), AnyCommand::SetCinematicConfiguration(newer)) => {
+            use crate::command::SetCinematicConfiguration as Config;
+
+            let mut merged = newer.clone();
+            let kept = older.mask & !newer.mask;
+            if kept & Config::MASK_SCREEN_SIZE != 0 { merged.screen_size = older.screen_size }
+            if kept & Config::MASK_SCREEN_DISTANCE != 0 { merged.screen_distance = older.screen_distance }
+            if kept & Config::MASK_IPD != 0 { merged.ipd = older.ipd }
+            if kept & Config::MASK_BRIGHTNESS != 0 { merged.brightness = older.brightness }
+            if kept & Config::MASK_MIC_VOLUME != 0 { merged.mic_volume = older.mic_volume }
+            merged.mask |= older.mask;
+
+            Some(merged.into())
+        },
+        // Sending the same command twice in a row does nothing more.
+        _ if adjacent && older == newer => Some(newer.clone()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emulator::{Emulator, Pacing, Timeline};
+
+    fn leds(led_mask: u16, value: u8) -> command::SetHmdLeds {
+        command::SetHmdLeds { led_mask, values: [value; 9], reserved: [0; 5] }
+    }
+
+    #[test]
+    fn coalesces_superseded_commands() {
+        let mut queue = CommandQueue::new();
+        queue.push(command::SetPower { on: true });
+        queue.push(leds(0b0_0000_0011, 10));
+        queue.push(command::SetVrMode { vr_mode: true });
+        queue.push(leds(0b0_0000_0110, 20));
+        queue.push(command::SetPower { on: false });
+        queue.push(command::SetVrMode { vr_mode: true });
+
+        let merged = command::SetHmdLeds { led_mask: 0b0_0000_0111, values: [10, 20, 20, 10, 10, 10, 10, 10, 10], reserved: [0; 5] };
+        assert_eq!(vec![AnyCommand::from(merged), command::SetPower { on: false }.into(), command::SetVrMode { vr_mode: true }.into()],
+                   queue.commands().cloned().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn only_coalesces_repeats_of_other_commands_in_a_row() {
+        let mut queue = CommandQueue::new();
+        queue.push(command::EnableVrTracking);
+        queue.push(command::EnableVrTracking);
+        queue.push(command::BoxOff);
+        queue.push(command::EnableVrTracking);
+
+        assert_eq!(vec![AnyCommand::from(command::EnableVrTracking), command::BoxOff.into(), command::EnableVrTracking.into()],
+                   queue.commands().cloned().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn state_is_not_coalesced_past_other_commands() {
+        let mut queue = CommandQueue::new();
+        // Powering on twice before the queue drains.
+        for _ in 0..2 {
+            queue.push_set_power(true);
+            queue.push(command::SetVrMode { vr_mode: true });
+            queue.push(command::EnableVrTracking);
+        }
+
+        let power_on: Vec<AnyCommand> = vec![command::SetPower { on: true }.into(), command::SetVrMode { vr_mode: true }.into(), command::EnableVrTracking.into()];
+        assert_eq!([&power_on[..], &power_on[..]].concat(), queue.commands().cloned().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn paces_commands() {
+        let emulator = Emulator::new(Timeline::at_rest());
+        let mut control = emulator.connect().split().1;
+        let mut queue = CommandQueue::new();
+        let start = Instant::now();
+
+        queue.push(command::SetVrMode { vr_mode: true });
+        queue.push(command::EnableVrTracking);
+
+        assert!(queue.poll_at(&mut control, start).unwrap());
+        assert!(!queue.poll_at(&mut control, start + DEFAULT_MIN_INTERVAL / 2).unwrap());
+        assert!(queue.poll_at(&mut control, start + DEFAULT_MIN_INTERVAL).unwrap());
+        assert!(queue.is_empty());
+
+        let state = emulator.state();
+        assert!(state.vr_mode && state.vr_tracking);
+    }
+
+    #[test]
+    fn retries_until_confirmed() {
+        let emulator = Emulator::new(Timeline::at_rest());
+        emulator.set_pacing(Pacing::Unpaced);
+        let mut control = emulator.connect().split().1;
+        let mut queue = CommandQueue::new();
+        let start = Instant::now();
+
+        queue.push_set_power(true);
+        queue.push(command::SetVrMode { vr_mode: true });
+        assert!(queue.poll_at(&mut control, start).unwrap());
+
+        // As if the processor unit ignored it.
+        emulator.update_state(|state| state.powered = false);
+        let off = sensor::Status {
+            worn: true, display_active: false, hdmi_disconnected: false,
+            microphone_muted: false, headphone_connected: false, tick: false,
+        };
+        queue.confirm(&off);
+        assert!(!queue.poll_at(&mut control, start + DEFAULT_RETRY_INTERVAL / 2).unwrap());
+        assert!(queue.poll_at(&mut control, start + DEFAULT_RETRY_INTERVAL).unwrap());
+        assert!(emulator.state().powered);
+
+        queue.confirm(&sensor::Status { display_active: true, ..off });
+        assert_eq!(1, queue.len());
+        assert!(queue.poll_at(&mut control, start + DEFAULT_RETRY_INTERVAL * 2).unwrap());
+        assert!(emulator.state().vr_mode);
+    }
+
+    #[test]
+    fn gives_up_on_unconfirmed_commands() {
+        let emulator = Emulator::new(Timeline::at_rest());
+        let mut control = emulator.connect().split().1;
+        let mut queue = CommandQueue::new();
+        queue.set_retries(Duration::from_millis(10), 2);
+        let start = Instant::now();
+
+        queue.push_confirmed(command::EnableVrTracking, |_| false);
+        assert!(queue.poll_at(&mut control, start).unwrap());
+        assert!(queue.poll_at(&mut control, start + Duration::from_millis(20)).unwrap());
+        assert!(!queue.poll_at(&mut control, start + Duration::from_millis(40)).unwrap());
+        assert!(queue.is_empty());
+        assert_eq!(vec![AnyCommand::from(command::EnableVrTracking)], queue.take_given_up());
+        assert!(queue.take_given_up().is_empty());
+    }
+
+    #[test]
+    fn flushes_through_the_emulator() {
+        let emulator = Emulator::new(Timeline::at_rest());
+        let mut psvr = emulator.connect();
+        let mut queue = CommandQueue::new();
+
+        queue.push_set_power(true);
+        queue.push(command::SetVrMode { vr_mode: true });
+        queue.push(command::EnableVrTracking);
+        queue.flush(&mut psvr).unwrap();
+
+        let state = emulator.state();
+        assert!(state.powered && state.vr_mode && state.vr_tracking);
+        assert_eq!(3, state.commands.len());
+    }
+}
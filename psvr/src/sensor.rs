@@ -41,31 +41,60 @@
 //! uint8_t reserved3[12];
 //! ```
 
-use hmdee_core::Error;
 use hmdee_core::math::Scalar;
 use crate::usb::ByteOrder;
+use crate::wire;
 
-use std::io::prelude::*;
-use std::{cmp, fmt, io};
-use byteorder::ReadBytesExt;
+use core::{cmp, fmt};
+use byteorder::ByteOrder as _;
 use na;
 
 /// The sensor frame size.
 pub const FRAME_SIZE: usize = 64;
 
+/// Where each field starts in a sensor frame.
+mod offset {
+    pub const BUTTONS: usize = 0;
+    pub const VOLUME: usize = 2;
+    pub const STATUS: usize = 8;
+    pub const INSTANTS: [usize; 2] = [20, 36];
+
+    /// The size of each instant, including its reserved bytes.
+    pub const INSTANT_SIZE: usize = 16;
+}
+
 /// Something that can be read from raw bytes.
 pub trait Readable : Sized {
     /// Reads a new value from a reader.
-    fn read(read: &mut dyn Read) -> Result<Self, Error>;
+    fn read(read: &mut wire::Reader) -> Result<Self, wire::Error>;
 
     /// Reads a new value from raw bytes.
-    fn read_bytes(raw: &[u8; FRAME_SIZE]) -> Result<Self, Error> {
-        Self::read(&mut io::Cursor::new(&raw[..]))
+    fn read_bytes(raw: &[u8; FRAME_SIZE]) -> Result<Self, wire::Error> {
+        Self::read(&mut wire::Reader::new(raw))
+    }
+}
+
+/// Something that can be written as raw bytes.
+///
+/// This is the inverse of `Readable`. Reserved regions are written as zeros,
+/// and reading back anything written gives back the original value.
+pub trait Writable {
+    /// Writes the value to a writer.
+    fn write(&self, write: &mut wire::Writer) -> Result<(), wire::Error>;
+
+    /// Writes the value into a frame of raw bytes.
+    ///
+    /// Any bytes the value does not cover are left as zeros.
+    fn write_bytes(&self) -> [u8; FRAME_SIZE] {
+        let mut raw = [0; FRAME_SIZE];
+        self.write(&mut wire::Writer::new(&mut raw)).expect("value does not fit in a sensor frame");
+        raw
     }
 }
 
 /// The status of the PSVR headset sensors.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Readout {
     /// The status of the buttons.
     pub buttons: Buttons,
@@ -79,6 +108,7 @@ pub struct Readout {
 
 /// The status of the PSVR headset buttons.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Buttons {
     /// True if the plus button is pressed.
     ///
@@ -94,6 +124,7 @@ pub struct Buttons {
 
 /// The headset status.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Status {
     /// True if the headset is currently being worn.
     pub worn: bool,
@@ -109,6 +140,7 @@ pub struct Status {
 
 /// Inertia sensor values at an instant in time.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct InertiaInstant {
     /// The gyroscope readout.
     pub gyroscope_raw: na::Vector3<i16>,
@@ -117,46 +149,148 @@ pub struct InertiaInstant {
 }
 
 impl InertiaInstant {
-    /// Gets the accelerometer readout vector.
+    /// Creates an instant from gyroscope and accelerometer readout vectors.
+    ///
+    /// This is the inverse of `gyroscope` and `accelerometer`, up to the
+    /// precision and range of the raw readouts.
+    pub fn from_vectors(gyroscope: &na::Vector3<Scalar>,
+                        accelerometer: &na::Vector3<Scalar>) -> Self {
+        let gyro = |v: Scalar| {
+            let pi = ::core::f64::consts::PI as Scalar;
+            let raw = v / (pi / 180.0) / 2000.0 * 32768.0;
+            round(raw.clamp(-i16::MAX as Scalar, i16::MAX as Scalar))
+        };
+        // The accelerometer readout is 12 bits.
+        let accel = |v: Scalar| {
+            let raw = v * 32768.0 / 16.0;
+            round(raw.clamp(-2048.0, 2047.0))
+        };
+
+        // Turning half a turn about Z is its own inverse.
+        InertiaInstant {
+            gyroscope_raw: chip_to_canonical(gyroscope).map(gyro),
+            accelerometer_raw: chip_to_canonical(accelerometer).map(accel),
+        }
+    }
+
+    /// Gets the accelerometer readout vector, in g.
+    ///
+    /// The vector is in the canonical headset frame described in
+    /// `hmdee_core::math`, so it reads +1g on Z while the headset sits level.
     pub fn accelerometer(&self) -> na::Vector3<Scalar> {
-        let f = |c| {
+        let f = |c: i16| {
             let raw = c << 4; // Shift 12->16 bits.
-            -(raw as Scalar / 32768.0)
+            raw as Scalar / 32768.0
         };
 
-        na::Vector3::new(f(self.accelerometer_raw.x),
-                         f(self.accelerometer_raw.y),
-                         f(-self.accelerometer_raw.z))
+        chip_to_canonical(&self.accelerometer_raw.map(f))
     }
 
-    /// Gets the gyroscope readout vector.
+    /// Gets the gyroscope readout vector, in radians per second.
+    ///
+    /// The vector is in the canonical headset frame described in
+    /// `hmdee_core::math`, with positive rotation following the right-hand
+    /// rule.
     pub fn gyroscope(&self) -> na::Vector3<Scalar> {
-        let f = |c| {
-            let pi = ::std::f64::consts::PI as Scalar;
+        let f = |c: i16| {
+            let pi = ::core::f64::consts::PI as Scalar;
             (c as Scalar / 32768.0) * 2000.0
                 * (pi / 180.0) // DEGTORAD
         };
 
-        na::Vector3::new(f(self.gyroscope_raw.x),
-                         f(self.gyroscope_raw.y),
-                         f(-self.gyroscope_raw.z))
+        chip_to_canonical(&self.gyroscope_raw.map(f))
+    }
+}
+
+/// Turns a vector from the sensor chip's axes into the canonical headset
+/// frame.
+///
+/// The chip sits half a turn about Z from the canonical frame, so its X and
+/// Y axes point the other way. The gyroscope and accelerometer share the
+/// chip, and so share this rotation.
+fn chip_to_canonical(v: &na::Vector3<Scalar>) -> na::Vector3<Scalar> {
+    na::Vector3::new(-v.x, -v.y, v.z)
+}
+
+/// Rounds a readout to the nearest integer, away from zero on ties.
+///
+/// `f32::round` needs the standard library.
+fn round(raw: Scalar) -> i16 {
+    (if raw < 0.0 { raw - 0.5 } else { raw + 0.5 }) as i16
+}
+
+impl Readout {
+    /// Decodes a whole sensor frame.
+    ///
+    /// Every field sits at a fixed offset in the frame, so this reads them
+    /// directly and cannot fail.
+    pub fn from_frame(raw: &[u8; FRAME_SIZE]) -> Self {
+        Readout {
+            buttons: Buttons::from_bits(raw[offset::BUTTONS]),
+            volume: raw[offset::VOLUME],
+            status: Status::from_bits(raw[offset::STATUS]),
+            instants: [
+                InertiaInstant::from_bytes(&raw[offset::INSTANTS[0]..][..offset::INSTANT_SIZE]),
+                InertiaInstant::from_bytes(&raw[offset::INSTANTS[1]..][..offset::INSTANT_SIZE]),
+            ],
+        }
+    }
+}
+
+impl Buttons {
+    fn from_bits(b: u8) -> Self {
+        Buttons {
+            // reserved:  (b & 0b0001) != 0,
+            plus:  (b & 0b0010) != 0,
+            minus: (b & 0b0100) != 0,
+            mute:  (b & 0b1000) != 0,
+        }
+    }
+}
+
+impl Status {
+    fn from_bits(b: u8) -> Self {
+        Status {
+            worn:                (b & (1 << 0)) != 0,
+            display_active:      (b & (1 << 1)) != 0,
+            hdmi_disconnected:   (b & (1 << 2)) != 0,
+            microphone_muted:    (b & (1 << 3)) != 0,
+            headphone_connected: (b & (1 << 4)) != 0,
+            // reserved:         (b & (1 << 5)) != 0,
+            tick:                (b & (1 << 6)) != 0,
+        }
+    }
+}
+
+impl InertiaInstant {
+    fn from_bytes(raw: &[u8]) -> Self {
+        let at = |i: usize| ByteOrder::read_i16(&raw[i * 2..]);
+
+        InertiaInstant {
+            gyroscope_raw: na::Vector3::new(at(0), at(1), at(2)),
+            accelerometer_raw: na::Vector3::new(at(3), at(4), at(5)),
+        }
     }
 }
 
 impl Readable for Readout {
-    fn read(read: &mut dyn Read) -> Result<Self, Error> {
+    fn read_bytes(raw: &[u8; FRAME_SIZE]) -> Result<Self, wire::Error> {
+        Ok(Readout::from_frame(raw))
+    }
+
+    fn read(read: &mut wire::Reader) -> Result<Self, wire::Error> {
         let buttons = Buttons::read(read)?;
 
-        read_reserved(read, 1)?;
+        read.skip(1)?;
         let volume = read.read_u8()?;
-        read_reserved(read, 5)?;
+        read.skip(5)?;
         let status = Status::read(read)?;
-        read_reserved(read, 11)?;
+        read.skip(11)?;
 
         let instant_one = InertiaInstant::read(read)?;
         let instant_two = InertiaInstant::read(read)?;
         let instants = [instant_one, instant_two];
-        read_reserved(read, 12)?;
+        read.skip(12)?;
 
         Ok(Readout {
             buttons, volume, status, instants,
@@ -164,45 +298,22 @@ impl Readable for Readout {
     }
 }
 
-/// Reads reserved data.
-fn read_reserved(read: &mut dyn Read, n: usize) -> Result<(), Error> {
-    for _ in 0..n {
-        read.read_u8()?;
-    }
-    Ok(())
-}
-
 
 impl Readable for Buttons {
-    fn read(read: &mut dyn Read) -> Result<Self, Error> {
-        let b = read.read_u8()?;
-        Ok(Buttons {
-            // reserved:  (b & 0b0001) != 0,
-            plus:  (b & 0b0010) != 0,
-            minus: (b & 0b0100) != 0,
-            mute:  (b & 0b1000) != 0,
-        })
+    fn read(read: &mut wire::Reader) -> Result<Self, wire::Error> {
+        Ok(Buttons::from_bits(read.read_u8()?))
     }
 }
 
 impl Readable for Status {
-    fn read(read: &mut dyn Read) -> Result<Self, Error> {
-        let b = read.read_u8()?;
-        Ok(Status {
-            worn:                (b & (1 << 0)) != 0,
-            display_active:      (b & (1 << 1)) != 0,
-            hdmi_disconnected:   (b & (1 << 2)) != 0,
-            microphone_muted:    (b & (1 << 3)) != 0,
-            headphone_connected: (b & (1 << 4)) != 0,
-            // reserved:         (b & (1 << 5)) != 0,
-            tick:                (b & (1 << 6)) != 0,
-        })
+    fn read(read: &mut wire::Reader) -> Result<Self, wire::Error> {
+        Ok(Status::from_bits(read.read_u8()?))
     }
 }
 
 impl<T> Readable for na::Vector3<T>
     where T: Copy + Readable + fmt::Debug + cmp::PartialEq + 'static{
-    fn read(read: &mut dyn Read) -> Result<Self, Error> {
+    fn read(read: &mut wire::Reader) -> Result<Self, wire::Error> {
         Ok(na::Vector3::new(
             Readable::read(read)?,
             Readable::read(read)?,
@@ -212,28 +323,85 @@ impl<T> Readable for na::Vector3<T>
 }
 
 impl Readable for InertiaInstant {
-    fn read(read: &mut dyn Read) -> Result<Self, Error> {
+    fn read(read: &mut wire::Reader) -> Result<Self, wire::Error> {
         let gyroscope_raw = Readable::read(read)?;
         let accelerometer_raw = Readable::read(read)?;
-        read_reserved(read, 4)?;
+        read.skip(4)?;
 
         Ok(InertiaInstant { gyroscope_raw, accelerometer_raw })
     }
 }
 
 macro_rules! impl_readable_primitive {
-    ($ty:ident, $read_fn:ident, $byte_order:ty) => {
+    ($ty:ident, $read_fn:ident) => {
         impl Readable for $ty {
-            fn read(read: &mut dyn Read) -> Result<Self, Error> {
-                Ok(read.$read_fn::<$byte_order>()?)
+            fn read(read: &mut wire::Reader) -> Result<Self, wire::Error> {
+                read.$read_fn()
             }
         }
     };
+}
 
-    ($ty:ident, $read_fn:ident) => {
-        impl Readable for $ty {
-            fn read(read: &mut dyn Read) -> Result<Self, Error> {
-                Ok(read.$read_fn()?)
+impl Writable for Readout {
+    fn write(&self, write: &mut wire::Writer) -> Result<(), wire::Error> {
+        self.buttons.write(write)?;
+
+        write.write_zeros(1)?;
+        write.write_u8(self.volume)?;
+        write.write_zeros(5)?;
+        self.status.write(write)?;
+        write.write_zeros(11)?;
+
+        for instant in self.instants.iter() {
+            instant.write(write)?;
+        }
+        write.write_zeros(12)
+    }
+}
+
+impl Writable for Buttons {
+    fn write(&self, write: &mut wire::Writer) -> Result<(), wire::Error> {
+        let b = (self.plus as u8) << 1 |
+                (self.minus as u8) << 2 |
+                (self.mute as u8) << 3;
+        write.write_u8(b)
+    }
+}
+
+impl Writable for Status {
+    fn write(&self, write: &mut wire::Writer) -> Result<(), wire::Error> {
+        let b = (self.worn as u8) |
+                (self.display_active as u8) << 1 |
+                (self.hdmi_disconnected as u8) << 2 |
+                (self.microphone_muted as u8) << 3 |
+                (self.headphone_connected as u8) << 4 |
+                (self.tick as u8) << 6;
+        write.write_u8(b)
+    }
+}
+
+impl<T> Writable for na::Vector3<T>
+    where T: Copy + Writable + fmt::Debug + cmp::PartialEq + 'static {
+    fn write(&self, write: &mut wire::Writer) -> Result<(), wire::Error> {
+        self.x.write(write)?;
+        self.y.write(write)?;
+        self.z.write(write)
+    }
+}
+
+impl Writable for InertiaInstant {
+    fn write(&self, write: &mut wire::Writer) -> Result<(), wire::Error> {
+        self.gyroscope_raw.write(write)?;
+        self.accelerometer_raw.write(write)?;
+        write.write_zeros(4)
+    }
+}
+
+macro_rules! impl_writable_primitive {
+    ($ty:ident, $write_fn:ident) => {
+        impl Writable for $ty {
+            fn write(&self, write: &mut wire::Writer) -> Result<(), wire::Error> {
+                write.$write_fn(*self)
             }
         }
     };
@@ -241,23 +409,135 @@ macro_rules! impl_readable_primitive {
 
 impl_readable_primitive!(i8, read_i8);
 impl_readable_primitive!(u8, read_u8);
-impl_readable_primitive!(i16, read_i16, ByteOrder);
-impl_readable_primitive!(u16, read_u16, ByteOrder);
-impl_readable_primitive!(i32, read_i32, ByteOrder);
-impl_readable_primitive!(u32, read_u32, ByteOrder);
+impl_readable_primitive!(i16, read_i16);
+impl_readable_primitive!(u16, read_u16);
+impl_readable_primitive!(i32, read_i32);
+impl_readable_primitive!(u32, read_u32);
+
+impl_writable_primitive!(i8, write_i8);
+impl_writable_primitive!(u8, write_u8);
+impl_writable_primitive!(i16, write_i16);
+impl_writable_primitive!(u16, write_u16);
+impl_writable_primitive!(i32, write_i32);
+impl_writable_primitive!(u32, write_u32);
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use std::io;
 
     #[test]
     fn reads_exactly_64_bytes() {
         let data: [u8; 64] = [0; 64];
-        let mut read = io::Cursor::new(&data[..]);
+        let mut read = wire::Reader::new(&data);
 
         assert_eq!(0, read.position());
         Readout::read(&mut read).expect("failed to parse sensor readout");
-        assert_eq!(FRAME_SIZE, read.position() as usize);
+        assert_eq!(FRAME_SIZE, read.position());
+    }
+
+    fn example_readout() -> Readout {
+        Readout {
+            buttons: Buttons { plus: true, minus: false, mute: true },
+            volume: 42,
+            status: Status {
+                worn: true,
+                display_active: false,
+                hdmi_disconnected: true,
+                microphone_muted: false,
+                headphone_connected: true,
+                tick: true,
+            },
+            instants: [
+                InertiaInstant {
+                    gyroscope_raw: na::Vector3::new(1, -2, 3),
+                    accelerometer_raw: na::Vector3::new(-400, 500, i16::MIN),
+                },
+                InertiaInstant {
+                    gyroscope_raw: na::Vector3::new(i16::MAX, 0, -1),
+                    accelerometer_raw: na::Vector3::new(7, 8, 9),
+                },
+            ],
+        }
+    }
+
+    /// Gets what the chip reads after turning `angle` about one of its own
+    /// axes from level, turning at `rate` radians per second.
+    fn chip_turning(axis: na::Vector3<Scalar>, angle: Scalar, rate: Scalar) -> InertiaInstant {
+        let orientation = na::UnitQuaternion::from_axis_angle(&na::Unit::new_normalize(axis), angle);
+        let gravity = orientation.inverse() * na::Vector3::z();
+        let pi = ::core::f64::consts::PI as Scalar;
+
+        InertiaInstant {
+            gyroscope_raw: (axis * rate).map(|v| (v / (pi / 180.0) / 2000.0 * 32768.0) as i16),
+            accelerometer_raw: gravity.map(|v| (v * 2048.0) as i16),
+        }
+    }
+
+    #[test]
+    fn gyroscope_agrees_with_how_gravity_moves() {
+        let step = 0.1;
+
+        for &axis in &[na::Vector3::x(), na::Vector3::y(), -na::Vector3::x()] {
+            let before = chip_turning(axis, 0.3, 1.0);
+            let after = chip_turning(axis, 0.3 + step, 1.0);
+
+            // Gravity turns the other way to the headset, seen from the headset.
+            let expected = -before.gyroscope().cross(&before.accelerometer()) * step;
+            let moved = after.accelerometer() - before.accelerometer();
+            assert!(moved.dot(&expected) > 0.9 * moved.norm() * expected.norm(),
+                    "turning about {:?} moved gravity by {:?} but the gyroscope expects {:?}", axis, moved, expected);
+        }
+    }
+
+    #[test]
+    fn writes_exactly_64_bytes() {
+        let mut raw = [0; FRAME_SIZE + 1];
+        let mut write = wire::Writer::new(&mut raw);
+        example_readout().write(&mut write).expect("failed to write sensor readout");
+
+        assert_eq!(FRAME_SIZE, write.position());
+    }
+
+    #[test]
+    fn readout_round_trips() {
+        let readout = example_readout();
+
+        assert_eq!(readout, Readout::read_bytes(&readout.write_bytes()).unwrap());
+    }
+
+    #[test]
+    fn from_frame_matches_streaming_read() {
+        let mut raw = [0; FRAME_SIZE];
+        for (i, b) in raw.iter_mut().enumerate() {
+            *b = (i as u8).wrapping_mul(37);
+        }
+
+        let streamed = Readout::read(&mut wire::Reader::new(&raw)).unwrap();
+        assert_eq!(streamed, Readout::from_frame(&raw));
+    }
+
+    #[test]
+    fn frames_with_zeroed_reserved_regions_round_trip() {
+        let mut raw = [0; FRAME_SIZE];
+        raw[0] = 0b1010;
+        raw[2] = 13;
+        raw[8] = 0b0101_0011;
+        for (i, b) in raw[20..52].iter_mut().enumerate() {
+            *b = i as u8 + 1;
+        }
+        // Zero the reserved bytes after each instant.
+        raw[32..36].copy_from_slice(&[0; 4]);
+        raw[48..52].copy_from_slice(&[0; 4]);
+
+        assert_eq!(&raw[..], &Readout::read_bytes(&raw).unwrap().write_bytes()[..]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn readout_round_trips_through_json() {
+        let readout = example_readout();
+        let json = serde_json::to_string(&readout).unwrap();
+
+        assert_eq!(readout, serde_json::from_str(&json).unwrap());
     }
 }
@@ -0,0 +1,39 @@
+//! Transports that carry HID reports to and from a PSVR interface.
+
+use hmdee_core::Error;
+use std::time;
+#[cfg(feature = "hidapi")] use hidapi;
+
+/// A connection to a single HID interface of the PSVR.
+pub trait Transport : Send {
+    /// Writes a report to the interface.
+    ///
+    /// Returns the number of bytes written.
+    fn write(&mut self, data: &[u8]) -> Result<usize, Error>;
+
+    /// Reads a report from the interface.
+    ///
+    /// Waits at most `timeout_milliseconds` for a report to arrive, or
+    /// forever if the timeout is negative. Returns the number of bytes read,
+    /// which is zero if no report arrived in time.
+    fn read_timeout(&mut self, buf: &mut [u8], timeout_milliseconds: i32) -> Result<usize, Error>;
+
+    /// Gets the current time by the clock that paces the interface's reports.
+    ///
+    /// A report that was just read arrived at this time. Real interfaces
+    /// use the system clock.
+    fn now(&self) -> time::Instant {
+        time::Instant::now()
+    }
+}
+
+#[cfg(feature = "hidapi")]
+impl Transport for hidapi::HidDevice {
+    fn write(&mut self, data: &[u8]) -> Result<usize, Error> {
+        hidapi::HidDevice::write(self, data).map_err(Error::communication_error)
+    }
+
+    fn read_timeout(&mut self, buf: &mut [u8], timeout_milliseconds: i32) -> Result<usize, Error> {
+        hidapi::HidDevice::read_timeout(self, buf, timeout_milliseconds).map_err(Error::communication_error)
+    }
+}
@@ -1,6 +1,6 @@
 //! PSVR usb stuff.
 
-use hmdee_core;
+#[cfg(feature = "std")] use hmdee_core;
 
 /// The vendor ID of the PSVR.
 pub const PSVR_VID: u16 = 0x054c;
@@ -12,6 +12,7 @@ pub type ByteOrder = ::byteorder::LittleEndian;
 
 /// PSVR USB interface definitions.
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Interface {
     Audio3D = 0,
     AudioControl = 1,
@@ -26,6 +27,7 @@ pub enum Interface {
     HidControl2 = 8,
 }
 
+#[cfg(feature = "std")]
 impl Interface {
     pub fn from_i32(value: i32) -> Result<Self, hmdee_core::Error> {
         use crate::usb::Interface::*;
@@ -0,0 +1,183 @@
+//! Reading and writing raw protocol bytes.
+//!
+//! Everything here works on borrowed byte slices, and so is available
+//! without the standard library or an allocator.
+
+use crate::usb::ByteOrder;
+
+use core::fmt;
+use byteorder::ByteOrder as _;
+
+/// An error in the raw protocol bytes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// Ran out of bytes while reading or writing.
+    UnexpectedEnd {
+        /// How many bytes were needed.
+        needed: usize,
+        /// How many bytes were left.
+        available: usize,
+    },
+    /// A command header has the wrong magic byte.
+    BadMagic {
+        id: u8,
+        magic: u8,
+    },
+    /// A command payload is not the size of the command.
+    PayloadLength {
+        id: u8,
+        length: usize,
+    },
+    /// A command payload is too large to describe in its header.
+    PayloadTooLarge {
+        id: u8,
+        length: usize,
+    },
+}
+
+/// Reads values from the front of a byte slice.
+#[derive(Clone, Debug)]
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+/// Writes values to the front of a byte slice.
+#[derive(Debug)]
+pub struct Writer<'a> {
+    bytes: &'a mut [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    /// Creates a reader starting at the beginning of some bytes.
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Reader { bytes, position: 0 }
+    }
+
+    /// Gets how many bytes have been read.
+    pub fn position(&self) -> usize { self.position }
+
+    /// Gets how many bytes are left to read.
+    pub fn remaining(&self) -> usize { self.bytes.len() - self.position }
+
+    /// Reads the next `n` bytes.
+    pub fn read_slice(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        let slice = self.bytes[self.position..].get(..n)
+            .ok_or(Error::UnexpectedEnd { needed: n, available: self.remaining() })?;
+        self.position += n;
+        Ok(slice)
+    }
+
+    /// Reads the next bytes into an array.
+    pub fn read_array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.read_slice(N)?);
+        Ok(array)
+    }
+
+    /// Skips over bytes, such as reserved regions.
+    pub fn skip(&mut self, n: usize) -> Result<(), Error> {
+        self.read_slice(n).map(|_| ())
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, Error> { Ok(self.read_slice(1)?[0]) }
+    pub fn read_i8(&mut self) -> Result<i8, Error> { Ok(self.read_u8()? as i8) }
+    pub fn read_u16(&mut self) -> Result<u16, Error> { Ok(ByteOrder::read_u16(self.read_slice(2)?)) }
+    pub fn read_i16(&mut self) -> Result<i16, Error> { Ok(ByteOrder::read_i16(self.read_slice(2)?)) }
+    pub fn read_u32(&mut self) -> Result<u32, Error> { Ok(ByteOrder::read_u32(self.read_slice(4)?)) }
+    pub fn read_i32(&mut self) -> Result<i32, Error> { Ok(ByteOrder::read_i32(self.read_slice(4)?)) }
+}
+
+impl<'a> Writer<'a> {
+    /// Creates a writer starting at the beginning of some bytes.
+    pub fn new(bytes: &'a mut [u8]) -> Self {
+        Writer { bytes, position: 0 }
+    }
+
+    /// Gets how many bytes have been written.
+    pub fn position(&self) -> usize { self.position }
+
+    /// Gets how many more bytes can be written.
+    pub fn remaining(&self) -> usize { self.bytes.len() - self.position }
+
+    /// Gets the next `n` bytes to be written.
+    fn next_slice(&mut self, n: usize) -> Result<&mut [u8], Error> {
+        let available = self.remaining();
+        let slice = self.bytes[self.position..].get_mut(..n)
+            .ok_or(Error::UnexpectedEnd { needed: n, available })?;
+        self.position += n;
+        Ok(slice)
+    }
+
+    /// Writes bytes as they are.
+    pub fn write_slice(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.next_slice(bytes.len())?.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// Writes zeros in place of reserved regions.
+    pub fn write_zeros(&mut self, n: usize) -> Result<(), Error> {
+        for b in self.next_slice(n)?.iter_mut() {
+            *b = 0;
+        }
+        Ok(())
+    }
+
+    pub fn write_u8(&mut self, value: u8) -> Result<(), Error> { self.write_slice(&[value]) }
+    pub fn write_i8(&mut self, value: i8) -> Result<(), Error> { self.write_u8(value as u8) }
+    pub fn write_u16(&mut self, value: u16) -> Result<(), Error> { ByteOrder::write_u16(self.next_slice(2)?, value); Ok(()) }
+    pub fn write_i16(&mut self, value: i16) -> Result<(), Error> { ByteOrder::write_i16(self.next_slice(2)?, value); Ok(()) }
+    pub fn write_u32(&mut self, value: u32) -> Result<(), Error> { ByteOrder::write_u32(self.next_slice(4)?, value); Ok(()) }
+    pub fn write_i32(&mut self, value: i32) -> Result<(), Error> { ByteOrder::write_i32(self.next_slice(4)?, value); Ok(()) }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::UnexpectedEnd { needed, available } =>
+                write!(fmt, "needed {} more bytes but only {} are left", needed, available),
+            Error::BadMagic { id, magic } =>
+                write!(fmt, "command 0x{:02x} has magic byte 0x{:02x} but it should be 0x{:02x}",
+                       id, magic, crate::protocol::COMMAND_MAGIC),
+            Error::PayloadLength { id, length } =>
+                write!(fmt, "payload of {} bytes is the wrong size for command 0x{:02x}", length, id),
+            Error::PayloadTooLarge { id, length } =>
+                write!(fmt, "payload of {} bytes is too large for command 0x{:02x}", length, id),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<Error> for hmdee_core::Error {
+    fn from(e: Error) -> Self {
+        hmdee_core::Error::communication_error(e)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn reads_little_endian() {
+        let mut read = Reader::new(&[0x34, 0x12, 0xff, 0xff, 0x01]);
+
+        assert_eq!(Ok(0x1234), read.read_u16());
+        assert_eq!(Ok(-1), read.read_i16());
+        assert_eq!(Err(Error::UnexpectedEnd { needed: 4, available: 1 }), read.read_u32());
+        assert_eq!(4, read.position());
+    }
+
+    #[test]
+    fn writes_little_endian() {
+        let mut raw = [0xff; 7];
+        let mut write = Writer::new(&mut raw);
+
+        write.write_u16(0x1234).unwrap();
+        write.write_zeros(1).unwrap();
+        write.write_i32(-2).unwrap();
+        assert_eq!(Err(Error::UnexpectedEnd { needed: 1, available: 0 }), write.write_u8(0));
+        assert_eq!([0x34, 0x12, 0, 0xfe, 0xff, 0xff, 0xff], raw);
+    }
+}
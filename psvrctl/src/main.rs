@@ -0,0 +1,385 @@
+//! Command line tool for controlling the PlayStation VR.
+
+extern crate failure;
+extern crate psvr;
+#[macro_use] extern crate serde_json;
+
+use psvr::{command, hidapi, sensor, usb};
+use serde_json::Value;
+
+use std::{env, process};
+use std::time::Duration;
+
+const USAGE: &str = "\
+usage: psvrctl [--json] <command> [arguments]
+
+commands:
+  list                        list connected PSVRs and their USB interfaces
+  info                        show the firmware version and serial number
+  power on|off                power the headset on or off
+  mode vr|cinematic           switch between VR mode and cinematic mode
+  leds LED=VALUE...           set LED brightness, where LED is a-i or 'all'
+                              and VALUE is between 0 and 100
+  cinematic [--size N] [--distance N] [--brightness N]
+                              configure cinematic mode
+  monitor [--count N]         print orientation, buttons, and status as
+                              sensor reports arrive
+  raw ID [PAYLOAD] [--reply]  send a command with an arbitrary ID and hex
+                              payload, optionally waiting for a reply
+
+options:
+  --json                      print results as JSON, one object per line";
+
+/// The number of LEDs on the headset.
+const LED_COUNT: usize = 9;
+/// The LED names, in mask bit order.
+const LED_NAMES: [char; LED_COUNT] = ['a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'i'];
+/// How long `raw --reply` waits for a reply.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How results are printed.
+struct Output {
+    json: bool,
+}
+
+fn main() {
+    match run() {
+        Ok(..) => (),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            process::exit(1);
+        },
+    }
+}
+
+fn run() -> Result<(), failure::Error> {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+
+    let json = args.iter().any(|a| a == "--json");
+    args.retain(|a| a != "--json");
+    let output = Output { json };
+
+    let (command, args) = match args.split_first() {
+        Some((command, args)) => (command.as_str(), args),
+        None => return Err(usage_error("no command given")),
+    };
+
+    // Each command checks its arguments before it opens HIDAPI, so that
+    // mistakes are reported as such even where HIDAPI can't start.
+    match command {
+        "list" => list(&output),
+        "info" => info(&output),
+        "power" => power(&output, args),
+        "mode" => mode(&output, args),
+        "leds" => leds(&output, args),
+        "cinematic" => cinematic(&output, args),
+        "monitor" => monitor(&output, args),
+        "raw" => raw(&output, args),
+        "help" | "-h" | "--help" => {
+            println!("{}", USAGE);
+            Ok(())
+        },
+        _ => Err(usage_error(&format!("unknown command '{}'", command))),
+    }
+}
+
+fn list(output: &Output) -> Result<(), failure::Error> {
+    let hidapi = hidapi::HidApi::new()?;
+    let interfaces: Vec<_> = hidapi.device_list().filter(|device_info| {
+        device_info.vendor_id() == usb::PSVR_VID && device_info.product_id() == usb::PSVR_PID
+    }).map(|device_info| {
+        let number = device_info.interface_number();
+        let name = usb::Interface::from_i32(number).map(|i| format!("{:?}", i)).unwrap_or_else(|_| "Unknown".to_owned());
+
+        json!({
+            "interface": number,
+            "name": name,
+            "path": device_info.path().to_string_lossy(),
+        })
+    }).collect();
+
+    if output.json {
+        output.print(json!({ "vendor_id": usb::PSVR_VID, "product_id": usb::PSVR_PID, "interfaces": interfaces }), "");
+    } else if interfaces.is_empty() {
+        println!("no PSVR connected");
+    } else {
+        println!("PSVR {:04x}:{:04x}", usb::PSVR_VID, usb::PSVR_PID);
+        for interface in interfaces {
+            println!("  interface {} {:<16} {}", interface["interface"], interface["name"].as_str().unwrap_or(""),
+                     interface["path"].as_str().unwrap_or(""));
+        }
+    }
+
+    Ok(())
+}
+
+fn info(output: &Output) -> Result<(), failure::Error> {
+    let info = connect()?.read_device_info()?;
+    let firmware_version = format!("{}.{}", info.firmware_version_major, info.firmware_version_minor);
+
+    output.print(json!({
+        "firmware_version": firmware_version,
+        "serial_number": info.serial_number(),
+    }), &format!("firmware version: {}\nserial number: {}", firmware_version, info.serial_number()));
+    Ok(())
+}
+
+fn power(output: &Output, args: &[String]) -> Result<(), failure::Error> {
+    let on = match single_argument(args)? {
+        "on" => true,
+        "off" => false,
+        other => return Err(usage_error(&format!("power state must be 'on' or 'off', not '{}'", other))),
+    };
+
+    connect()?.set_power(on)?;
+    output.print(json!({ "power": on }), if on { "powered on" } else { "powered off" });
+    Ok(())
+}
+
+fn mode(output: &Output, args: &[String]) -> Result<(), failure::Error> {
+    let vr_mode = match single_argument(args)? {
+        "vr" => true,
+        "cinematic" => false,
+        other => return Err(usage_error(&format!("mode must be 'vr' or 'cinematic', not '{}'", other))),
+    };
+
+    connect()?.set_vr_mode(vr_mode)?;
+    let mode = if vr_mode { "vr" } else { "cinematic" };
+    output.print(json!({ "mode": mode }), &format!("switched to {} mode", mode));
+    Ok(())
+}
+
+fn leds(output: &Output, args: &[String]) -> Result<(), failure::Error> {
+    if args.is_empty() {
+        return Err(usage_error("no LEDs given"));
+    }
+
+    let mut command = command::SetHmdLeds { led_mask: 0, values: [0; LED_COUNT], reserved: [0; 5] };
+
+    for arg in args {
+        let mut parts = arg.splitn(2, '=');
+        let led = parts.next().unwrap_or("");
+        let value: u8 = match parts.next().map(str::parse) {
+            Some(Ok(value)) if value <= 100 => value,
+            _ => return Err(usage_error(&format!("LED values must be written as LED=VALUE with VALUE between 0 and 100, not '{}'", arg))),
+        };
+
+        let indices: Vec<usize> = if led == "all" {
+            (0..LED_COUNT).collect()
+        } else {
+            match LED_NAMES.iter().position(|&name| led.len() == 1 && led.starts_with(name)) {
+                Some(index) => vec![index],
+                None => return Err(usage_error(&format!("unknown LED '{}', expected a-i or 'all'", led))),
+            }
+        };
+
+        for index in indices {
+            command.led_mask |= 1 << index;
+            command.values[index] = value;
+        }
+    }
+
+    connect()?.send_command(&command)?;
+
+    let leds: serde_json::Map<String, Value> = (0..LED_COUNT).filter(|i| command.led_mask & (1 << i) != 0)
+        .map(|i| (LED_NAMES[i].to_string(), json!(command.values[i]))).collect();
+    output.print(json!({ "leds": leds }), "LEDs updated");
+    Ok(())
+}
+
+fn cinematic(output: &Output, args: &[String]) -> Result<(), failure::Error> {
+    let mut command = command::SetCinematicConfiguration {
+        mask: 0,
+        screen_size: 0,
+        screen_distance: 0,
+        ipd: 0,
+        reserved0: [0; 6],
+        brightness: 0,
+        mic_volume: 0,
+        reserved1: [0; 2],
+        unknown: false,
+        reserved2: 0,
+    };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value: u8 = match args.next().map(|v| v.parse()) {
+            Some(Ok(value)) => value,
+            _ => return Err(usage_error(&format!("'{}' needs a numeric value", arg))),
+        };
+
+        match arg.as_str() {
+            "--size" => { command.screen_size = value; command.mask |= command::SetCinematicConfiguration::MASK_SCREEN_SIZE },
+            "--distance" => { command.screen_distance = value; command.mask |= command::SetCinematicConfiguration::MASK_SCREEN_DISTANCE },
+            "--brightness" => { command.brightness = value; command.mask |= command::SetCinematicConfiguration::MASK_BRIGHTNESS },
+            _ => return Err(usage_error(&format!("unknown cinematic option '{}'", arg))),
+        }
+    }
+
+    if command.mask == 0 {
+        return Err(usage_error("nothing to configure, give at least one of --size, --distance, or --brightness"));
+    }
+
+    connect()?.send_command(&command)?;
+
+    let mut applied = serde_json::Map::new();
+    if command.mask & command::SetCinematicConfiguration::MASK_SCREEN_SIZE != 0 { applied.insert("size".to_owned(), json!(command.screen_size)); }
+    if command.mask & command::SetCinematicConfiguration::MASK_SCREEN_DISTANCE != 0 { applied.insert("distance".to_owned(), json!(command.screen_distance)); }
+    if command.mask & command::SetCinematicConfiguration::MASK_BRIGHTNESS != 0 { applied.insert("brightness".to_owned(), json!(command.brightness)); }
+    output.print(json!({ "cinematic": applied }), "cinematic configuration updated");
+    Ok(())
+}
+
+fn monitor(output: &Output, args: &[String]) -> Result<(), failure::Error> {
+    let count: Option<usize> = match args {
+        [] => None,
+        [flag, count] if flag == "--count" => Some(count.parse()?),
+        _ => return Err(usage_error("monitor only accepts '--count N'")),
+    };
+
+    let mut psvr = connect()?;
+    let mut received = 0;
+
+    while count.map(|count| received < count).unwrap_or(true) {
+        let readout = psvr.receive_sensor()?;
+        let orientation = psvr.orientation().quaternion();
+        received += 1;
+
+        let value = readout_json(&readout, [orientation.w, orientation.i, orientation.j, orientation.k]);
+        let text = format!("orientation [{:+.4}, {:+.4}, {:+.4}, {:+.4}] buttons {:?} {:?}",
+                           orientation.w, orientation.i, orientation.j, orientation.k, readout.buttons, readout.status);
+        output.print(value, &text);
+    }
+
+    Ok(())
+}
+
+fn raw(output: &Output, args: &[String]) -> Result<(), failure::Error> {
+    let wait_for_reply = args.iter().any(|a| a == "--reply");
+    let args: Vec<&String> = args.iter().filter(|a| *a != "--reply").collect();
+
+    let (id, payload) = match args.split_first() {
+        Some((id, payload)) => (parse_byte(id)?, parse_hex(&payload.iter().map(|s| s.as_str()).collect::<String>())?),
+        None => return Err(usage_error("raw needs a command ID")),
+    };
+
+    let mut psvr = connect()?;
+    psvr.send_raw_command(id, &payload)?;
+
+    if !wait_for_reply {
+        output.print(json!({ "sent": { "id": id, "payload": hex(&payload) } }), "sent");
+        return Ok(());
+    }
+
+    match psvr.receive_reply(id, REPLY_TIMEOUT)? {
+        Some(reply) => output.print(json!({
+            "reply": {
+                "id": reply.header.id,
+                "status": reply.header.status,
+                "payload": hex(&reply.payload),
+            },
+        }), &format!("reply 0x{:02x} (status 0x{:02x}): {}", reply.header.id, reply.header.status, hex(&reply.payload))),
+        None => output.print(json!({ "reply": null }), "no reply"),
+    }
+    Ok(())
+}
+
+impl Output {
+    /// Prints a result, either as a line of JSON or as human-readable text.
+    fn print(&self, value: Value, text: &str) {
+        if self.json {
+            println!("{}", value);
+        } else if !text.is_empty() {
+            println!("{}", text);
+        }
+    }
+}
+
+/// Opens the first connected PSVR.
+fn connect() -> Result<psvr::Psvr, failure::Error> {
+    let hidapi = hidapi::HidApi::new()?;
+    match psvr::get(&hidapi)? {
+        Some(psvr) => Ok(psvr),
+        None => Err(failure::format_err!("no PSVR connected")),
+    }
+}
+
+fn readout_json(readout: &sensor::Readout, orientation: [f32; 4]) -> Value {
+    json!({
+        "orientation": orientation,
+        "volume": readout.volume,
+        "buttons": {
+            "plus": readout.buttons.plus,
+            "minus": readout.buttons.minus,
+            "mute": readout.buttons.mute,
+        },
+        "status": {
+            "worn": readout.status.worn,
+            "display_active": readout.status.display_active,
+            "hdmi_disconnected": readout.status.hdmi_disconnected,
+            "microphone_muted": readout.status.microphone_muted,
+            "headphone_connected": readout.status.headphone_connected,
+        },
+    })
+}
+
+fn single_argument(args: &[String]) -> Result<&str, failure::Error> {
+    match args {
+        [arg] => Ok(arg),
+        _ => Err(usage_error("expected exactly one argument")),
+    }
+}
+
+/// Parses a decimal or `0x`-prefixed hexadecimal byte.
+fn parse_byte(s: &str) -> Result<u8, failure::Error> {
+    if let Some(hex) = s.strip_prefix("0x") {
+        Ok(u8::from_str_radix(hex, 16)?)
+    } else {
+        Ok(s.parse()?)
+    }
+}
+
+/// Parses a string of hex digits into bytes.
+fn parse_hex(s: &str) -> Result<Vec<u8>, failure::Error> {
+    s.trim_start_matches("0x").as_bytes().chunks(2).map(|pair| {
+        match std::str::from_utf8(pair) {
+            Ok(pair) if pair.len() == 2 => Ok(u8::from_str_radix(pair, 16)?),
+            _ => Err(usage_error("hex payload must be made of pairs of hex digits")),
+        }
+    }).collect()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn usage_error(message: &str) -> failure::Error {
+    failure::format_err!("{}\n\n{}", message, USAGE)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_decimal_and_hex_bytes() {
+        assert_eq!(23, parse_byte("23").unwrap());
+        assert_eq!(0x17, parse_byte("0x17").unwrap());
+        assert!(parse_byte("256").is_err());
+        assert!(parse_byte("0xzz").is_err());
+    }
+
+    #[test]
+    fn parses_hex_payloads() {
+        assert_eq!(vec![0x01, 0xab], parse_hex("01ab").unwrap());
+        assert_eq!(vec![0x01, 0xab], parse_hex("0x01ab").unwrap());
+        assert_eq!(Vec::<u8>::new(), parse_hex("").unwrap());
+        assert!(parse_hex("01a").is_err());
+        assert!(parse_hex("0g").is_err());
+    }
+
+    #[test]
+    fn hex_round_trips() {
+        assert_eq!("00ff10", hex(&parse_hex("00ff10").unwrap()));
+    }
+}
@@ -0,0 +1,53 @@
+//! Runs the `psvrctl` binary for the cases that don't need a headset.
+
+use std::process::{Command, Output};
+
+fn psvrctl(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_psvrctl")).args(args).output().unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn prints_help() {
+    for flag in &["help", "-h", "--help"] {
+        let output = psvrctl(&[flag]);
+        assert!(output.status.success());
+        assert!(String::from_utf8_lossy(&output.stdout).starts_with("usage: psvrctl"));
+    }
+}
+
+#[test]
+fn fails_without_a_command() {
+    let output = psvrctl(&[]);
+    assert_eq!(Some(1), output.status.code());
+    assert!(stderr(&output).starts_with("error: no command given"));
+}
+
+#[test]
+fn rejects_unknown_commands() {
+    let output = psvrctl(&["--json", "frobnicate"]);
+    assert_eq!(Some(1), output.status.code());
+    assert!(stderr(&output).starts_with("error: unknown command 'frobnicate'"));
+}
+
+#[test]
+fn rejects_bad_arguments_before_connecting() {
+    let cases: &[(&[&str], &str)] = &[
+        (&["power", "sideways"], "power state must be 'on' or 'off'"),
+        (&["mode"], "expected exactly one argument"),
+        (&["leds", "z=10"], "unknown LED 'z'"),
+        (&["leds", "a=101"], "LED values must be written as LED=VALUE"),
+        (&["cinematic"], "nothing to configure"),
+        (&["raw"], "raw needs a command ID"),
+        (&["raw", "0x17", "0a1"], "hex payload must be made of pairs of hex digits"),
+    ];
+
+    for (args, message) in cases {
+        let output = psvrctl(args);
+        assert_eq!(Some(1), output.status.code(), "{:?}", args);
+        assert!(stderr(&output).contains(message), "{:?}: {}", args, stderr(&output));
+    }
+}